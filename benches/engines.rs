@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/*
+Times the tree-walking interpreter against the bytecode VM on every program
+in benches/programs. Each engine runs the `parser` binary, so the times
+include lexing, parsing and, for the VM, compiling. The best of a few runs
+is reported to keep noise from other processes out.
+*/
+
+const RUNS: usize = 5;
+
+fn time(args: &[&str]) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let status = Command::new(env!("CARGO_BIN_EXE_parser"))
+                .args(args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .expect("couldn't run parser");
+            assert!(status.success(), "parser {} failed", args.join(" "));
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/programs");
+    let mut programs: Vec<_> = fs::read_dir(&dir)
+        .expect("couldn't list benches/programs")
+        .map(|entry| entry.expect("couldn't read benches/programs").path())
+        .collect();
+    programs.sort();
+    println!(
+        "{:<16} {:>12} {:>12} {:>8}",
+        "program", "interpreter", "vm", "speedup"
+    );
+    for program in programs.iter() {
+        let path = program.to_string_lossy();
+        let interpreted = time(&["run", &path]);
+        let executed = time(&["run", "--vm", &path]);
+        println!(
+            "{:<16} {:>12.3?} {:>12.3?} {:>7.2}x",
+            program.file_stem().unwrap_or_default().to_string_lossy(),
+            interpreted,
+            executed,
+            interpreted.as_secs_f64() / executed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,12 @@
+(module
+    (import "env" "print" (func $print (param f64)))
+    (global $a (mut f64) (f64.const 0))
+    (global $b (mut f64) (f64.const 0))
+    (func $asdf_main
+        (global.set $a (f64.const 1.0))
+        (global.set $a (f64.const 2.0))
+        (global.set $b (f64.const 5.0))
+        (call $print (f64.add (global.get $a) (global.get $b)))
+    )
+    (start $asdf_main)
+)
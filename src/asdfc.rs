@@ -0,0 +1,411 @@
+use crate::bytecode::{Chunk, Constant, FunctionProto, OpCode, Program};
+use crate::lexer::Span;
+
+/*
+Layout of a compiled .asdfc file, all integers little endian:
+
+header    := magic:[u8; 4] version:u16 flags:u16
+constants := count:u32 (tag:u8 (f64 | string))*
+globals   := count:u32 string*
+functions := count:u32 function*
+function  := name:string arity:u16 locals:(count:u32 string*)
+             cells:(count:u32 u16*) free:(count:u32 (string u16)*)
+             code:(len:u32 u8*) lines?
+lines     := count:u32 (offset:u32 line:u32 col:u32)*
+string    := len:u32 utf8 bytes
+
+`lines` is only present when the DEBUG_LINES flag is set.
+*/
+
+pub const MAGIC: [u8; 4] = *b"ASDC";
+pub const VERSION: u16 = 2;
+const DEBUG_LINES: u16 = 1;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+}
+
+pub fn write(program: &Program, debug: bool) -> Vec<u8> {
+    let mut w = Writer { bytes: vec![] };
+    w.bytes.extend_from_slice(&MAGIC);
+    w.u16(VERSION);
+    w.u16(if debug { DEBUG_LINES } else { 0 });
+    w.u32(program.constants.len());
+    for constant in program.constants.iter() {
+        match constant {
+            Constant::Number(n) => {
+                w.u8(TAG_NUMBER);
+                w.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Constant::Str(s) => {
+                w.u8(TAG_STRING);
+                w.string(s);
+            }
+        }
+    }
+    w.u32(program.globals.len());
+    for global in program.globals.iter() {
+        w.string(global);
+    }
+    w.u32(program.functions.len());
+    for function in program.functions.iter() {
+        w.string(&function.name);
+        w.u16(function.arity as u16);
+        w.u32(function.locals.len());
+        for local in function.locals.iter() {
+            w.string(local);
+        }
+        w.u32(function.cells.len());
+        for slot in function.cells.iter() {
+            w.u16(*slot as u16);
+        }
+        w.u32(function.free.len());
+        for (name, cell) in function.free.iter() {
+            w.string(name);
+            w.u16(*cell as u16);
+        }
+        w.u32(function.chunk.code.len());
+        w.bytes.extend_from_slice(&function.chunk.code);
+        if debug {
+            w.u32(function.chunk.lines.len());
+            for (offset, span) in function.chunk.lines.iter() {
+                w.u32(*offset);
+                w.u32(span.line);
+                w.u32(span.col);
+            }
+        }
+    }
+    w.bytes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("Unexpected end of file at byte {}", self.pos))?;
+        let res = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(b))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()?;
+        let pos = self.pos;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| format!("Invalid UTF-8 string at byte {}", pos))
+    }
+
+    /// Reads a count prefixed list, refusing counts that could not possibly
+    /// fit in the remaining input.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let count = self.u32()?;
+        if count > self.bytes.len() - self.pos {
+            return Err(format!("Invalid table size {} at byte {}", count, self.pos));
+        }
+        (0..count).map(|_| item(self)).collect()
+    }
+}
+
+pub fn read(bytes: &[u8]) -> Result<Program, String> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(4).ok() != Some(&MAGIC[..]) {
+        return Err("Not a compiled asdf file".to_string());
+    }
+    let version = r.u16()?;
+    if version != VERSION {
+        return Err(format!(
+            "Unsupported bytecode version {}, expected {}",
+            version, VERSION
+        ));
+    }
+    let flags = r.u16()?;
+    if flags & !DEBUG_LINES != 0 {
+        return Err(format!("Unknown flags {:#06x}", flags));
+    }
+    let constants = r.list(|r| match r.u8()? {
+        TAG_NUMBER => Ok(Constant::Number(r.f64()?)),
+        TAG_STRING => Ok(Constant::Str(r.string()?)),
+        tag => Err(format!("Invalid constant tag {}", tag)),
+    })?;
+    let globals = r.list(|r| r.string())?;
+    let functions = r.list(|r| {
+        let name = r.string()?;
+        let arity = r.u16()? as usize;
+        let locals = r.list(|r| r.string())?;
+        let cells = r.list(|r| Ok(r.u16()? as usize))?;
+        let free = r.list(|r| Ok((r.string()?, r.u16()? as usize)))?;
+        let len = r.u32()?;
+        let code = r.take(len)?.to_vec();
+        let lines = if flags & DEBUG_LINES != 0 {
+            r.list(|r| {
+                let offset = r.u32()?;
+                let line = r.u32()?;
+                let col = r.u32()?;
+                Ok((
+                    offset,
+                    Span {
+                        line,
+                        col,
+                        ..Default::default()
+                    },
+                ))
+            })?
+        } else {
+            vec![]
+        };
+        Ok(FunctionProto {
+            name,
+            arity,
+            locals,
+            cells,
+            free,
+            chunk: Chunk { code, lines },
+        })
+    })?;
+    if r.pos != bytes.len() {
+        return Err(format!("Trailing data at byte {}", r.pos));
+    }
+    let program = Program {
+        constants,
+        globals,
+        functions,
+    };
+    verify(&program)?;
+    Ok(program)
+}
+
+/// Checks that every operand indexes an existing table entry, that jumps
+/// land on instruction boundaries and that no path through a function pops
+/// more than it pushed or runs off its end. The VM relies on this instead
+/// of checking every instruction.
+fn verify(program: &Program) -> Result<(), String> {
+    match program.functions.first() {
+        None => return Err("Program has no entry point".to_string()),
+        Some(main) if main.arity > 0 || !main.free.is_empty() => {
+            return Err("Entry point has parameters or free names".to_string())
+        }
+        Some(_) => {}
+    }
+    for function in program.functions.iter() {
+        let fail = |offset: usize, message: &str| {
+            Err(format!("{} at {}+{:04}", message, function.name, offset))
+        };
+        if function.arity > function.locals.len() {
+            return fail(0, "Arity exceeds number of locals");
+        }
+        if function
+            .cells
+            .iter()
+            .any(|slot| *slot >= function.locals.len())
+        {
+            return fail(0, "Cell of a missing local");
+        }
+        let cells = function.cells.len() + function.free.len();
+        let chunk = &function.chunk;
+        let mut starts = vec![false; chunk.code.len() + 1];
+        let mut jumps = vec![];
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            starts[offset] = true;
+            let op = match OpCode::from_byte(chunk.code[offset]) {
+                Some(op) => op,
+                None => return fail(offset, "Invalid opcode"),
+            };
+            if offset + op.operand_len() >= chunk.code.len() && op.operand_len() > 0 {
+                return fail(offset, "Truncated instruction");
+            }
+            if op.operand_len() > 0 {
+                let arg = chunk.read_u16(offset + 1) as usize;
+                let limit = match op {
+                    OpCode::Constant => Some(program.constants.len()),
+                    OpCode::GetLocal | OpCode::SetLocal => Some(function.locals.len()),
+                    OpCode::GetGlobal | OpCode::SetGlobal => Some(program.globals.len()),
+                    OpCode::GetCell | OpCode::SetCell => Some(cells),
+                    OpCode::Function => match program.functions.get(arg) {
+                        Some(f) if f.free.iter().any(|(_, cell)| *cell >= cells) => {
+                            return fail(offset, "Closure captures a missing cell")
+                        }
+                        _ => Some(program.functions.len()),
+                    },
+                    OpCode::Jump | OpCode::JumpIfFalse => {
+                        jumps.push((offset, arg));
+                        None
+                    }
+                    _ => None,
+                };
+                if limit.is_some_and(|l| arg >= l) {
+                    return fail(offset, "Operand out of range");
+                }
+            }
+            offset += 1 + op.operand_len();
+        }
+        for (offset, target) in jumps {
+            if target >= chunk.code.len() || !starts[target] {
+                return fail(offset, "Invalid jump target");
+            }
+        }
+        // The depth of the frame's stack before each instruction, which has
+        // to be the same on every path reaching it.
+        let mut depths = vec![None; chunk.code.len()];
+        let mut pending = vec![(0, 0)];
+        while let Some((offset, depth)) = pending.pop() {
+            if offset >= chunk.code.len() {
+                return fail(offset, "Function does not end with a return");
+            }
+            match depths[offset] {
+                Some(d) if d == depth => continue,
+                Some(_) => return fail(offset, "Inconsistent stack depth"),
+                None => depths[offset] = Some(depth),
+            }
+            let op = OpCode::from_byte(chunk.code[offset]).ok_or("Invalid state")?;
+            let arg = match op.operand_len() {
+                0 => 0,
+                _ => chunk.read_u16(offset + 1) as usize,
+            };
+            let (pops, pushes) = op.stack_effect(arg);
+            if depth < pops {
+                return fail(offset, "Stack underflow");
+            }
+            let depth = depth - pops + pushes;
+            let next = offset + 1 + op.operand_len();
+            match op {
+                OpCode::Return => {}
+                OpCode::Jump => pending.push((arg, depth)),
+                OpCode::JumpIfFalse => pending.extend([(arg, depth), (next, depth)]),
+                _ => pending.push((next, depth)),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Compiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Writes a program whose top level is `code` and loads it back.
+    fn load(code: Vec<u8>) -> Result<Program, String> {
+        let program = Program {
+            constants: vec![Constant::Number(1.0)],
+            globals: vec![],
+            functions: vec![FunctionProto {
+                name: "<main>".to_string(),
+                arity: 0,
+                locals: vec![],
+                cells: vec![],
+                free: vec![],
+                chunk: Chunk {
+                    code,
+                    lines: vec![],
+                },
+            }],
+        };
+        read(&write(&program, false))
+    }
+
+    fn op(op: OpCode) -> u8 {
+        op as u8
+    }
+
+    #[test]
+    fn loads_compiled_program() {
+        let source = "fn f(a) { fn g() { return a } return g() } let x = 1 while (x < 3) { if (x == 1) { print(f(x)) } else { print(x) } let x = x + 1 }";
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let program = Compiler::new().compile(&nodes).unwrap();
+        let loaded = read(&write(&program, true)).unwrap();
+        assert_eq!(loaded.to_string(), program.to_string());
+    }
+
+    #[test]
+    fn rejects_pop_of_empty_stack() {
+        let err = load(vec![op(OpCode::Pop), op(OpCode::Return)]).unwrap_err();
+        assert_eq!(err, "Stack underflow at <main>+0000");
+    }
+
+    #[test]
+    fn rejects_call_without_its_arguments() {
+        let code = vec![
+            op(OpCode::Constant),
+            0,
+            0,
+            op(OpCode::Call),
+            2,
+            0,
+            op(OpCode::Return),
+        ];
+        assert_eq!(load(code).unwrap_err(), "Stack underflow at <main>+0003");
+    }
+
+    #[test]
+    fn rejects_loop_that_grows_the_stack() {
+        let code = vec![op(OpCode::Constant), 0, 0, op(OpCode::Jump), 0, 0];
+        assert_eq!(
+            load(code).unwrap_err(),
+            "Inconsistent stack depth at <main>+0000"
+        );
+    }
+
+    #[test]
+    fn rejects_running_off_the_end() {
+        let code = vec![op(OpCode::Constant), 0, 0, op(OpCode::Print)];
+        assert_eq!(
+            load(code).unwrap_err(),
+            "Function does not end with a return at <main>+0004"
+        );
+    }
+}
@@ -0,0 +1,662 @@
+use crate::lexer::{Operator, Span, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    None,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    SetGlobal,
+    Function,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+    Positive,
+    Equal,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Jump,
+    JumpIfFalse,
+    Call,
+    Return,
+    Print,
+    GetCell,
+    SetCell,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 26] = [
+        OpCode::Constant,
+        OpCode::None,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::SetGlobal,
+        OpCode::Function,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Negate,
+        OpCode::Positive,
+        OpCode::Equal,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Call,
+        OpCode::Return,
+        OpCode::Print,
+        OpCode::GetCell,
+        OpCode::SetCell,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+
+    /// Number of operand bytes following the opcode. Every operand is a
+    /// little endian u16.
+    pub fn operand_len(self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::Function
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Call
+            | OpCode::GetCell
+            | OpCode::SetCell => 2,
+            _ => 0,
+        }
+    }
+
+    /// How many values the instruction pops and pushes, given its operand.
+    pub fn stack_effect(self, arg: usize) -> (usize, usize) {
+        match self {
+            OpCode::Constant
+            | OpCode::None
+            | OpCode::GetLocal
+            | OpCode::GetGlobal
+            | OpCode::GetCell
+            | OpCode::Function => (0, 1),
+            OpCode::Pop
+            | OpCode::SetLocal
+            | OpCode::SetGlobal
+            | OpCode::SetCell
+            | OpCode::JumpIfFalse
+            | OpCode::Return
+            | OpCode::Print => (1, 0),
+            OpCode::Negate | OpCode::Positive => (1, 1),
+            OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Equal
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual => (2, 1),
+            OpCode::Jump => (0, 0),
+            OpCode::Call => (arg + 1, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    Str(String),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", n),
+            Constant::Str(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// Bytecode of a single function together with the source location of
+/// every instruction that starts a new statement or expression.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub lines: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Source location of the instruction at `offset`.
+    pub fn span_at(&self, offset: usize) -> Span {
+        match self.lines.binary_search_by_key(&offset, |(o, _)| *o) {
+            Ok(i) => self.lines[i].1,
+            Err(0) => Span::default(),
+            Err(i) => self.lines[i - 1].1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    /// Slot names, parameters first.
+    pub locals: Vec<String>,
+    /// Slots of the locals that nested functions read. They are kept in
+    /// cells instead, so closures see later assignments.
+    pub cells: Vec<usize>,
+    /// Names read from enclosing functions, each with the index of the cell
+    /// it is taken from in the frame that creates the closure.
+    pub free: Vec<(String, usize)>,
+    pub chunk: Chunk,
+}
+
+impl FunctionProto {
+    /// Name of the variable in cell `index` of a frame of this function:
+    /// its own captured locals come first, then its free names.
+    pub fn cell_name(&self, index: usize) -> Option<&str> {
+        match self.cells.get(index) {
+            Some(slot) => self.locals.get(*slot).map(|l| l.as_str()),
+            None => self
+                .free
+                .get(index - self.cells.len())
+                .map(|(name, _)| name.as_str()),
+        }
+    }
+}
+
+/// A compiled program. `functions[0]` holds the top level statements.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub constants: Vec<Constant>,
+    pub globals: Vec<String>,
+    pub functions: Vec<FunctionProto>,
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    slots: HashMap<String, usize>,
+    /// Cell index of every captured local and free name.
+    cells: HashMap<String, usize>,
+}
+
+pub struct Compiler {
+    constants: Vec<Constant>,
+    globals: Vec<String>,
+    functions: Vec<FunctionProto>,
+    scopes: Vec<FunctionState>,
+}
+
+fn identifier(token: &TokenType) -> Result<&str, String> {
+    match token {
+        TokenType::Identifier(x) => Ok(x),
+        _ => Err("Invalid state".to_string()),
+    }
+}
+
+fn error<T>(span: Span, message: impl fmt::Display) -> Result<T, String> {
+    Err(format!("Compile error at {}: {}", span, message))
+}
+
+fn push_unique(out: &mut Vec<String>, name: &str) {
+    if !out.iter().any(|n| n == name) {
+        out.push(name.to_string());
+    }
+}
+
+/// Parameters of a function followed by the other names it binds.
+fn locals(node: &ParseNode) -> Result<Vec<String>, String> {
+    let mut locals = node
+        .extra_info
+        .as_ref()
+        .ok_or("Invalid state")?
+        .children
+        .iter()
+        .map(|p| identifier(&p.token).map(|x| x.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut bindings = vec![];
+    collect_bindings(&node.children, &mut bindings);
+    for binding in bindings.iter() {
+        push_unique(&mut locals, binding);
+    }
+    Ok(locals)
+}
+
+/// Names read in `node`, with functions in it contributing their free names.
+fn references(node: &ParseNode, out: &mut Vec<String>) {
+    match &node.token {
+        TokenType::Identifier(x) => push_unique(out, x),
+        TokenType::Call(info) => {
+            if let TokenType::Identifier(x) = &*info.name {
+                push_unique(out, x);
+            }
+        }
+        TokenType::Fn(Some(_)) => {
+            for name in free_names(node).iter() {
+                push_unique(out, name);
+            }
+            return;
+        }
+        _ => {}
+    }
+    for child in node
+        .extra_info
+        .iter()
+        .map(|e| &**e)
+        .chain(node.children.iter())
+    {
+        references(child, out);
+    }
+}
+
+/// Names a function reads that it doesn't bind itself, including those read
+/// by functions nested in it.
+fn free_names(node: &ParseNode) -> Vec<String> {
+    let locals = locals(node).unwrap_or_default();
+    let mut names = vec![];
+    for child in node.children.iter() {
+        references(child, &mut names);
+    }
+    names.retain(|name| !locals.contains(name));
+    names
+}
+
+/// Free names of the functions declared directly in `nodes`.
+fn nested_free_names(nodes: &[ParseNode], out: &mut Vec<String>) {
+    for node in nodes.iter() {
+        match node.token {
+            TokenType::Fn(Some(_)) => {
+                for name in free_names(node).iter() {
+                    push_unique(out, name);
+                }
+            }
+            _ => nested_free_names(&node.children, out),
+        }
+    }
+}
+
+fn operand(value: usize, span: Span) -> Result<u16, String> {
+    u16::try_from(value).or_else(|_| error(span, "program too large for bytecode"))
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            constants: vec![],
+            globals: vec![],
+            // Reserve the slot of the top level function.
+            functions: vec![FunctionProto {
+                name: "<main>".to_string(),
+                arity: 0,
+                locals: vec![],
+                cells: vec![],
+                free: vec![],
+                chunk: Chunk::default(),
+            }],
+            scopes: vec![],
+        }
+    }
+
+    pub fn compile(mut self, nodes: &[ParseNode]) -> Result<Program, String> {
+        for node in nodes.iter() {
+            self.statement(node)?;
+        }
+        let span = nodes.last().map(|n| n.span).unwrap_or_default();
+        self.emit(OpCode::None, span);
+        self.emit(OpCode::Return, span);
+        Ok(Program {
+            constants: self.constants,
+            globals: self.globals,
+            functions: self.functions,
+        })
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        match self.scopes.last_mut() {
+            Some(scope) => &mut scope.proto.chunk,
+            None => &mut self.functions[0].chunk,
+        }
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) {
+        let chunk = self.chunk();
+        let offset = chunk.code.len();
+        if chunk.lines.last().map(|(_, s)| *s) != Some(span) {
+            chunk.lines.push((offset, span));
+        }
+        chunk.code.push(op as u8);
+    }
+
+    fn emit_with(&mut self, op: OpCode, value: u16, span: Span) {
+        self.emit(op, span);
+        self.chunk().code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Emits a jump with a placeholder target and returns the operand offset.
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit_with(op, u16::MAX, span);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, at: usize, span: Span) -> Result<(), String> {
+        let target = operand(self.chunk().code.len(), span)?;
+        self.chunk().code[at..at + 2].copy_from_slice(&target.to_le_bytes());
+        Ok(())
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> Result<(), String> {
+        let idx = match self.constants.iter().position(|c| *c == constant) {
+            Some(i) => i,
+            None => {
+                self.constants.push(constant);
+                self.constants.len() - 1
+            }
+        };
+        self.emit_with(OpCode::Constant, operand(idx, span)?, span);
+        Ok(())
+    }
+
+    fn global(&mut self, name: &str) -> usize {
+        match self.globals.iter().position(|g| g == name) {
+            Some(i) => i,
+            None => {
+                self.globals.push(name.to_string());
+                self.globals.len() - 1
+            }
+        }
+    }
+
+    fn load(&mut self, name: &str, span: Span) -> Result<(), String> {
+        if let Some(scope) = self.scopes.last() {
+            if let Some(cell) = scope.cells.get(name) {
+                let cell = operand(*cell, span)?;
+                self.emit_with(OpCode::GetCell, cell, span);
+                return Ok(());
+            }
+            if let Some(slot) = scope.slots.get(name) {
+                let slot = operand(*slot, span)?;
+                self.emit_with(OpCode::GetLocal, slot, span);
+                return Ok(());
+            }
+        }
+        let idx = operand(self.global(name), span)?;
+        self.emit_with(OpCode::GetGlobal, idx, span);
+        Ok(())
+    }
+
+    fn store(&mut self, name: &str, span: Span) -> Result<(), String> {
+        if let Some(scope) = self.scopes.last() {
+            match scope.cells.get(name) {
+                Some(cell) => {
+                    let cell = operand(*cell, span)?;
+                    self.emit_with(OpCode::SetCell, cell, span);
+                }
+                None => {
+                    let slot = operand(scope.slots[name], span)?;
+                    self.emit_with(OpCode::SetLocal, slot, span);
+                }
+            }
+        } else {
+            let idx = operand(self.global(name), span)?;
+            self.emit_with(OpCode::SetGlobal, idx, span);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, nodes: &[ParseNode]) -> Result<(), String> {
+        for node in nodes.iter() {
+            self.statement(node)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, node: &ParseNode) -> Result<(), String> {
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                self.expression(node.children.get(1).ok_or("Invalid state")?)?;
+                self.store(id, node.span)
+            }
+            TokenType::Print => {
+                self.expression(node.children.first().ok_or("Invalid state")?)?;
+                self.emit(OpCode::Print, node.span);
+                Ok(())
+            }
+            TokenType::While => {
+                let start = operand(self.chunk().code.len(), node.span)?;
+                self.expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                let exit = self.emit_jump(OpCode::JumpIfFalse, node.span);
+                self.block(&node.children)?;
+                self.emit_with(OpCode::Jump, start, node.span);
+                self.patch_jump(exit, node.span)
+            }
+            TokenType::If => {
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                let mut ends = vec![];
+                self.expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                let mut next = self.emit_jump(OpCode::JumpIfFalse, node.span);
+                self.block(body)?;
+                for branch in branches.iter() {
+                    ends.push(self.emit_jump(OpCode::Jump, branch.span));
+                    self.patch_jump(next, branch.span)?;
+                    if let Some(cond) = &branch.extra_info {
+                        self.expression(cond)?;
+                        next = self.emit_jump(OpCode::JumpIfFalse, branch.span);
+                        self.block(&branch.children)?;
+                    } else {
+                        self.block(&branch.children)?;
+                        next = usize::MAX;
+                    }
+                }
+                if next != usize::MAX {
+                    self.patch_jump(next, node.span)?;
+                }
+                for end in ends {
+                    self.patch_jump(end, node.span)?;
+                }
+                Ok(())
+            }
+            TokenType::Fn(Some(info)) => {
+                let name = identifier(&info.name)?;
+                let idx = self.function(name, node)?;
+                self.emit_with(OpCode::Function, operand(idx, node.span)?, node.span);
+                self.store(name, node.span)
+            }
+            TokenType::Call(_) => {
+                self.expression(node)?;
+                self.emit(OpCode::Pop, node.span);
+                Ok(())
+            }
+            TokenType::Return => {
+                if self.scopes.is_empty() {
+                    return error(node.span, "'return' outside function");
+                }
+                match node.children.first() {
+                    Some(value) => self.expression(value)?,
+                    None => self.emit(OpCode::None, node.span),
+                }
+                self.emit(OpCode::Return, node.span);
+                Ok(())
+            }
+            t => error(node.span, format!("{:?} is not a statement", t)),
+        }
+    }
+
+    fn function(&mut self, name: &str, node: &ParseNode) -> Result<usize, String> {
+        let locals = locals(node)?;
+        let arity = node.extra_info.as_ref().map_or(0, |p| p.children.len());
+        let mut captured = vec![];
+        nested_free_names(&node.children, &mut captured);
+        let cells: Vec<usize> = (0..locals.len())
+            .filter(|slot| captured.contains(&locals[*slot]))
+            .collect();
+        // Free names that no enclosing function holds are globals.
+        let free: Vec<(String, usize)> = free_names(node)
+            .into_iter()
+            .filter_map(|name| {
+                let cell = *self.scopes.last()?.cells.get(&name)?;
+                Some((name, cell))
+            })
+            .collect();
+        let slots = locals
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (l.clone(), i))
+            .collect();
+        let cell_names = cells
+            .iter()
+            .map(|slot| &locals[*slot])
+            .chain(free.iter().map(|(name, _)| name))
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i))
+            .collect();
+        self.scopes.push(FunctionState {
+            proto: FunctionProto {
+                name: name.to_string(),
+                arity,
+                locals,
+                cells,
+                free,
+                chunk: Chunk::default(),
+            },
+            slots,
+            cells: cell_names,
+        });
+        let res = self.block(&node.children);
+        let end = node.children.last().map(|n| n.span).unwrap_or(node.span);
+        self.emit(OpCode::None, end);
+        self.emit(OpCode::Return, end);
+        let scope = self.scopes.pop().ok_or("Invalid state")?;
+        res?;
+        self.functions.push(scope.proto);
+        Ok(self.functions.len() - 1)
+    }
+
+    fn expression(&mut self, node: &ParseNode) -> Result<(), String> {
+        match &node.token {
+            TokenType::Number(x) => {
+                self.constant(Constant::Number(x.into_inner() as f64), node.span)
+            }
+            TokenType::StringLiteral(x) => self.constant(Constant::Str(x.clone()), node.span),
+            TokenType::Identifier(x) => self.load(x, node.span),
+            TokenType::Operator(op) => {
+                for child in node.children.iter() {
+                    self.expression(child)?;
+                }
+                let code = match (op, node.children.len()) {
+                    (Operator::Plus, 1) => OpCode::Positive,
+                    (Operator::Minus, 1) => OpCode::Negate,
+                    (Operator::Plus, 2) => OpCode::Add,
+                    (Operator::Minus, 2) => OpCode::Subtract,
+                    (Operator::Multiply, 2) => OpCode::Multiply,
+                    (Operator::Divide, 2) => OpCode::Divide,
+                    (Operator::Equality, 2) => OpCode::Equal,
+                    (Operator::LessThan, 2) => OpCode::Less,
+                    (Operator::LessThanEqual, 2) => OpCode::LessEqual,
+                    (Operator::GreaterThan, 2) => OpCode::Greater,
+                    (Operator::GreaterThanEqual, 2) => OpCode::GreaterEqual,
+                    _ => return error(node.span, format!("invalid operator {}", op)),
+                };
+                self.emit(code, node.span);
+                Ok(())
+            }
+            TokenType::Call(info) => {
+                self.load(identifier(&info.name)?, node.span)?;
+                for arg in node.children.iter() {
+                    self.expression(arg)?;
+                }
+                let argc = operand(node.children.len(), node.span)?;
+                self.emit_with(OpCode::Call, argc, node.span);
+                Ok(())
+            }
+            t => error(node.span, format!("{:?} is not an expression", t)),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    /// Human readable listing of every function in the program.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(
+                f,
+                "== {} (#{}, arity {}, locals [{}]) ==",
+                function.name,
+                i,
+                function.arity,
+                function.locals.join(", ")
+            )?;
+            let chunk = &function.chunk;
+            let mut offset = 0;
+            let mut last_line = 0;
+            while offset < chunk.code.len() {
+                let line = chunk.span_at(offset).line;
+                if line == last_line {
+                    write!(f, "{:04}    | ", offset)?;
+                } else {
+                    write!(f, "{:04} {:4} ", offset, line)?;
+                    last_line = line;
+                }
+                let op = match OpCode::from_byte(chunk.code[offset]) {
+                    Some(op) => op,
+                    None => {
+                        writeln!(f, "<invalid opcode {}>", chunk.code[offset])?;
+                        offset += 1;
+                        continue;
+                    }
+                };
+                if op.operand_len() == 0 {
+                    writeln!(f, "{:?}", op)?;
+                } else {
+                    let arg = chunk.read_u16(offset + 1) as usize;
+                    let detail = match op {
+                        OpCode::Constant => self.constants.get(arg).map(|c| c.to_string()),
+                        OpCode::GetLocal | OpCode::SetLocal => function.locals.get(arg).cloned(),
+                        OpCode::GetCell | OpCode::SetCell => {
+                            function.cell_name(arg).map(|n| n.to_string())
+                        }
+                        OpCode::GetGlobal | OpCode::SetGlobal => self.globals.get(arg).cloned(),
+                        OpCode::Function => self.functions.get(arg).map(|f| f.name.clone()),
+                        _ => None,
+                    };
+                    match detail {
+                        Some(d) => writeln!(f, "{:<14} {:5} ; {}", format!("{:?}", op), arg, d)?,
+                        None => writeln!(f, "{:<14} {:5}", format!("{:?}", op), arg)?,
+                    }
+                }
+                offset += 1 + op.operand_len();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use crate::json::{object, quote, Json};
+use crate::lexer::{Span, Token, TokenType};
+use crate::parser::ParseNode;
+
+/*
+Dumps of the lexer and parser output for tools outside Rust, printed by
+`--emit`. The JSON schema, version 1:
+
+    span   {"start": int, "end": int, "line": int, "col": int}
+           start and end are character offsets, end exclusive; line and col
+           are 1-based and locate start.
+
+    token  {"kind": kind, "value"?: value, "span": span}
+
+    node   {"kind": kind, "value"?: value, "span": span,
+            "extra"?: node, "children": [node]}
+
+    tokens-json   {"version": 1, "tokens": [token],
+                   "errors": [{"span": span, "message": string}]}
+    ast-json      {"version": 1, "nodes": [node],
+                   "warnings": [{"span": span, "message": string}]}
+
+Kinds are "let", "print", "if", "elif", "else", "while", "fn", "return",
+"call", "parameters", "operator", "number", "string", "identifier",
+"left_paren", "right_paren", "left_curly", "right_curly" and "semicolon".
+"value" is the operator symbol for "operator", a number for "number", the
+contents for "string", the name for "identifier", "call" and a "fn" node,
+and absent otherwise.
+
+In the tree, "extra" is the condition of a "while", "if" or "elif" and the
+"parameters" of a "fn", whose children are its "identifier"s. A "call" has
+its arguments as children, a "let" its "identifier" and value, an "if" its
+body followed by its "elif" and "else" branches, and an "operator" one or
+two operands.
+
+`dot` draws the same tree for Graphviz under a "program" root, labelling
+each node with its kind and value and giving its span as a tooltip. Extra
+nodes hang off dashed edges named "condition" or "parameters".
+*/
+
+/// What `--emit` can print instead of transpiling.
+pub const EMITS: [&str; 4] = ["tokens-json", "ast-json", "sexp", "dot"];
+
+const VERSION: f64 = 1.0;
+
+/// The kind of a token or node, and its value if it has one.
+fn kind(token: &TokenType) -> (&'static str, Option<Json>) {
+    let name = |token: &TokenType| match token {
+        TokenType::Identifier(x) => Json::Str(x.clone()),
+        _ => Json::Null,
+    };
+    match token {
+        TokenType::Let => ("let", None),
+        TokenType::Print => ("print", None),
+        TokenType::If => ("if", None),
+        TokenType::Elif => ("elif", None),
+        TokenType::Else => ("else", None),
+        TokenType::While => ("while", None),
+        TokenType::Fn(None) => ("fn", None),
+        TokenType::Fn(Some(info)) => ("fn", Some(name(&info.name))),
+        TokenType::Return => ("return", None),
+        TokenType::Call(info) => ("call", Some(name(&info.name))),
+        TokenType::Parameters => ("parameters", None),
+        TokenType::Operator(op) => ("operator", Some(Json::Str(op.to_string()))),
+        TokenType::Number(n) => ("number", Some(Json::Number(n.0 as f64))),
+        TokenType::StringLiteral(s) => ("string", Some(Json::Str(s.clone()))),
+        TokenType::Identifier(x) => ("identifier", Some(Json::Str(x.clone()))),
+        TokenType::LeftParen => ("left_paren", None),
+        TokenType::RightParen => ("right_paren", None),
+        TokenType::LeftCurly => ("left_curly", None),
+        TokenType::RightCurly => ("right_curly", None),
+        TokenType::SemiColon => ("semicolon", None),
+    }
+}
+
+fn span(span: Span) -> Json {
+    object(vec![
+        ("start", Json::Number(span.start as f64)),
+        ("end", Json::Number(span.end as f64)),
+        ("line", Json::Number(span.line as f64)),
+        ("col", Json::Number(span.col as f64)),
+    ])
+}
+
+fn messages(messages: &[(Span, String)]) -> Json {
+    Json::Array(
+        messages
+            .iter()
+            .map(|(at, message)| {
+                object(vec![
+                    ("span", span(*at)),
+                    ("message", Json::Str(message.clone())),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn token(token: &Token) -> Json {
+    let (kind, value) = kind(&token.token);
+    let mut fields = vec![("kind", Json::Str(kind.to_string()))];
+    fields.extend(value.map(|v| ("value", v)));
+    fields.push(("span", span(token.span)));
+    object(fields)
+}
+
+fn node(parse_node: &ParseNode) -> Json {
+    let (kind, value) = kind(&parse_node.token);
+    let mut fields = vec![("kind", Json::Str(kind.to_string()))];
+    fields.extend(value.map(|v| ("value", v)));
+    fields.push(("span", span(parse_node.span)));
+    fields.extend(
+        parse_node
+            .extra_info
+            .as_deref()
+            .map(|extra| ("extra", node(extra))),
+    );
+    fields.push((
+        "children",
+        Json::Array(parse_node.children.iter().map(node).collect()),
+    ));
+    object(fields)
+}
+
+pub fn tokens_json(tokens: &[Token], errors: &[(Span, String)]) -> Json {
+    object(vec![
+        ("version", Json::Number(VERSION)),
+        ("tokens", Json::Array(tokens.iter().map(token).collect())),
+        ("errors", messages(errors)),
+    ])
+}
+
+pub fn ast_json(nodes: &[ParseNode], warnings: &[(Span, String)]) -> Json {
+    object(vec![
+        ("version", Json::Number(VERSION)),
+        ("nodes", Json::Array(nodes.iter().map(node).collect())),
+        ("warnings", messages(warnings)),
+    ])
+}
+
+/// Adds `parse_node` and everything below it to a Graphviz digraph as node
+/// `id`, returning the next free id.
+fn dot_node(res: &mut String, parse_node: &ParseNode, id: usize) -> usize {
+    let (kind, value) = kind(&parse_node.token);
+    let label = match value {
+        Some(Json::Str(s)) if kind != "string" => format!("{} {}", kind, s),
+        Some(value) => format!("{} {}", kind, value),
+        None => kind.to_string(),
+    };
+    let at = parse_node.span;
+    res.push_str(&format!(
+        "    n{} [label={}, tooltip={}];\n",
+        id,
+        quote(&label),
+        quote(&format!("{} ({}..{})", at, at.start, at.end))
+    ));
+    let mut next = id + 1;
+    if let Some(extra) = parse_node.extra_info.as_deref() {
+        let role = match parse_node.token {
+            TokenType::Fn(_) => "parameters",
+            _ => "condition",
+        };
+        res.push_str(&format!(
+            "    n{} -> n{} [style=dashed, label={}];\n",
+            id,
+            next,
+            quote(role)
+        ));
+        next = dot_node(res, extra, next);
+    }
+    for child in parse_node.children.iter() {
+        res.push_str(&format!("    n{} -> n{};\n", id, next));
+        next = dot_node(res, child, next);
+    }
+    next
+}
+
+/// The tree as a Graphviz digraph.
+pub fn dot(nodes: &[ParseNode]) -> String {
+    let mut res = String::from(
+        "digraph ast {\n    ordering=out;\n    node [shape=box, fontname=monospace];\n    n0 [label=\"program\"];\n",
+    );
+    let mut next = 1;
+    for parse_node in nodes.iter() {
+        res.push_str(&format!("    n0 -> n{};\n", next));
+        next = dot_node(&mut res, parse_node, next);
+    }
+    res.push_str("}\n");
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn dot_of_conditions_and_parameters() {
+        let source = r#"fn f(n) {
+    if (n < 1) {
+        return "a\"b"
+    }
+}
+print(f(0))
+"#;
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        assert_eq!(
+            dot(&nodes),
+            r#"digraph ast {
+    ordering=out;
+    node [shape=box, fontname=monospace];
+    n0 [label="program"];
+    n0 -> n1;
+    n1 [label="fn f", tooltip="1:1 (0..2)"];
+    n1 -> n2 [style=dashed, label="parameters"];
+    n2 [label="parameters", tooltip="1:6 (5..6)"];
+    n2 -> n3;
+    n3 [label="identifier n", tooltip="1:6 (5..6)"];
+    n1 -> n4;
+    n4 [label="if", tooltip="2:5 (14..16)"];
+    n4 -> n5 [style=dashed, label="condition"];
+    n5 [label="operator <", tooltip="2:11 (20..21)"];
+    n5 -> n6;
+    n6 [label="identifier n", tooltip="2:9 (18..19)"];
+    n5 -> n7;
+    n7 [label="number 1", tooltip="2:13 (22..23)"];
+    n4 -> n8;
+    n8 [label="return", tooltip="3:9 (35..41)"];
+    n8 -> n9;
+    n9 [label="string \"a\\\"b\"", tooltip="3:16 (42..48)"];
+    n0 -> n10;
+    n10 [label="print", tooltip="6:1 (57..62)"];
+    n10 -> n11;
+    n11 [label="call f", tooltip="6:7 (63..64)"];
+    n11 -> n12;
+    n12 [label="number 0", tooltip="6:9 (65..66)"];
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::lexer::{Span, Token, TokenType};
+use crate::parser::ParseNode;
+use crate::transpile::CodeWriter;
+
+/*
+Prints a parse tree back as asdf source in a single layout: one statement per
+line, blocks indented, a space around binary operators and after commas.
+Literals are copied from the source so numbers and escapes keep their
+spelling.
+
+The tree has no comments, so they are taken from the source and put back
+before the statement that follows them, or at the end of the line they
+trailed. A single blank line between statements is kept.
+*/
+
+struct Comment {
+    line: usize,
+    text: String,
+}
+
+/// The `//` comments of `source` in order, skipping any inside strings.
+fn comments(source: &str) -> Vec<Comment> {
+    let mut res = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut prev = ' ';
+        for (at, c) in line.char_indices() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+            } else if c == '"' {
+                in_string = true;
+            } else if c == '/' && prev == '/' {
+                res.push(Comment {
+                    line: i + 1,
+                    text: line[at - 1..].trim_end().to_string(),
+                });
+                break;
+            }
+            prev = c;
+        }
+    }
+    res
+}
+
+pub fn format(nodes: &[ParseNode], tokens: &[Token], source: &str, indent_width: usize) -> String {
+    let mut formatter = Formatter {
+        source: source.chars().collect(),
+        lines: source.lines().collect(),
+        tokens,
+        comments: comments(source),
+        next_comment: 0,
+        first_in_block: true,
+        w: CodeWriter::new(indent_width),
+    };
+    formatter.block(nodes, usize::MAX);
+    formatter.w.finish("").contents
+}
+
+struct Formatter<'a> {
+    source: Vec<char>,
+    lines: Vec<&'a str>,
+    tokens: &'a [Token],
+    comments: Vec<Comment>,
+    next_comment: usize,
+    first_in_block: bool,
+    w: CodeWriter,
+}
+
+impl Formatter<'_> {
+    fn text(&self, span: Span) -> String {
+        self.source[span.start..span.end.min(self.source.len())]
+            .iter()
+            .collect()
+    }
+
+    /// Keeps a blank line the source has before line `line`.
+    fn separate(&mut self, line: usize) {
+        let blank_before = line >= 2
+            && self
+                .lines
+                .get(line - 2)
+                .is_some_and(|l| l.trim().is_empty());
+        if blank_before && !self.first_in_block {
+            self.w.blank();
+        }
+        self.first_in_block = false;
+    }
+
+    /// Writes the comments that come before line `line`.
+    fn flush(&mut self, line: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.line >= line {
+                break;
+            }
+            let (line, text) = (comment.line, comment.text.clone());
+            self.separate(line);
+            self.w.line(&text);
+            self.next_comment += 1;
+        }
+    }
+
+    /// Writes `text` as the line for source line `line`, followed by the
+    /// comment that ends that line in the source.
+    fn line(&mut self, text: &str, line: usize) {
+        match self.comments.get(self.next_comment) {
+            Some(comment) if comment.line == line => {
+                self.w.line(&format!("{} {}", text, comment.text));
+                self.next_comment += 1;
+            }
+            _ => self.w.line(text),
+        }
+    }
+
+    /// Lines of the closing braces of the blocks of the statement starting at
+    /// `node`: one for a loop or function, one per branch for an `if`.
+    fn closing_lines(&self, node: &ParseNode) -> Vec<usize> {
+        let mut res = vec![];
+        let start = match self
+            .tokens
+            .iter()
+            .position(|t| t.span.start == node.span.start)
+        {
+            Some(start) => start,
+            None => return res,
+        };
+        let mut depth = 0usize;
+        for (i, token) in self.tokens.iter().enumerate().skip(start) {
+            match token.token {
+                TokenType::LeftCurly => depth += 1,
+                TokenType::RightCurly => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        res.push(token.span.line);
+                        let next = self.tokens.get(i + 1).map(|t| &t.token);
+                        if node.token != TokenType::If
+                            || !matches!(next, Some(TokenType::Elif | TokenType::Else))
+                        {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+
+    fn block(&mut self, nodes: &[ParseNode], end: usize) {
+        self.first_in_block = true;
+        for node in nodes.iter() {
+            self.statement(node);
+        }
+        self.flush(end);
+    }
+
+    /// Writes `{header} {` and the body, or `{header} {}` for a last empty
+    /// block with nothing to keep inside. Returns whether the block is still
+    /// open.
+    fn braced(
+        &mut self,
+        header: &str,
+        line: usize,
+        body: &[ParseNode],
+        end: usize,
+        last: bool,
+    ) -> bool {
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|c| c.line > line && c.line < end);
+        if body.is_empty() && !has_comments && last {
+            self.line(&format!("{} {{}}", header), line);
+            return false;
+        }
+        self.line(&format!("{} {{", header), line);
+        self.w.indent();
+        self.block(body, end);
+        self.w.dedent();
+        self.first_in_block = false;
+        true
+    }
+
+    fn statement(&mut self, node: &ParseNode) {
+        let line = node.span.line;
+        self.flush(line);
+        self.separate(line);
+        let closing = self.closing_lines(node);
+        let end = |i: usize| closing.get(i).copied().unwrap_or(usize::MAX);
+        match &node.token {
+            TokenType::Let => {
+                let target = node.children.first().map(|c| self.expression(c));
+                let value = node.children.get(1).map(|c| self.expression(c));
+                let text = format!(
+                    "let {} = {}",
+                    target.unwrap_or_default(),
+                    value.unwrap_or_default()
+                );
+                self.line(&text, line);
+            }
+            TokenType::Print => {
+                let value = node.children.first().map(|c| self.expression(c));
+                self.line(&format!("print({})", value.unwrap_or_default()), line);
+            }
+            TokenType::Return => match node.children.first() {
+                Some(value) => {
+                    let text = format!("return {}", self.expression(value));
+                    self.line(&text, line);
+                }
+                None => self.line("return", line),
+            },
+            TokenType::Call(_) => {
+                let text = self.expression(node);
+                self.line(&text, line);
+            }
+            TokenType::While => {
+                let cond = node.extra_info.as_deref().map(|c| self.expression(c));
+                let header = format!("while ({})", cond.unwrap_or_default());
+                if self.braced(&header, line, &node.children, end(0), true) {
+                    self.line("}", end(0));
+                }
+            }
+            TokenType::Fn(Some(info)) => {
+                let params: Vec<String> = node
+                    .extra_info
+                    .iter()
+                    .flat_map(|p| p.children.iter())
+                    .map(|p| self.expression(p))
+                    .collect();
+                let name = match &*info.name {
+                    TokenType::Identifier(x) => x.clone(),
+                    _ => String::new(),
+                };
+                let header = format!("fn {}({})", name, params.join(", "));
+                if self.braced(&header, line, &node.children, end(0), true) {
+                    self.line("}", end(0));
+                }
+            }
+            TokenType::If => {
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                let cond = node.extra_info.as_deref().map(|c| self.expression(c));
+                let header = format!("if ({})", cond.unwrap_or_default());
+                let mut open = self.braced(&header, line, body, end(0), branches.is_empty());
+                for (i, branch) in branches.iter().enumerate() {
+                    let header = match branch.extra_info.as_deref() {
+                        Some(cond) => format!("}} elif ({})", self.expression(cond)),
+                        None => "} else".to_string(),
+                    };
+                    let last = i + 1 == branches.len();
+                    open = self.braced(&header, end(i), &branch.children, end(i + 1), last);
+                }
+                if open {
+                    self.line("}", end(branches.len()));
+                }
+            }
+            _ => {
+                let text = self.text(node.span);
+                self.line(&text, line);
+            }
+        }
+    }
+
+    fn expression(&self, node: &ParseNode) -> String {
+        match &node.token {
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => format!("{}{}", op, self.expression(operand)),
+                [lhs, rhs] => format!("{} {} {}", self.expression(lhs), op, self.expression(rhs)),
+                _ => self.text(node.span),
+            },
+            TokenType::Call(info) => {
+                let args: Vec<String> = node.children.iter().map(|a| self.expression(a)).collect();
+                let name = match &*info.name {
+                    TokenType::Identifier(x) => x.clone(),
+                    _ => String::new(),
+                };
+                format!("{}({})", name, args.join(", "))
+            }
+            _ => self.text(node.span),
+        }
+    }
+}
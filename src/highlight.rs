@@ -0,0 +1,222 @@
+use crate::json::{object, Json};
+use crate::lexer::{Span, Token, TokenType, KEYWORDS, OPERATORS};
+
+/*
+Highlighting comes from the lexer so editors color exactly what it accepts.
+Tokens are classified by their TokenType, with a match that has to be
+extended along with it, and identifiers by the tokens around them: the name
+after `fn` or before `(` is a function, names inside the parentheses of a
+`fn` are parameters.
+
+The TextMate grammar is built from the lexer's keyword and operator tables.
+It only sees text, so it recognises declarations and calls by their shape.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Keyword,
+    Operator,
+    Number,
+    String,
+    Comment,
+    Function,
+    Parameter,
+    Variable,
+}
+
+impl Class {
+    /// Every class, in the order of the LSP semantic token legend.
+    pub const ALL: [Class; 8] = [
+        Class::Keyword,
+        Class::Operator,
+        Class::Number,
+        Class::String,
+        Class::Comment,
+        Class::Function,
+        Class::Parameter,
+        Class::Variable,
+    ];
+
+    /// The LSP semantic token type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Class::Keyword => "keyword",
+            Class::Operator => "operator",
+            Class::Number => "number",
+            Class::String => "string",
+            Class::Comment => "comment",
+            Class::Function => "function",
+            Class::Parameter => "parameter",
+            Class::Variable => "variable",
+        }
+    }
+
+    /// The TextMate scope.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            Class::Keyword => "keyword.control.asdf",
+            Class::Operator => "keyword.operator.asdf",
+            Class::Number => "constant.numeric.asdf",
+            Class::String => "string.quoted.double.asdf",
+            Class::Comment => "comment.line.double-slash.asdf",
+            Class::Function => "entity.name.function.asdf",
+            Class::Parameter => "variable.parameter.asdf",
+            Class::Variable => "variable.other.asdf",
+        }
+    }
+}
+
+/// The class of a token on its own, or None for punctuation.
+fn class(token: &TokenType) -> Option<Class> {
+    match token {
+        TokenType::Print
+        | TokenType::Let
+        | TokenType::If
+        | TokenType::Elif
+        | TokenType::Else
+        | TokenType::While
+        | TokenType::Fn(_)
+        | TokenType::Return => Some(Class::Keyword),
+        TokenType::Operator(_) => Some(Class::Operator),
+        TokenType::Number(_) => Some(Class::Number),
+        TokenType::StringLiteral(_) => Some(Class::String),
+        TokenType::Identifier(_) => Some(Class::Variable),
+        TokenType::Call(_) => Some(Class::Function),
+        TokenType::LeftParen
+        | TokenType::RightParen
+        | TokenType::LeftCurly
+        | TokenType::RightCurly
+        | TokenType::SemiColon
+        | TokenType::Parameters => None,
+    }
+}
+
+/// Classes of the tokens and comments of a source, in order.
+pub fn classify(tokens: &[Token], comments: &[Span]) -> Vec<(Span, Class)> {
+    let mut res: Vec<(Span, Class)> = comments.iter().map(|c| (*c, Class::Comment)).collect();
+    let mut in_parameters = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|i| &tokens[i].token);
+        let next = tokens.get(i + 1).map(|t| &t.token);
+        let class = match (&token.token, previous, next) {
+            (TokenType::Identifier(_), Some(TokenType::Fn(_)), _) => {
+                in_parameters = true;
+                Some(Class::Function)
+            }
+            (TokenType::Identifier(_), _, _) if in_parameters => Some(Class::Parameter),
+            (TokenType::Identifier(_), _, Some(TokenType::LeftParen)) => Some(Class::Function),
+            (TokenType::RightParen, _, _) => {
+                in_parameters = false;
+                None
+            }
+            (token, _, _) => class(token),
+        };
+        res.extend(class.map(|class| (token.span, class)));
+    }
+    res.sort_by_key(|(span, _)| span.start);
+    res
+}
+
+/// Escapes `s` for use in an Oniguruma regex.
+fn escape(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| match c.is_alphanumeric() {
+            true => vec![c],
+            false => vec!['\\', c],
+        })
+        .collect()
+}
+
+fn named(scope: &str) -> Json {
+    object(vec![("name", Json::Str(scope.to_string()))])
+}
+
+fn rule(class: Class, pattern: String) -> Json {
+    object(vec![
+        ("name", Json::Str(class.scope().to_string())),
+        ("match", Json::Str(pattern)),
+    ])
+}
+
+/// A TextMate grammar for asdf, as JSON.
+pub fn textmate() -> Json {
+    let keywords: Vec<&str> = KEYWORDS.iter().map(|(word, _)| *word).collect();
+    // Longer operators first so `==` isn't read as two `=`.
+    let mut operators: Vec<String> = OPERATORS.iter().map(|op| escape(&op.to_string())).collect();
+    operators.sort_by_key(|op| std::cmp::Reverse(op.len()));
+    let identifier = r"\p{L}[\p{L}\p{N}_]*";
+    let patterns = vec![
+        rule(Class::Comment, "//.*$".to_string()),
+        object(vec![
+            ("name", Json::Str(Class::String.scope().to_string())),
+            ("begin", Json::Str("\"".to_string())),
+            ("end", Json::Str("\"".to_string())),
+            (
+                "patterns",
+                Json::Array(vec![object(vec![
+                    (
+                        "name",
+                        Json::Str("constant.character.escape.asdf".to_string()),
+                    ),
+                    ("match", Json::Str(r"\\.".to_string())),
+                ])]),
+            ),
+        ]),
+        object(vec![
+            ("match", Json::Str(format!(r"\b(fn)\s+({})", identifier))),
+            (
+                "captures",
+                object(vec![
+                    ("1", named(Class::Keyword.scope())),
+                    ("2", named(Class::Function.scope())),
+                ]),
+            ),
+        ]),
+        rule(Class::Keyword, format!(r"\b({})\b", keywords.join("|"))),
+        rule(Class::Function, format!(r"{}(?=\s*\()", identifier)),
+        rule(Class::Number, "[0-9]+".to_string()),
+        rule(Class::Variable, identifier.to_string()),
+        rule(Class::Operator, operators.join("|")),
+    ];
+    object(vec![
+        ("name", Json::Str("asdf".to_string())),
+        ("scopeName", Json::Str("source.asdf".to_string())),
+        (
+            "fileTypes",
+            Json::Array(vec![Json::Str("asdf".to_string())]),
+        ),
+        ("patterns", Json::Array(patterns)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn classes_follow_the_lexer() {
+        let keywords: Vec<&str> = KEYWORDS.iter().map(|(word, _)| *word).collect();
+        let operators: Vec<String> = OPERATORS.iter().map(|op| op.to_string()).collect();
+        let source = format!(
+            "{}\n{}\n12 \"a string\" // a comment\n",
+            keywords.join(" "),
+            operators.join(" ")
+        );
+        let mut lexer = Lexer::new(&source);
+        let tokens = lexer.lex();
+        let classes: Vec<(&str, Class)> = classify(&tokens, &lexer.comments)
+            .into_iter()
+            .map(|(span, class)| (&source[span.start..span.end], class))
+            .collect();
+        let mut expected: Vec<(&str, Class)> = vec![];
+        expected.extend(keywords.iter().map(|k| (*k, Class::Keyword)));
+        expected.extend(operators.iter().map(|op| (op.as_str(), Class::Operator)));
+        expected.extend([
+            ("12", Class::Number),
+            ("\"a string\"", Class::String),
+            ("// a comment", Class::Comment),
+        ]);
+        assert_eq!(classes, expected);
+    }
+}
@@ -0,0 +1,384 @@
+use crate::lexer::{Lexer, Span, Token, TokenType};
+use crate::parser::{ParseNode, Parser};
+use std::collections::HashSet;
+
+/*
+Keeps the tokens and top level statements of a source that is edited in
+place, redoing only the part an edit can change.
+
+The lexer carries nothing from one token to the next but its position, so
+after an edit lexing restarts at the last token that ends before it and stops
+as soon as it reaches the start of a token that was there before. Everything
+from there on is the old tokens moved by the size of the edit.
+
+A statement depends on its own tokens, one token of lookahead and the top
+level names declared before it, which only decide its warnings. Parsing
+restarts at the statement holding the token before the first changed one and
+stops at the start of an old statement past the changed tokens, if the names
+declared by then are the same as they were there.
+*/
+
+/// A source with its tokens and parse, kept up to date as it is edited.
+/// The result is always the same as lexing and parsing the text again.
+#[derive(Default)]
+pub struct Tree {
+    pub text: String,
+    pub tokens: Vec<Token>,
+    pub lex_errors: Vec<(Span, String)>,
+    pub comments: Vec<Span>,
+    nodes: Vec<ParseNode>,
+    /// Index of the first token of each statement.
+    starts: Vec<usize>,
+    /// Warnings of each statement.
+    warnings: Vec<Vec<(Span, String)>>,
+    failure: Option<Failure>,
+}
+
+/// Where parsing failed, after the statements that parsed.
+struct Failure {
+    /// The parser's position when it gave up.
+    cur: usize,
+    message: String,
+    warnings: Vec<(Span, String)>,
+}
+
+/// Moves spans at or after `from` in the old text to start at `to` instead.
+#[derive(Clone, Copy)]
+struct Shift {
+    from: Span,
+    to: Span,
+}
+
+impl Shift {
+    fn span(&self, span: Span) -> Span {
+        let col = match span.line == self.from.line {
+            true => span.col - self.from.col + self.to.col,
+            false => span.col,
+        };
+        Span {
+            start: span.start - self.from.start + self.to.start,
+            end: span.end - self.from.start + self.to.start,
+            line: span.line - self.from.line + self.to.line,
+            col,
+        }
+    }
+
+    fn node(&self, node: &mut ParseNode) {
+        node.span = self.span(node.span);
+        if let Some(extra) = node.extra_info.as_deref_mut() {
+            self.node(extra);
+        }
+        for child in node.children.iter_mut() {
+            self.node(child);
+        }
+    }
+
+    fn messages(&self, messages: &mut [(Span, String)]) {
+        for (span, _) in messages.iter_mut() {
+            *span = self.span(*span);
+        }
+    }
+}
+
+/// The name a top level statement adds to the parser's environment.
+fn declares(node: &ParseNode) -> Option<TokenType> {
+    match node.token {
+        TokenType::Let => node.children.first().map(|c| c.token.clone()),
+        TokenType::Fn(Some(ref info)) => Some((*info.name).clone()),
+        _ => None,
+    }
+}
+
+impl Tree {
+    pub fn new(text: &str) -> Self {
+        let mut tree = Tree::default();
+        tree.edit(0, 0, text);
+        tree
+    }
+
+    pub fn nodes(&self) -> &[ParseNode] {
+        &self.nodes
+    }
+
+    /// The parse error and where it was found, if the source doesn't parse.
+    pub fn error(&self) -> Option<(Span, &str)> {
+        self.failure.as_ref().map(|failure| {
+            let span = self
+                .tokens
+                .get(failure.cur.saturating_sub(1))
+                .or_else(|| self.tokens.last())
+                .map(|t| t.span)
+                .unwrap_or_default();
+            (span, failure.message.as_str())
+        })
+    }
+
+    /// Warnings from the parser, in the order it gave them.
+    pub fn warnings(&self) -> impl Iterator<Item = &(Span, String)> {
+        self.warnings
+            .iter()
+            .flatten()
+            .chain(self.failure.iter().flat_map(|f| f.warnings.iter()))
+    }
+
+    /// Replaces the characters from `start` to `end` with `text`.
+    pub fn edit(&mut self, start: usize, end: usize, text: &str) {
+        let byte = |at: usize| {
+            self.text
+                .char_indices()
+                .nth(at)
+                .map_or(self.text.len(), |(i, _)| i)
+        };
+        let range = byte(start)..byte(end);
+        self.text.replace_range(range, text);
+        let inserted = text.chars().count();
+        let (first, old_end, new_end, shift) = self.relex(start, end, inserted);
+        self.reparse(first, old_end, new_end, shift);
+    }
+
+    /// Lexes the edited region again. Returns the index of the first token
+    /// that may have changed, the indices after the changed tokens before
+    /// and after the edit, and how the tokens after them moved if lexing
+    /// caught up with them.
+    fn relex(
+        &mut self,
+        start: usize,
+        end: usize,
+        inserted: usize,
+    ) -> (usize, usize, usize, Option<Shift>) {
+        // The last token ending before the edit is lexed again too, the
+        // character after it may have been its lookahead.
+        let (first, restart) = match self.tokens.partition_point(|t| t.span.end < start) {
+            0 => (
+                0,
+                Span {
+                    line: 1,
+                    col: 1,
+                    ..Span::default()
+                },
+            ),
+            n => (n - 1, self.tokens[n - 1].span),
+        };
+        let rest: Vec<char> = self.text.chars().skip(restart.start).collect();
+        let mut lexer = Lexer::resume(rest, restart);
+        let mut lexed = vec![];
+        let mut old = self.tokens.partition_point(|t| t.span.start < end);
+        let mut shift = None;
+        loop {
+            let at = lexer.mark();
+            if at.start >= start + inserted {
+                let before = at.start - inserted - start + end;
+                while self.tokens.get(old).is_some_and(|t| t.span.start < before) {
+                    old += 1;
+                }
+                if let Some(t) = self.tokens.get(old).filter(|t| t.span.start == before) {
+                    shift = Some(Shift {
+                        from: t.span,
+                        to: at,
+                    });
+                    break;
+                }
+            }
+            if !lexer.step(&mut lexed) {
+                old = self.tokens.len();
+                break;
+            }
+        }
+        let mut tail = self.tokens.split_off(old);
+        self.tokens.truncate(first);
+        let new_end = first + lexed.len();
+        self.tokens.extend(lexed);
+        let errors = std::mem::take(&mut self.lex_errors);
+        let (before, mut after): (Vec<_>, Vec<_>) = errors
+            .into_iter()
+            .partition(|(span, _)| span.start < restart.start);
+        self.lex_errors = before;
+        self.lex_errors.append(&mut lexer.errors);
+        let mut after_comments = self.comments.split_off(
+            self.comments
+                .partition_point(|span| span.start < restart.start),
+        );
+        self.comments.append(&mut lexer.comments);
+        if let Some(shift) = shift {
+            for token in tail.iter_mut() {
+                token.span = shift.span(token.span);
+            }
+            after.retain(|(span, _)| span.start >= shift.from.start);
+            shift.messages(&mut after);
+            self.tokens.append(&mut tail);
+            self.lex_errors.append(&mut after);
+            after_comments.retain(|span| span.start >= shift.from.start);
+            self.comments
+                .extend(after_comments.iter().map(|span| shift.span(*span)));
+        }
+        (first, old, new_end, shift)
+    }
+
+    /// Parses the statements around the changed tokens `first..new_end`,
+    /// which replaced `first..old_end`, again.
+    fn reparse(&mut self, first: usize, old_end: usize, new_end: usize, shift: Option<Shift>) {
+        // The statement before the change looked one token ahead into it.
+        let keep = self
+            .starts
+            .partition_point(|s| *s < first)
+            .saturating_sub(1);
+        let mut env: HashSet<TokenType> = self.nodes[..keep].iter().filter_map(declares).collect();
+        let mut old_nodes = self.nodes.split_off(keep);
+        let old_starts = self.starts.split_off(keep);
+        let mut old_warnings = self.warnings.split_off(keep);
+        let old_failure = self.failure.take();
+        let mut old_env = env.clone();
+        let mut old = 0;
+
+        let cur = old_starts.first().copied().unwrap_or(0);
+        let mut parser = Parser::resume(std::mem::take(&mut self.tokens), cur);
+        loop {
+            let cur = parser.position();
+            if let (Some(shift), true) = (shift, cur >= new_end) {
+                let before = cur - new_end + old_end;
+                while old_starts.get(old).is_some_and(|s| *s < before) {
+                    old_env.extend(declares(&old_nodes[old]));
+                    old += 1;
+                }
+                if old_starts.get(old) == Some(&before) && old_env == env {
+                    for node in old_nodes[old..].iter_mut() {
+                        shift.node(node);
+                    }
+                    for warnings in old_warnings[old..].iter_mut() {
+                        shift.messages(warnings);
+                    }
+                    self.nodes.extend(old_nodes.drain(old..));
+                    self.starts
+                        .extend(old_starts[old..].iter().map(|s| s - old_end + new_end));
+                    self.warnings.extend(old_warnings.drain(old..));
+                    self.failure = old_failure.map(|mut failure| {
+                        failure.cur = failure.cur - old_end + new_end;
+                        shift.messages(&mut failure.warnings);
+                        failure
+                    });
+                    break;
+                }
+            }
+            match parser.parse_top_level(&mut env) {
+                None => break,
+                Some(Ok(node)) => {
+                    self.nodes.push(node);
+                    self.starts.push(cur);
+                    self.warnings.push(std::mem::take(&mut parser.warnings));
+                }
+                Some(Err(message)) => {
+                    self.failure = Some(Failure {
+                        cur: parser.position(),
+                        message,
+                        warnings: std::mem::take(&mut parser.warnings),
+                    });
+                    break;
+                }
+            }
+        }
+        self.tokens = parser.into_tokens();
+    }
+}
+
+/// Compares `tree` with lexing and parsing its text from scratch.
+fn check(tree: &Tree) -> Result<(), String> {
+    let mut lexer = Lexer::new(&tree.text);
+    let tokens = lexer.lex();
+    if tokens != tree.tokens {
+        return Err("tokens differ".to_string());
+    }
+    if lexer.errors != tree.lex_errors {
+        return Err("lexer errors differ".to_string());
+    }
+    if lexer.comments != tree.comments {
+        return Err("comments differ".to_string());
+    }
+    let mut parser = Parser::new(tokens);
+    let full = match parser.parse() {
+        Ok(nodes) => Ok(format!("{:?}", nodes)),
+        Err(message) => Err((parser.error_span(), message)),
+    };
+    let incremental = match tree.error() {
+        None => Ok(format!("{:?}", tree.nodes())),
+        Some((span, message)) => Err((span, message.to_string())),
+    };
+    if full != incremental {
+        return Err(format!("parses differ: {:?} vs {:?}", full, incremental));
+    }
+    if !parser.warnings.iter().eq(tree.warnings()) {
+        return Err("warnings differ".to_string());
+    }
+    Ok(())
+}
+
+/// Pieces of asdf that random edits insert.
+const SNIPPETS: [&str; 24] = [
+    "", " ", "\n", "let ", "x", "y1", "7", "\"", "\"s\"", "(", ")", "{", "}", "+", "-", "=", "==",
+    "<", "//", "fn ", "if ", "else ", "return ", "print(",
+];
+
+/// Applies `edits` random edits to `text`, each undone again afterwards, and
+/// checks the incremental result against a full parse after every one.
+pub fn fuzz(text: &str, seed: u64, edits: usize) -> Result<(), String> {
+    // xorshift64, a seed of 0 would stay 0.
+    let mut state = seed.max(1);
+    let mut random = |n: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n.max(1) as u64) as usize
+    };
+    let original: Vec<char> = text.chars().collect();
+    let mut tree = Tree::new(text);
+    check(&tree).map_err(|e| format!("initial parse: {}", e))?;
+    for i in 0..edits {
+        let len = tree.text.chars().count();
+        let start = random(len + 1);
+        let end = (start + random(8)).min(len);
+        let inserted = match random(3) {
+            0 => {
+                let from = random(original.len() + 1);
+                let to = (from + random(16)).min(original.len());
+                original[from..to].iter().collect()
+            }
+            _ => SNIPPETS[random(SNIPPETS.len())].to_string(),
+        };
+        let removed: String = tree.text.chars().skip(start).take(end - start).collect();
+        tree.edit(start, end, &inserted);
+        check(&tree).map_err(|e| {
+            format!(
+                "edit {} replacing {}..{} with {:?}: {}",
+                i, start, end, inserted, e
+            )
+        })?;
+        let inserted_len = inserted.chars().count();
+        tree.edit(start, start + inserted_len, &removed);
+        check(&tree).map_err(|e| format!("undoing edit {}: {}", i, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "// counts down
+let total = 0
+fn add(n) {
+    if (n < 1) { return total } elif (n == 1) { return 1 }
+    return add(n - 1) + n
+}
+let name = \"héllo 😀\"
+while (total < 3) { let total = total + 1 }
+print(add(total)) print(name)
+";
+
+    #[test]
+    fn edits_match_a_full_parse() {
+        for seed in [1, 42, 2024] {
+            if let Err(e) = fuzz(PROGRAM, seed, 500) {
+                panic!("seed {}: {}", seed, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use crate::lexer::{Span, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+use crate::value::{self, Callable, MAX_CALL_DEPTH};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub type Value = value::Value<Function>;
+
+#[derive(Debug)]
+pub struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<ParseNode>,
+    closure: Env,
+}
+
+impl Callable for Function {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+type Env = Rc<RefCell<Environment>>;
+
+/// Variables are scoped per function like in the generated Python: a name
+/// bound anywhere in a function is local to the whole call, and reading it
+/// before it is bound is an error rather than a read of an outer variable.
+/// Other names are looked up in the environment the function was defined
+/// in, so nested functions see later assignments to them.
+#[derive(Debug, Default)]
+pub struct Environment {
+    /// None for a local that is not bound yet.
+    values: HashMap<String, Option<Value>>,
+    parent: Option<Env>,
+}
+
+impl Environment {
+    fn child(parent: &Env) -> Env {
+        Rc::new(RefCell::new(Environment {
+            values: HashMap::new(),
+            parent: Some(Rc::clone(parent)),
+        }))
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(v) => v.clone(),
+            None => self.parent.as_ref().and_then(|p| p.borrow().get(name)),
+        }
+    }
+
+    /// Whether `name` is a local of this or an enclosing scope.
+    fn declares(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|p| p.borrow().declares(name))
+    }
+
+    fn declare(&mut self, name: &str) {
+        self.values.entry(name.to_string()).or_insert(None);
+    }
+
+    fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), Some(value));
+    }
+
+    fn lookup(&self, name: &str, span: Span) -> Result<Value, String> {
+        match self.get(name) {
+            Some(v) => Ok(v),
+            None if self.declares(name) => error(
+                span,
+                format!("local variable '{}' referenced before assignment", name),
+            ),
+            None => error(span, format!("name '{}' is not defined", name)),
+        }
+    }
+}
+
+enum Flow {
+    Normal,
+    Return(Value),
+}
+
+fn error<T>(span: Span, message: impl fmt::Display) -> Result<T, String> {
+    Err(format!("Runtime error at {}: {}", span, message))
+}
+
+fn identifier(token: &TokenType) -> Result<&str, String> {
+    match token {
+        TokenType::Identifier(x) => Ok(x),
+        _ => Err("Invalid state".to_string()),
+    }
+}
+
+pub struct Interpreter {
+    globals: Env,
+    depth: usize,
+    /// Lines printed by the program, when they are collected instead of
+    /// written to stdout.
+    output: Option<Vec<String>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            globals: Rc::new(RefCell::new(Environment::default())),
+            depth: 0,
+            output: None,
+        }
+    }
+
+    /// An interpreter that keeps what the program prints, see `output`.
+    #[cfg(test)]
+    pub fn collecting() -> Self {
+        Self {
+            output: Some(vec![]),
+            ..Self::new()
+        }
+    }
+
+    #[cfg(test)]
+    pub fn output(&self) -> &[String] {
+        self.output.as_deref().unwrap_or_default()
+    }
+
+    pub fn run(&mut self, nodes: &[ParseNode]) -> Result<(), String> {
+        let globals = Rc::clone(&self.globals);
+        for node in nodes.iter() {
+            if let Flow::Return(_) = self.exec(node, &globals)? {
+                return error(node.span, "'return' outside function");
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates a single expression against the global environment.
+    pub fn evaluate(&mut self, node: &ParseNode) -> Result<Value, String> {
+        let globals = Rc::clone(&self.globals);
+        self.eval(node, &globals)
+    }
+
+    fn exec_block(&mut self, nodes: &[ParseNode], env: &Env) -> Result<Flow, String> {
+        for node in nodes.iter() {
+            if let Flow::Return(v) = self.exec(node, env)? {
+                return Ok(Flow::Return(v));
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, node: &ParseNode, env: &Env) -> Result<Flow, String> {
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let value = self.eval(node.children.get(1).ok_or("Invalid state")?, env)?;
+                env.borrow_mut().define(id, value);
+                Ok(Flow::Normal)
+            }
+            TokenType::Print => {
+                let value = self.eval(node.children.first().ok_or("Invalid state")?, env)?;
+                match &mut self.output {
+                    Some(output) => output.push(value.to_string()),
+                    None => println!("{}", value),
+                }
+                Ok(Flow::Normal)
+            }
+            TokenType::While => {
+                let cond = node.extra_info.as_ref().ok_or("Invalid state")?;
+                while self.eval(cond, env)?.is_truthy() {
+                    if let Flow::Return(v) = self.exec_block(&node.children, env)? {
+                        return Ok(Flow::Return(v));
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            TokenType::If => {
+                let cond = node.extra_info.as_ref().ok_or("Invalid state")?;
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                if self.eval(cond, env)?.is_truthy() {
+                    return self.exec_block(body, env);
+                }
+                for branch in branches.iter() {
+                    let taken = match &branch.extra_info {
+                        Some(cond) => self.eval(cond, env)?.is_truthy(),
+                        None => true,
+                    };
+                    if taken {
+                        return self.exec_block(&branch.children, env);
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            TokenType::Fn(Some(info)) => {
+                let name = identifier(&info.name)?;
+                let params = node
+                    .extra_info
+                    .as_ref()
+                    .ok_or("Invalid state")?
+                    .children
+                    .iter()
+                    .map(|p| identifier(&p.token).map(|x| x.to_string()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let function = Function {
+                    name: name.to_string(),
+                    params,
+                    body: node.children.clone(),
+                    closure: Rc::clone(env),
+                };
+                env.borrow_mut()
+                    .define(name, Value::Function(Rc::new(function)));
+                Ok(Flow::Normal)
+            }
+            TokenType::Call(_) => {
+                self.eval(node, env)?;
+                Ok(Flow::Normal)
+            }
+            TokenType::Return => {
+                let value = match node.children.first() {
+                    Some(v) => self.eval(v, env)?,
+                    None => Value::None,
+                };
+                Ok(Flow::Return(value))
+            }
+            t => error(node.span, format!("{:?} is not a statement", t)),
+        }
+    }
+
+    fn eval(&mut self, node: &ParseNode, env: &Env) -> Result<Value, String> {
+        match &node.token {
+            TokenType::Number(x) => Ok(Value::Number(x.into_inner() as f64)),
+            TokenType::StringLiteral(x) => Ok(Value::Str(Rc::from(x.as_str()))),
+            TokenType::Identifier(x) => env.borrow().lookup(x, node.span),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => {
+                    let value = self.eval(operand, env)?;
+                    Value::unary(op, value).or_else(|message| error(node.span, message))
+                }
+                [lhs, rhs] => {
+                    let lhs = self.eval(lhs, env)?;
+                    let rhs = self.eval(rhs, env)?;
+                    Value::binary(op, lhs, rhs).or_else(|message| error(node.span, message))
+                }
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let name = identifier(&info.name)?;
+                let function = match env.borrow().lookup(name, node.span)? {
+                    Value::Function(f) => f,
+                    v => return error(node.span, format!("'{}' is not callable", v.type_name())),
+                };
+                let mut args = vec![];
+                for arg in node.children.iter() {
+                    args.push(self.eval(arg, env)?);
+                }
+                self.call(&function, args, node.span)
+            }
+            t => error(node.span, format!("{:?} is not an expression", t)),
+        }
+    }
+
+    fn call(&mut self, function: &Function, args: Vec<Value>, span: Span) -> Result<Value, String> {
+        if args.len() != function.params.len() {
+            return error(
+                span,
+                format!(
+                    "{}() takes {} arguments but {} were given",
+                    function.name,
+                    function.params.len(),
+                    args.len()
+                ),
+            );
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return error(span, "maximum recursion depth exceeded");
+        }
+        let env = Environment::child(&function.closure);
+        let mut bindings = vec![];
+        collect_bindings(&function.body, &mut bindings);
+        for binding in bindings.iter() {
+            env.borrow_mut().declare(binding);
+        }
+        for (param, arg) in function.params.iter().zip(args) {
+            env.borrow_mut().define(param, arg);
+        }
+        self.depth += 1;
+        let flow = self.exec_block(&function.body, &env);
+        self.depth -= 1;
+        match flow? {
+            Flow::Return(v) => Ok(v),
+            Flow::Normal => Ok(Value::None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// What `source` prints, and how it ends.
+    fn run(source: &str) -> (Vec<String>, Result<(), String>) {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let mut interpreter = Interpreter::collecting();
+        let result = interpreter.run(&nodes);
+        (interpreter.output().to_vec(), result)
+    }
+
+    fn prints(source: &str) -> Vec<String> {
+        let (output, result) = run(source);
+        assert_eq!(result, Ok(()), "{}", source);
+        output
+    }
+
+    #[test]
+    fn scoping() {
+        let source = "let x = 1
+            fn shadow() { let x = 2 return x }
+            fn global() { return x }
+            fn outer() { let y = 3 fn inner() { return y + x } return inner() }
+            if (1) { let z = 5 }
+            print(shadow()) print(global()) print(x) print(outer()) print(z)";
+        assert_eq!(prints(source), ["2", "1", "1", "4", "5"]);
+        let (output, result) = run("let x = 1\nfn f() {\n    print(x)\n    let x = 2\n}\nf()");
+        assert!(output.is_empty());
+        assert_eq!(
+            result,
+            Err("Runtime error at 3:11: local variable 'x' referenced before assignment".into())
+        );
+        assert_eq!(
+            run("print(y)").1,
+            Err("Runtime error at 1:7: name 'y' is not defined".into())
+        );
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "fn fact(n) { if (n < 2) { return 1 } return n * fact(n - 1) }
+            fn even(n) { if (n == 0) { return 1 } return odd(n - 1) }
+            fn odd(n) { if (n == 0) { return 0 } return even(n - 1) }
+            print(fact(10)) print(even(10)) print(odd(7))";
+        assert_eq!(prints(source), ["3628800", "1", "1"]);
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let down = |n: usize| {
+            format!(
+                "fn down(n) {{ if (n == 0) {{ return 0 }} return down(n - 1) }} print(down({}))",
+                n
+            )
+        };
+        // Each level of asdf recursion takes a few Rust frames.
+        let (deepest, too_deep) = std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || (run(&down(MAX_CALL_DEPTH - 1)), run(&down(MAX_CALL_DEPTH))))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deepest, (vec!["0".to_string()], Ok(())));
+        assert_eq!(
+            too_deep.1,
+            Err("Runtime error at 1:46: maximum recursion depth exceeded".into())
+        );
+    }
+
+    #[test]
+    fn string_concatenation() {
+        let source = "let s = \"a\" + \"b\"
+            let t = s + \"c\"
+            fn greet(name) { return \"hi \" + name }
+            print(t) print(greet(t)) print(\"\" + \"\" == \"\")";
+        assert_eq!(prints(source), ["abc", "hi abc", "True"]);
+        assert_eq!(
+            run("print(\"a\" + 1)").1,
+            Err(
+                "Runtime error at 1:11: unsupported operand types for +: 'string' and 'number'"
+                    .into()
+            )
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use super::{Block, Function, Instr, Module, Op, Operand, Place};
+use crate::lexer::Span;
+use std::collections::{BTreeSet, HashSet};
+
+/*
+Dataflow analyses over the blocks of a function, solved by iterating until
+no fact changes. An analysis only describes what a single instruction does
+to its fact and how facts meet where control flow joins; `solve` takes care
+of the blocks and the order of the instructions within them.
+
+Liveness understands SSA form: the arguments of a `phi` are live at the end
+of their predecessor rather than at the start of the phi's block. The other
+analyses treat a `phi` like any other assignment.
+
+Dead stores are assignments of a literal that is overwritten or forgotten
+before anything reads it. Only literals are considered, as evaluating
+anything else may fail or print. A variable another function reads is never
+dead, since liveness only sees the reads of one function, and one
+assignment of a variable that is read before any of them is kept too.
+*/
+
+pub trait Analysis {
+    type Fact: Clone + PartialEq;
+
+    /// Whether facts flow from the exits of the function towards its entry.
+    const BACKWARD: bool;
+
+    /// The fact at the entry of the function, or at its exits for a backward
+    /// analysis.
+    fn boundary(&self) -> Self::Fact;
+
+    /// The fact every other block starts from, which `meet` leaves alone.
+    fn top(&self) -> Self::Fact;
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact;
+
+    /// Applies the instruction at index `at.1` of block `at.0` to `fact`.
+    fn step(&self, at: (usize, usize), instr: &Instr, fact: &mut Self::Fact);
+
+    /// Adjusts the fact flowing along the edge from block `from` to `to`,
+    /// which is the fact at the start of `to` for a backward analysis and
+    /// the fact at the end of `from` otherwise.
+    fn edge(&self, _from: usize, _to: &Block, fact: &Self::Fact) -> Self::Fact {
+        fact.clone()
+    }
+}
+
+/// The facts at the start and the end of every block.
+pub struct Solution<F> {
+    pub entry: Vec<F>,
+    pub exit: Vec<F>,
+}
+
+pub fn solve<A: Analysis>(analysis: &A, function: &Function) -> Solution<A::Fact> {
+    let count = function.blocks.len();
+    let predecessors = function.predecessors();
+    let mut entry = vec![analysis.top(); count];
+    let mut exit = vec![analysis.top(); count];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..count {
+            // Visiting blocks against the flow would take more rounds.
+            let b = if A::BACKWARD { count - 1 - i } else { i };
+            let block = &function.blocks[b];
+            if A::BACKWARD {
+                let successors = block.successors();
+                let mut fact = if successors.is_empty() {
+                    analysis.boundary()
+                } else {
+                    successors.iter().fold(analysis.top(), |f, s| {
+                        let fact = analysis.edge(b, &function.blocks[*s], &entry[*s]);
+                        analysis.meet(&f, &fact)
+                    })
+                };
+                if fact != exit[b] {
+                    exit[b] = fact.clone();
+                    changed = true;
+                }
+                for (i, instr) in block.instrs.iter().enumerate().rev() {
+                    analysis.step((b, i), instr, &mut fact);
+                }
+                if fact != entry[b] {
+                    entry[b] = fact;
+                    changed = true;
+                }
+            } else {
+                let start = match b {
+                    0 => analysis.boundary(),
+                    _ => analysis.top(),
+                };
+                let mut fact = predecessors[b].iter().fold(start, |f, p| {
+                    analysis.meet(&f, &analysis.edge(*p, block, &exit[*p]))
+                });
+                if fact != entry[b] {
+                    entry[b] = fact.clone();
+                    changed = true;
+                }
+                for (i, instr) in block.instrs.iter().enumerate() {
+                    analysis.step((b, i), instr, &mut fact);
+                }
+                if fact != exit[b] {
+                    exit[b] = fact;
+                    changed = true;
+                }
+            }
+        }
+    }
+    Solution { entry, exit }
+}
+
+/// Variables and temporaries whose current value may still be read.
+pub struct Liveness;
+
+impl Analysis for Liveness {
+    type Fact = BTreeSet<Place>;
+    const BACKWARD: bool = true;
+
+    fn boundary(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn top(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.union(b).cloned().collect()
+    }
+
+    fn step(&self, _: (usize, usize), instr: &Instr, fact: &mut Self::Fact) {
+        if let Some(def) = instr.op.def() {
+            fact.remove(&def);
+        }
+        if !matches!(instr.op, Op::Phi { .. }) {
+            fact.extend(instr.op.uses().into_iter().filter_map(Operand::place));
+        }
+    }
+
+    fn edge(&self, from: usize, to: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut fact = fact.clone();
+        for instr in to.instrs.iter() {
+            if let Op::Phi { args, .. } = &instr.op {
+                let args = args.iter().filter(|(p, _)| *p == from);
+                fact.extend(args.filter_map(|(_, a)| a.place()));
+            }
+        }
+        fact
+    }
+}
+
+/// An assignment that may still be the current value of its place. `at` is
+/// the block and index of the instruction, or None for a parameter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Definition {
+    pub place: Place,
+    pub at: Option<(usize, usize)>,
+}
+
+pub struct ReachingDefinitions {
+    params: Vec<String>,
+}
+
+impl ReachingDefinitions {
+    pub fn new(function: &Function) -> Self {
+        Self {
+            params: function.params.clone(),
+        }
+    }
+}
+
+impl Analysis for ReachingDefinitions {
+    type Fact = BTreeSet<Definition>;
+    const BACKWARD: bool = false;
+
+    fn boundary(&self) -> Self::Fact {
+        self.params
+            .iter()
+            .map(|p| Definition {
+                place: Place::Var(p.clone()),
+                at: None,
+            })
+            .collect()
+    }
+
+    fn top(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.union(b).cloned().collect()
+    }
+
+    fn step(&self, at: (usize, usize), instr: &Instr, fact: &mut Self::Fact) {
+        if let Some(place) = instr.op.def() {
+            fact.retain(|d| d.place != place);
+            fact.insert(Definition {
+                place,
+                at: Some(at),
+            });
+        }
+    }
+}
+
+/// Variables assigned on every path from the entry of the function.
+pub struct DefiniteAssignment {
+    params: Vec<String>,
+    locals: Vec<String>,
+}
+
+impl DefiniteAssignment {
+    pub fn new(function: &Function) -> Self {
+        Self {
+            params: function.params.clone(),
+            locals: function.locals(),
+        }
+    }
+}
+
+impl Analysis for DefiniteAssignment {
+    type Fact = BTreeSet<String>;
+    const BACKWARD: bool = false;
+
+    fn boundary(&self) -> Self::Fact {
+        self.params.iter().cloned().collect()
+    }
+
+    fn top(&self) -> Self::Fact {
+        self.locals.iter().cloned().collect()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.intersection(b).cloned().collect()
+    }
+
+    fn step(&self, _: (usize, usize), instr: &Instr, fact: &mut Self::Fact) {
+        if let Some(Place::Var(x)) = instr.op.def() {
+            fact.insert(x);
+        }
+    }
+}
+
+/// Warns about reads of a function's own variables that may happen before
+/// the variable is assigned. Names an enclosing function or the top level
+/// assigns are left alone, since such a read falls back to the outer value.
+pub fn uninitialized(module: &Module) -> Vec<(Span, String)> {
+    let mut warnings = vec![];
+    for function in module.functions.iter() {
+        let mut outer = BTreeSet::new();
+        let mut parent = function.parent;
+        while let Some(p) = parent {
+            outer.extend(module.functions[p].locals());
+            parent = module.functions[p].parent;
+        }
+        let locals: Vec<String> = function
+            .locals()
+            .into_iter()
+            .filter(|x| !outer.contains(x))
+            .collect();
+        let definite = DefiniteAssignment::new(function);
+        let assigned = solve(&definite, function);
+        let reaching_analysis = ReachingDefinitions::new(function);
+        let reaching = solve(&reaching_analysis, function);
+        for (b, block) in function.blocks.iter().enumerate() {
+            let mut assigned_here = assigned.entry[b].clone();
+            let mut reaching_here = reaching.entry[b].clone();
+            for (i, instr) in block.instrs.iter().enumerate() {
+                for operand in instr.op.uses() {
+                    let x = match operand {
+                        Operand::Var(x) if locals.contains(x) => x,
+                        _ => continue,
+                    };
+                    if assigned_here.contains(x) {
+                        continue;
+                    }
+                    let place = Place::Var(x.clone());
+                    let message = if reaching_here.iter().any(|d| d.place == place) {
+                        format!("variable '{}' may be used uninitialized", x)
+                    } else {
+                        format!("variable '{}' is used uninitialized", x)
+                    };
+                    let warning = (instr.span, message);
+                    if !warnings.contains(&warning) {
+                        warnings.push(warning);
+                    }
+                }
+                definite.step((b, i), instr, &mut assigned_here);
+                reaching_analysis.step((b, i), instr, &mut reaching_here);
+            }
+        }
+    }
+    warnings.sort_by_key(|(span, _)| span.start);
+    warnings
+}
+
+/// Where the dead stores of `module` are.
+pub fn dead_stores(module: &Module) -> HashSet<Span> {
+    // Names functions read from their enclosing scopes or the top level.
+    let mut free = BTreeSet::new();
+    for function in module.functions.iter() {
+        let locals = function.locals();
+        for instr in function.blocks.iter().flat_map(|b| b.instrs.iter()) {
+            for operand in instr.op.uses() {
+                if let Operand::Var(x) = operand {
+                    if !locals.contains(x) {
+                        free.insert(x.clone());
+                    }
+                }
+            }
+        }
+    }
+    let mut dead = HashSet::new();
+    for function in module.functions.iter() {
+        let live = solve(&Liveness, function);
+        let mut stores: Vec<(&str, Span)> = vec![];
+        for (b, block) in function.blocks.iter().enumerate() {
+            let mut fact = live.exit[b].clone();
+            for (i, instr) in block.instrs.iter().enumerate().rev() {
+                if let Op::Copy {
+                    dest: Operand::Var(x),
+                    src: Operand::Number(_) | Operand::Str(_),
+                } = &instr.op
+                {
+                    if !fact.contains(&Place::Var(x.clone())) && !free.contains(x) {
+                        stores.push((x, instr.span));
+                    }
+                }
+                Liveness.step((b, i), instr, &mut fact);
+            }
+        }
+        stores.sort_by_key(|(_, span)| span.start);
+        let instrs: Vec<&Instr> = function.blocks.iter().flat_map(|b| &b.instrs).collect();
+        for (i, (x, span)) in stores.iter().enumerate() {
+            let place = Place::Var(x.to_string());
+            let assigned = instrs.iter().filter(|s| s.op.def() == Some(place.clone()));
+            let read = instrs
+                .iter()
+                .any(|s| s.op.uses().contains(&&Operand::Var(x.to_string())));
+            let first = !stores[..i].iter().any(|(y, _)| y == x);
+            let all = assigned.count() == stores.iter().filter(|(y, _)| y == x).count();
+            if first && all && read && !function.params.iter().any(|p| p == x) {
+                continue;
+            }
+            dead.insert(*span);
+        }
+    }
+    dead
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Lowerer;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn dead_stores_of_each_function() {
+        let source = "let a = 1\nlet a = 2\nprint(a)\nfn f(n) {\n    let n = 3\n    let m = 4\n    return m\n}\nprint(f(0))";
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let module = Lowerer::new().lower(&nodes).unwrap();
+        let mut dead: Vec<(usize, usize)> = dead_stores(&module)
+            .into_iter()
+            .map(|span| (span.line, span.col))
+            .collect();
+        dead.sort();
+        assert_eq!(dead, [(1, 1), (5, 5)]);
+    }
+}
@@ -0,0 +1,854 @@
+use crate::json::quote;
+use crate::lexer::{Operator, Span, TokenType};
+use crate::parser::ParseNode;
+use std::collections::BTreeSet;
+use std::fmt;
+
+pub mod dataflow;
+pub mod ssa;
+
+/*
+Three-address code grouped into basic blocks.
+
+Every instruction has at most one operator, its operands being variables,
+temporaries or constants. Temporaries are numbered per function and are
+assigned exactly once. A block is a straight run of instructions ending in
+exactly one terminator (`jump`, `branch` or `return`), so the edges of the
+control flow graph can be read off the last instruction of each block.
+Block 0 is the entry of its function.
+
+Function definitions are instructions too: `f = @1` binds `f` to
+`functions[1]` when the definition runs, as in the interpreter. Functions are
+numbered outside in, so an enclosing function comes before the functions
+defined in it.
+
+`phi` instructions only appear once a function is converted to SSA form, see
+ssa.rs.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Temp(usize),
+    Var(String),
+    Number(f64),
+    Str(String),
+    Function(usize),
+    None,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Temp(t) => write!(f, "t{}", t),
+            Operand::Var(x) => write!(f, "{}", x),
+            Operand::Number(n) => write!(f, "{}", n),
+            Operand::Str(s) => write!(f, "{}", quote(s)),
+            Operand::Function(i) => write!(f, "@{}", i),
+            Operand::None => write!(f, "None"),
+        }
+    }
+}
+
+impl Operand {
+    pub fn place(&self) -> Option<Place> {
+        match self {
+            Operand::Temp(t) => Some(Place::Temp(*t)),
+            Operand::Var(x) => Some(Place::Var(x.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Something an instruction can assign.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Place {
+    Temp(usize),
+    Var(String),
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Place::Temp(t) => write!(f, "t{}", t),
+            Place::Var(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+/// An instruction. Destinations are always a `Temp` or a `Var`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Copy {
+        dest: Operand,
+        src: Operand,
+    },
+    Unary {
+        dest: Operand,
+        op: Operator,
+        operand: Operand,
+    },
+    Binary {
+        dest: Operand,
+        op: Operator,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Call {
+        dest: Operand,
+        callee: Operand,
+        args: Vec<Operand>,
+    },
+    /// The value `dest` had at the end of the predecessor control came from.
+    Phi {
+        dest: Operand,
+        args: Vec<(usize, Operand)>,
+    },
+    Print(Operand),
+    Jump(usize),
+    Branch {
+        cond: Operand,
+        then: usize,
+        otherwise: usize,
+    },
+    Return(Operand),
+}
+
+impl Op {
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Op::Jump(_) | Op::Branch { .. } | Op::Return(_))
+    }
+
+    /// Blocks control may continue in after this instruction, if it is a
+    /// terminator.
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Op::Jump(target) => vec![*target],
+            Op::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            _ => vec![],
+        }
+    }
+
+    /// What the instruction assigns, if anything.
+    pub fn def(&self) -> Option<Place> {
+        match self {
+            Op::Copy { dest, .. }
+            | Op::Unary { dest, .. }
+            | Op::Binary { dest, .. }
+            | Op::Call { dest, .. }
+            | Op::Phi { dest, .. } => dest.place(),
+            _ => None,
+        }
+    }
+
+    /// The operands the instruction reads, in order.
+    pub fn uses(&self) -> Vec<&Operand> {
+        match self {
+            Op::Copy { src, .. } => vec![src],
+            Op::Unary { operand, .. } => vec![operand],
+            Op::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Op::Call { callee, args, .. } => std::iter::once(callee).chain(args.iter()).collect(),
+            Op::Phi { args, .. } => args.iter().map(|(_, a)| a).collect(),
+            Op::Print(value) | Op::Return(value) => vec![value],
+            Op::Branch { cond, .. } => vec![cond],
+            Op::Jump(_) => vec![],
+        }
+    }
+
+    /// Mutable access to the operands the instruction reads.
+    pub fn uses_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Op::Copy { src, .. } => vec![src],
+            Op::Unary { operand, .. } => vec![operand],
+            Op::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Op::Call { callee, args, .. } => {
+                std::iter::once(callee).chain(args.iter_mut()).collect()
+            }
+            Op::Phi { args, .. } => args.iter_mut().map(|(_, a)| a).collect(),
+            Op::Print(value) | Op::Return(value) => vec![value],
+            Op::Branch { cond, .. } => vec![cond],
+            Op::Jump(_) => vec![],
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Op::Copy { dest, .. }
+            | Op::Unary { dest, .. }
+            | Op::Binary { dest, .. }
+            | Op::Call { dest, .. }
+            | Op::Phi { dest, .. } => Some(dest),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Copy { dest, src } => write!(f, "{} = {}", dest, src),
+            Op::Unary { dest, op, operand } => write!(f, "{} = {}{}", dest, op, operand),
+            Op::Binary { dest, op, lhs, rhs } => write!(f, "{} = {} {} {}", dest, lhs, op, rhs),
+            Op::Call { dest, callee, args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{} = call {}({})", dest, callee, args.join(", "))
+            }
+            Op::Phi { dest, args } => {
+                let args: Vec<String> =
+                    args.iter().map(|(b, a)| format!("b{}: {}", b, a)).collect();
+                write!(f, "{} = phi [{}]", dest, args.join(", "))
+            }
+            Op::Print(value) => write!(f, "print {}", value),
+            Op::Jump(target) => write!(f, "jump b{}", target),
+            Op::Branch {
+                cond,
+                then,
+                otherwise,
+            } => write!(f, "branch {} b{} b{}", cond, then, otherwise),
+            Op::Return(value) => write!(f, "return {}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instr {
+    pub op: Op,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub instrs: Vec<Instr>,
+}
+
+impl Block {
+    pub fn terminator(&self) -> Option<&Op> {
+        self.instrs
+            .last()
+            .map(|i| &i.op)
+            .filter(|op| op.is_terminator())
+    }
+
+    pub fn successors(&self) -> Vec<usize> {
+        self.terminator().map(Op::successors).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    /// The function this one is defined in, None for the top level.
+    pub parent: Option<usize>,
+    pub blocks: Vec<Block>,
+    pub temps: usize,
+}
+
+impl Function {
+    /// Predecessors of every block.
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut res = vec![vec![]; self.blocks.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for succ in block.successors() {
+                if !res[succ].contains(&b) {
+                    res[succ].push(b);
+                }
+            }
+        }
+        res
+    }
+
+    /// Variables assigned somewhere in the function, parameters included,
+    /// in order of first assignment.
+    pub fn locals(&self) -> Vec<String> {
+        let mut res = self.params.clone();
+        for instr in self.blocks.iter().flat_map(|b| b.instrs.iter()) {
+            if let Some(Place::Var(x)) = instr.op.def() {
+                if !res.contains(&x) {
+                    res.push(x);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// A lowered program. `functions[0]` holds the top level statements.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub functions: Vec<Function>,
+}
+
+fn identifier(token: &TokenType) -> Result<&str, String> {
+    match token {
+        TokenType::Identifier(x) => Ok(x),
+        _ => Err("Invalid state".to_string()),
+    }
+}
+
+fn error<T>(span: Span, message: impl fmt::Display) -> Result<T, String> {
+    Err(format!("Compile error at {}: {}", span, message))
+}
+
+struct Builder {
+    index: usize,
+    function: Function,
+    current: usize,
+    /// Blocks in the order lowering entered them, which follows the source.
+    order: Vec<usize>,
+}
+
+impl Builder {
+    fn new(index: usize, name: &str, params: Vec<String>, parent: Option<usize>) -> Self {
+        Self {
+            index,
+            function: Function {
+                name: name.to_string(),
+                params,
+                parent,
+                blocks: vec![Block::default()],
+                temps: 0,
+            },
+            current: 0,
+            order: vec![0],
+        }
+    }
+
+    fn emit(&mut self, op: Op, span: Span) {
+        self.function.blocks[self.current]
+            .instrs
+            .push(Instr { op, span });
+    }
+
+    fn temp(&mut self) -> Operand {
+        self.function.temps += 1;
+        Operand::Temp(self.function.temps - 1)
+    }
+
+    fn new_block(&mut self) -> usize {
+        self.function.blocks.push(Block::default());
+        self.function.blocks.len() - 1
+    }
+
+    /// Ends the current block and continues in `next`.
+    fn terminate(&mut self, op: Op, span: Span, next: usize) {
+        self.emit(op, span);
+        self.current = next;
+        self.order.push(next);
+    }
+
+    /// Drops the blocks that cannot be reached from the entry, such as the
+    /// one code after a `return` goes to, and renumbers the others in source
+    /// order.
+    fn finish(self) -> Function {
+        let Function {
+            name,
+            params,
+            parent,
+            mut blocks,
+            temps,
+        } = self.function;
+        let mut reachable = vec![false; blocks.len()];
+        let mut pending = vec![0];
+        while let Some(b) = pending.pop() {
+            if !reachable[b] {
+                reachable[b] = true;
+                pending.extend(blocks[b].successors());
+            }
+        }
+        let order: Vec<usize> = self.order.into_iter().filter(|b| reachable[*b]).collect();
+        let mut numbers = vec![0; blocks.len()];
+        for (number, b) in order.iter().enumerate() {
+            numbers[*b] = number;
+        }
+        let blocks = order
+            .iter()
+            .map(|b| {
+                let mut block = std::mem::take(&mut blocks[*b]);
+                if let Some(last) = block.instrs.last_mut() {
+                    match &mut last.op {
+                        Op::Jump(target) => *target = numbers[*target],
+                        Op::Branch {
+                            then, otherwise, ..
+                        } => {
+                            *then = numbers[*then];
+                            *otherwise = numbers[*otherwise];
+                        }
+                        _ => {}
+                    }
+                }
+                block
+            })
+            .collect();
+        Function {
+            name,
+            params,
+            parent,
+            blocks,
+            temps,
+        }
+    }
+}
+
+pub struct Lowerer {
+    functions: Vec<Function>,
+    builders: Vec<Builder>,
+}
+
+impl Lowerer {
+    pub fn new() -> Self {
+        Self {
+            // Reserve the slot of the top level function.
+            functions: vec![Builder::new(0, "<main>", vec![], None).function],
+            builders: vec![Builder::new(0, "<main>", vec![], None)],
+        }
+    }
+
+    pub fn lower(mut self, nodes: &[ParseNode]) -> Result<Module, String> {
+        self.block(nodes)?;
+        let span = nodes.last().map(|n| n.span).unwrap_or_default();
+        self.builder().emit(Op::Return(Operand::None), span);
+        let main = self.builders.pop().ok_or("Invalid state")?;
+        self.functions[0] = main.finish();
+        Ok(Module {
+            functions: self.functions,
+        })
+    }
+
+    fn builder(&mut self) -> &mut Builder {
+        self.builders.last_mut().expect("no function being lowered")
+    }
+
+    fn block(&mut self, nodes: &[ParseNode]) -> Result<(), String> {
+        for node in nodes.iter() {
+            self.statement(node)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, node: &ParseNode) -> Result<(), String> {
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let value = self.expression(node.children.get(1).ok_or("Invalid state")?)?;
+                let dest = Operand::Var(id.to_string());
+                // Let the instruction computing a temporary assign the
+                // variable directly instead of copying the temporary.
+                let builder = self.builder();
+                let last = builder.function.blocks[builder.current].instrs.last_mut();
+                match (last.map(|i| &mut i.op), &value) {
+                    (
+                        Some(
+                            Op::Unary { dest: d, .. }
+                            | Op::Binary { dest: d, .. }
+                            | Op::Call { dest: d, .. },
+                        ),
+                        Operand::Temp(_),
+                    ) if *d == value => {
+                        *d = dest;
+                        builder.function.temps -= 1;
+                    }
+                    _ => builder.emit(Op::Copy { dest, src: value }, node.span),
+                }
+                Ok(())
+            }
+            TokenType::Print => {
+                let value = self.expression(node.children.first().ok_or("Invalid state")?)?;
+                self.builder().emit(Op::Print(value), node.span);
+                Ok(())
+            }
+            TokenType::While => {
+                let builder = self.builder();
+                let head = builder.new_block();
+                let body = builder.new_block();
+                let exit = builder.new_block();
+                builder.terminate(Op::Jump(head), node.span, head);
+                let cond = self.expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                let branch = Op::Branch {
+                    cond,
+                    then: body,
+                    otherwise: exit,
+                };
+                self.builder().terminate(branch, node.span, body);
+                self.block(&node.children)?;
+                self.builder().terminate(Op::Jump(head), node.span, exit);
+                Ok(())
+            }
+            TokenType::If => {
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                let join = self.builder().new_block();
+                let arms = std::iter::once((node.extra_info.as_deref(), body, node.span)).chain(
+                    branches
+                        .iter()
+                        .map(|b| (b.extra_info.as_deref(), b.children.as_slice(), b.span)),
+                );
+                for (cond, body, span) in arms {
+                    match cond {
+                        Some(cond) => {
+                            let cond = self.expression(cond)?;
+                            let builder = self.builder();
+                            let then = builder.new_block();
+                            let otherwise = builder.new_block();
+                            let branch = Op::Branch {
+                                cond,
+                                then,
+                                otherwise,
+                            };
+                            builder.terminate(branch, span, then);
+                            self.block(body)?;
+                            self.builder().terminate(Op::Jump(join), span, otherwise);
+                        }
+                        None => self.block(body)?,
+                    }
+                }
+                self.builder().terminate(Op::Jump(join), node.span, join);
+                Ok(())
+            }
+            TokenType::Fn(Some(info)) => {
+                let name = identifier(&info.name)?;
+                let idx = self.function(name, node)?;
+                let dest = Operand::Var(name.to_string());
+                let src = Operand::Function(idx);
+                self.builder().emit(Op::Copy { dest, src }, node.span);
+                Ok(())
+            }
+            TokenType::Call(_) => {
+                self.expression(node)?;
+                Ok(())
+            }
+            TokenType::Return => {
+                if self.builders.len() == 1 {
+                    return error(node.span, "'return' outside function");
+                }
+                let value = match node.children.first() {
+                    Some(value) => self.expression(value)?,
+                    None => Operand::None,
+                };
+                // Anything after the return goes to a block nothing jumps to.
+                let builder = self.builder();
+                let dead = builder.new_block();
+                builder.terminate(Op::Return(value), node.span, dead);
+                Ok(())
+            }
+            t => error(node.span, format!("{:?} is not a statement", t)),
+        }
+    }
+
+    fn function(&mut self, name: &str, node: &ParseNode) -> Result<usize, String> {
+        let params = node
+            .extra_info
+            .as_ref()
+            .ok_or("Invalid state")?
+            .children
+            .iter()
+            .map(|p| identifier(&p.token).map(|x| x.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let index = self.functions.len();
+        let parent = self.builder().index;
+        let builder = Builder::new(index, name, params, Some(parent));
+        // Reserve the slot so functions defined inside come after this one.
+        self.functions.push(builder.function.clone());
+        self.builders.push(builder);
+        let res = self.block(&node.children);
+        let end = node.children.last().map(|n| n.span).unwrap_or(node.span);
+        self.builder().emit(Op::Return(Operand::None), end);
+        let builder = self.builders.pop().ok_or("Invalid state")?;
+        res?;
+        self.functions[index] = builder.finish();
+        Ok(index)
+    }
+
+    fn expression(&mut self, node: &ParseNode) -> Result<Operand, String> {
+        match &node.token {
+            TokenType::Number(x) => Ok(Operand::Number(x.into_inner() as f64)),
+            TokenType::StringLiteral(x) => Ok(Operand::Str(x.clone())),
+            TokenType::Identifier(x) => Ok(Operand::Var(x.clone())),
+            TokenType::Operator(op) => {
+                let op = op.clone();
+                let op = match node.children.as_slice() {
+                    [operand] => {
+                        let operand = self.expression(operand)?;
+                        let dest = self.builder().temp();
+                        Op::Unary { dest, op, operand }
+                    }
+                    [lhs, rhs] => {
+                        let lhs = self.expression(lhs)?;
+                        let rhs = self.expression(rhs)?;
+                        let dest = self.builder().temp();
+                        Op::Binary { dest, op, lhs, rhs }
+                    }
+                    _ => return error(node.span, format!("invalid operator {}", op)),
+                };
+                Ok(self.result(op, node.span))
+            }
+            TokenType::Call(info) => {
+                let callee = Operand::Var(identifier(&info.name)?.to_string());
+                let args = node
+                    .children
+                    .iter()
+                    .map(|arg| self.expression(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let dest = self.builder().temp();
+                Ok(self.result(Op::Call { dest, callee, args }, node.span))
+            }
+            t => error(node.span, format!("{:?} is not an expression", t)),
+        }
+    }
+
+    /// Emits an instruction computing a temporary and returns the temporary.
+    fn result(&mut self, op: Op, span: Span) -> Operand {
+        let dest = match &op {
+            Op::Unary { dest, .. } | Op::Binary { dest, .. } | Op::Call { dest, .. } => {
+                dest.clone()
+            }
+            _ => Operand::None,
+        };
+        self.builder().emit(op, span);
+        dest
+    }
+}
+
+fn places(places: &BTreeSet<Place>) -> String {
+    if places.is_empty() {
+        return "-".to_string();
+    }
+    let places: Vec<String> = places.iter().map(|p| p.to_string()).collect();
+    places.join(", ")
+}
+
+impl Module {
+    /// Listing of every function, one block after the other, with the
+    /// source location of each instruction. With `live`, every block also
+    /// lists what is live when it starts and when it ends.
+    pub fn listing(&self, live: bool) -> String {
+        let mut res = String::new();
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                res.push('\n');
+            }
+            res.push_str(&format!(
+                "fn @{} {}({}):\n",
+                i,
+                function.name,
+                function.params.join(", ")
+            ));
+            let liveness = live.then(|| dataflow::solve(&dataflow::Liveness, function));
+            for (b, block) in function.blocks.iter().enumerate() {
+                match &liveness {
+                    Some(l) => res.push_str(&format!(
+                        "{:<36} ; live in: {}\n",
+                        format!("  b{}:", b),
+                        places(&l.entry[b])
+                    )),
+                    None => res.push_str(&format!("  b{}:\n", b)),
+                }
+                for instr in block.instrs.iter() {
+                    res.push_str(&format!(
+                        "    {:<32} ; {}\n",
+                        instr.op.to_string(),
+                        instr.span
+                    ));
+                }
+                if let Some(l) = &liveness {
+                    res.push_str(&format!("{:<36} ; live out: {}\n", "", places(&l.exit[b])));
+                }
+            }
+        }
+        res
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.listing(false))
+    }
+}
+
+/// Escapes text for a Graphviz label, keeping the `\l` line breaks.
+fn dot_label(lines: &[String]) -> String {
+    let mut res = String::new();
+    for line in lines.iter() {
+        for c in line.chars() {
+            match c {
+                '"' | '\\' => {
+                    res.push('\\');
+                    res.push(c);
+                }
+                c => res.push(c),
+            }
+        }
+        res.push_str("\\l");
+    }
+    res
+}
+
+impl Module {
+    /// The control flow graph of every function as a Graphviz digraph, one
+    /// cluster per function.
+    pub fn dot(&self) -> String {
+        let mut res = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (i, function) in self.functions.iter().enumerate() {
+            res.push_str(&format!("    subgraph cluster_{} {{\n", i));
+            let title = format!("@{} {}({})", i, function.name, function.params.join(", "));
+            res.push_str(&format!("        label={};\n", quote(&title)));
+            for (b, block) in function.blocks.iter().enumerate() {
+                let mut lines = vec![format!("b{}:", b)];
+                lines.extend(block.instrs.iter().map(|instr| instr.op.to_string()));
+                res.push_str(&format!(
+                    "        f{}_b{} [label=\"{}\"];\n",
+                    i,
+                    b,
+                    dot_label(&lines)
+                ));
+            }
+            for (b, block) in function.blocks.iter().enumerate() {
+                match block.terminator() {
+                    Some(Op::Branch {
+                        then, otherwise, ..
+                    }) => {
+                        res.push_str(&format!(
+                            "        f{}_b{} -> f{}_b{} [label=\"true\"];\n",
+                            i, b, i, then
+                        ));
+                        res.push_str(&format!(
+                            "        f{}_b{} -> f{}_b{} [label=\"false\"];\n",
+                            i, b, i, otherwise
+                        ));
+                    }
+                    Some(Op::Jump(target)) => {
+                        res.push_str(&format!("        f{}_b{} -> f{}_b{};\n", i, b, i, target));
+                    }
+                    _ => {}
+                }
+            }
+            res.push_str("    }\n");
+        }
+        res.push_str("}\n");
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn listing(source: &str) -> String {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        Lowerer::new().lower(&nodes).unwrap().to_string()
+    }
+
+    #[test]
+    fn if_elif_else() {
+        let source = "let x = 1\nif (x < 0) {\n    print(\"neg\")\n} elif (x == 0) {\n    print(\"zero\")\n} else {\n    print(\"pos\")\n}\nprint(x)\n";
+        assert_eq!(
+            listing(source),
+            "\
+fn @0 <main>():
+  b0:
+    x = 1                            ; 1:1
+    t0 = x < 0                       ; 2:7
+    branch t0 b1 b2                  ; 2:1
+  b1:
+    print \"neg\"                      ; 3:5
+    jump b5                          ; 2:1
+  b2:
+    t1 = x == 0                      ; 4:11
+    branch t1 b3 b4                  ; 4:3
+  b3:
+    print \"zero\"                     ; 5:5
+    jump b5                          ; 4:3
+  b4:
+    print \"pos\"                      ; 7:5
+    jump b5                          ; 2:1
+  b5:
+    print x                          ; 9:1
+    return None                      ; 9:1
+"
+        );
+    }
+
+    #[test]
+    fn while_loop() {
+        let source = "let i = 0\nwhile (i < 3) {\n    let i = i + 1\n}\nprint(i)\n";
+        assert_eq!(
+            listing(source),
+            "\
+fn @0 <main>():
+  b0:
+    i = 0                            ; 1:1
+    jump b1                          ; 2:1
+  b1:
+    t0 = i < 3                       ; 2:10
+    branch t0 b2 b3                  ; 2:1
+  b2:
+    i = i + 1                        ; 3:15
+    jump b1                          ; 2:1
+  b3:
+    print i                          ; 5:1
+    return None                      ; 5:1
+"
+        );
+    }
+
+    #[test]
+    fn early_return() {
+        // Nothing is lowered after a `return`.
+        let source = "fn f(n) {\n    if (n) {\n        return 1\n        print(n)\n    }\n    print(n)\n    return\n    print(2)\n}\nf(0)\n";
+        assert_eq!(
+            listing(source),
+            "\
+fn @0 <main>():
+  b0:
+    f = @1                           ; 1:1
+    t0 = call f(0)                   ; 10:1
+    return None                      ; 10:1
+
+fn @1 f(n):
+  b0:
+    branch n b1 b2                   ; 2:5
+  b1:
+    return 1                         ; 3:9
+  b2:
+    jump b3                          ; 2:5
+  b3:
+    print n                          ; 6:5
+    return None                      ; 7:5
+"
+        );
+    }
+
+    #[test]
+    fn nested_functions() {
+        let source = "fn outer(a) {\n    fn inner(b) {\n        return a + b\n    }\n    return inner(2)\n}\nprint(outer(1))\n";
+        assert_eq!(
+            listing(source),
+            "\
+fn @0 <main>():
+  b0:
+    outer = @1                       ; 1:1
+    t0 = call outer(1)               ; 7:7
+    print t0                         ; 7:1
+    return None                      ; 7:1
+
+fn @1 outer(a):
+  b0:
+    inner = @2                       ; 2:5
+    t0 = call inner(2)               ; 5:12
+    return t0                        ; 5:5
+
+fn @2 inner(b):
+  b0:
+    t0 = a + b                       ; 3:18
+    return t0                        ; 3:9
+"
+        );
+    }
+}
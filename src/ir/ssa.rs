@@ -0,0 +1,320 @@
+use super::dataflow::{solve, Liveness};
+use super::{Function, Instr, Module, Op, Operand, Place};
+use std::collections::BTreeSet;
+
+/*
+Conversion to static single assignment form.
+
+Every assignment to a variable gets a version of its own, written `x.1`,
+`x.2` and so on, and `x.0` stands for the value on entry: the argument for
+a parameter, and whatever an enclosing scope holds otherwise. Where
+different versions meet, a `phi` at the start of the block picks the one
+of the predecessor control came from.
+
+Phis go on the dominance frontier of the assignments (Cytron et al.), with
+dominators computed as in "A Simple, Fast Dominance Algorithm" by Cooper,
+Harvey and Kennedy. A phi is only placed where the variable is live, so
+the result is pruned SSA. Names the function never assigns, such as
+globals read by a function, are left as they are.
+*/
+
+impl Module {
+    pub fn to_ssa(&self) -> Module {
+        Module {
+            functions: self.functions.iter().map(to_ssa).collect(),
+        }
+    }
+}
+
+/// Blocks in reverse postorder from the entry.
+fn reverse_postorder(function: &Function) -> Vec<usize> {
+    fn visit(function: &Function, b: usize, seen: &mut Vec<bool>, out: &mut Vec<usize>) {
+        seen[b] = true;
+        for s in function.blocks[b].successors() {
+            if !seen[s] {
+                visit(function, s, seen, out);
+            }
+        }
+        out.push(b);
+    }
+    let mut out = vec![];
+    visit(
+        function,
+        0,
+        &mut vec![false; function.blocks.len()],
+        &mut out,
+    );
+    out.reverse();
+    out
+}
+
+/// The immediate dominator of every block, the entry being its own.
+pub fn dominators(function: &Function) -> Vec<usize> {
+    let order = reverse_postorder(function);
+    let mut rank = vec![usize::MAX; function.blocks.len()];
+    for (i, b) in order.iter().enumerate() {
+        rank[*b] = i;
+    }
+    let predecessors = function.predecessors();
+    let mut idom: Vec<Option<usize>> = vec![None; function.blocks.len()];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in order.iter().skip(1) {
+            let mut new: Option<usize> = None;
+            for p in predecessors[*b].iter().filter(|p| idom[**p].is_some()) {
+                new = Some(match new {
+                    None => *p,
+                    Some(other) => {
+                        // Walk up from both until the paths meet.
+                        let (mut x, mut y) = (other, *p);
+                        while x != y {
+                            while rank[x] > rank[y] {
+                                x = idom[x].unwrap_or(0);
+                            }
+                            while rank[y] > rank[x] {
+                                y = idom[y].unwrap_or(0);
+                            }
+                        }
+                        x
+                    }
+                });
+            }
+            if new.is_some() && idom[*b] != new {
+                idom[*b] = new;
+                changed = true;
+            }
+        }
+    }
+    idom.into_iter().map(|d| d.unwrap_or(0)).collect()
+}
+
+/// The blocks where the dominance of each block ends.
+fn dominance_frontiers(function: &Function, idom: &[usize]) -> Vec<BTreeSet<usize>> {
+    let mut frontiers = vec![BTreeSet::new(); function.blocks.len()];
+    for (b, predecessors) in function.predecessors().iter().enumerate() {
+        if predecessors.len() < 2 {
+            continue;
+        }
+        for p in predecessors.iter() {
+            let mut runner = *p;
+            while runner != idom[b] {
+                frontiers[runner].insert(b);
+                if runner == idom[runner] {
+                    break;
+                }
+                runner = idom[runner];
+            }
+        }
+    }
+    frontiers
+}
+
+pub fn to_ssa(function: &Function) -> Function {
+    let idom = dominators(function);
+    let frontiers = dominance_frontiers(function, &idom);
+    let live = solve(&Liveness, function);
+    let predecessors = function.predecessors();
+    let locals = function.locals();
+    let mut function = function.clone();
+
+    // Which variable each phi of a block is for, in order.
+    let mut phis: Vec<Vec<String>> = vec![vec![]; function.blocks.len()];
+    for x in locals.iter() {
+        let place = Place::Var(x.clone());
+        let mut pending: Vec<usize> = function
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| {
+                block
+                    .instrs
+                    .iter()
+                    .any(|i| i.op.def() == Some(place.clone()))
+            })
+            .map(|(b, _)| b)
+            .collect();
+        let mut defined: BTreeSet<usize> = pending.iter().copied().collect();
+        while let Some(b) = pending.pop() {
+            for d in frontiers[b].iter() {
+                if phis[*d].contains(x) || !live.entry[*d].contains(&place) {
+                    continue;
+                }
+                phis[*d].push(x.clone());
+                if defined.insert(*d) {
+                    pending.push(*d);
+                }
+            }
+        }
+    }
+    for (b, vars) in phis.iter().enumerate() {
+        let block = &mut function.blocks[b];
+        let span = block.instrs.first().map(|i| i.span).unwrap_or_default();
+        let new = vars.iter().map(|x| Instr {
+            op: Op::Phi {
+                dest: Operand::Var(x.clone()),
+                args: predecessors[b]
+                    .iter()
+                    .map(|p| (*p, Operand::Var(x.clone())))
+                    .collect(),
+            },
+            span,
+        });
+        block.instrs.splice(0..0, new);
+    }
+
+    let mut children = vec![vec![]; function.blocks.len()];
+    for (b, d) in idom.iter().enumerate().skip(1) {
+        children[*d].push(b);
+    }
+    let mut renamer = Renamer {
+        versions: vec![0; locals.len()],
+        stacks: vec![vec![0]; locals.len()],
+        locals,
+        phis,
+        children,
+    };
+    renamer.rename(&mut function, 0);
+    function
+}
+
+struct Renamer {
+    locals: Vec<String>,
+    /// The last version handed out for each local.
+    versions: Vec<usize>,
+    /// The versions in scope for each local, innermost last.
+    stacks: Vec<Vec<usize>>,
+    phis: Vec<Vec<String>>,
+    children: Vec<Vec<usize>>,
+}
+
+impl Renamer {
+    fn current(&self, x: &str) -> Option<String> {
+        let i = self.locals.iter().position(|l| l == x)?;
+        Some(format!(
+            "{}.{}",
+            x,
+            self.stacks[i].last().copied().unwrap_or(0)
+        ))
+    }
+
+    fn rename(&mut self, function: &mut Function, b: usize) {
+        let mut pushed = vec![];
+        for instr in function.blocks[b].instrs.iter_mut() {
+            if !matches!(instr.op, Op::Phi { .. }) {
+                for operand in instr.op.uses_mut() {
+                    if let Operand::Var(x) = operand {
+                        if let Some(current) = self.current(x) {
+                            *x = current;
+                        }
+                    }
+                }
+            }
+            if let Some(Operand::Var(x)) = instr.op.dest_mut() {
+                if let Some(i) = self.locals.iter().position(|l| l == x) {
+                    self.versions[i] += 1;
+                    self.stacks[i].push(self.versions[i]);
+                    pushed.push(i);
+                    *x = format!("{}.{}", x, self.versions[i]);
+                }
+            }
+        }
+        for s in function.blocks[b].successors() {
+            for (k, x) in self.phis[s].clone().iter().enumerate() {
+                let current = self.current(x);
+                if let Op::Phi { args, .. } = &mut function.blocks[s].instrs[k].op {
+                    for (p, arg) in args.iter_mut() {
+                        if *p == b {
+                            *arg = Operand::Var(current.clone().unwrap_or_default());
+                        }
+                    }
+                }
+            }
+        }
+        for c in self.children[b].clone() {
+            self.rename(function, c);
+        }
+        for i in pushed {
+            self.stacks[i].pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Lowerer;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// The only function defined by `source`.
+    fn lower(source: &str) -> Function {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let module = Lowerer::new().lower(&nodes).unwrap();
+        module.functions[1].clone()
+    }
+
+    /// The instructions of every block, without their locations.
+    fn listing(function: &Function) -> Vec<Vec<String>> {
+        function
+            .blocks
+            .iter()
+            .map(|b| b.instrs.iter().map(|i| i.op.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn while_loop() {
+        let function = lower(
+            "fn f(n) { let i = 0 let s = 0 while (i < n) { let s = s + i let i = i + 1 } return s }",
+        );
+        let idom = dominators(&function);
+        assert_eq!(idom, [0, 0, 1, 1]);
+        let frontiers = dominance_frontiers(&function, &idom);
+        let frontiers: Vec<Vec<usize>> = frontiers
+            .iter()
+            .map(|f| f.iter().copied().collect())
+            .collect();
+        assert_eq!(frontiers, [vec![], vec![1], vec![1], vec![]]);
+        assert_eq!(
+            listing(&to_ssa(&function)),
+            [
+                vec!["i.1 = 0", "s.1 = 0", "jump b1"],
+                vec![
+                    "i.2 = phi [b0: i.1, b2: i.3]",
+                    "s.2 = phi [b0: s.1, b2: s.3]",
+                    "t0 = i.2 < n.0",
+                    "branch t0 b2 b3"
+                ],
+                vec!["s.3 = s.2 + i.2", "i.3 = i.2 + 1", "jump b1"],
+                vec!["return s.2"],
+            ]
+        );
+    }
+
+    #[test]
+    fn if_without_else() {
+        // `z` is not live where the branches join, so it gets no phi.
+        let function = lower(
+            "fn g(x) { let y = 1 let z = 1 if (x) { let y = 2 let z = 2 print(z) } return y }",
+        );
+        let idom = dominators(&function);
+        assert_eq!(idom, [0, 0, 0, 0]);
+        let frontiers = dominance_frontiers(&function, &idom);
+        let frontiers: Vec<Vec<usize>> = frontiers
+            .iter()
+            .map(|f| f.iter().copied().collect())
+            .collect();
+        assert_eq!(frontiers, [vec![], vec![3], vec![3], vec![]]);
+        assert_eq!(
+            listing(&to_ssa(&function)),
+            [
+                vec!["y.1 = 1", "z.1 = 1", "branch x.0 b1 b2"],
+                vec!["y.2 = 2", "z.2 = 2", "print z.2", "jump b3"],
+                vec!["jump b3"],
+                vec!["y.3 = phi [b1: y.2, b2: y.1]", "return y.3"],
+            ]
+        );
+    }
+}
@@ -1,195 +1,275 @@
-use std::fs;
 use std::fmt;
+use std::fs;
 use std::iter::Peekable;
 use std::vec::IntoIter;
 
 #[derive(Debug)]
 pub struct Lexer {
     raw_data: Peekable<IntoIter<char>>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    /// Problems found while lexing. The offending input is skipped.
+    pub errors: Vec<(Span, String)>,
+    /// Spans of `//` comments, up to the end of their line.
+    pub comments: Vec<Span>,
 }
 
+/// Words the lexer reads as keywords rather than identifiers.
+pub const KEYWORDS: [(&str, TokenType); 8] = [
+    ("print", TokenType::Print),
+    ("let", TokenType::Let),
+    ("if", TokenType::If),
+    ("elif", TokenType::Elif),
+    ("while", TokenType::While),
+    ("else", TokenType::Else),
+    ("fn", TokenType::Fn(None)),
+    ("return", TokenType::Return),
+];
+
 impl Lexer {
     pub fn from_file(filename: &str) -> Self {
+        Lexer::new(&fs::read_to_string(filename).expect("Something went wrong"))
+    }
+
+    pub fn new(source: &str) -> Self {
         Lexer {
-            raw_data: (fs::read_to_string(filename).expect("Something went wrong"))
-                .chars()
-                .collect::<Vec<_>>()
-                .into_iter()
-                .peekable(),
+            raw_data: source.chars().collect::<Vec<_>>().into_iter().peekable(),
+            pos: 0,
+            line: 1,
+            col: 1,
+            errors: vec![],
+            comments: vec![],
+        }
+    }
+
+    /// A lexer for `rest`, the end of a source whose first part was lexed
+    /// up to `at`.
+    pub fn resume(rest: Vec<char>, at: Span) -> Self {
+        Lexer {
+            raw_data: rest.into_iter().peekable(),
+            pos: at.start,
+            line: at.line,
+            col: at.col,
+            errors: vec![],
+            comments: vec![],
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.raw_data.next()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
         }
+        Some(c)
+    }
+
+    /// The position the lexer is at, as an empty span.
+    pub fn mark(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    fn token(&self, token: TokenType, start: Span) -> Token {
+        Token::new(
+            token,
+            Span {
+                end: self.pos,
+                ..start
+            },
+        )
     }
 
     pub fn lex(&mut self) -> Vec<Token> {
         let mut res = vec![];
-        let mut line_no = 1;
-        while let Some(c) = self.raw_data.next() {
-            match c {
-                '\n' => {
-                    line_no += 1;
-                }
-                ' ' | '\t' => {}
-                '+' => res.push(Token::new(TokenType::Operator(Operator::Plus), line_no)),
-                '-' => res.push(Token::new(TokenType::Operator(Operator::Minus), line_no)),
-                '(' => res.push(Token::new(TokenType::LeftParen, line_no)),
-                ')' => res.push(Token::new(TokenType::RightParen, line_no)),
-                '{' => res.push(Token::new(TokenType::LeftCurly, line_no)),
-                '}' => res.push(Token::new(TokenType::RightCurly, line_no)),
-                '/' => {
-                    if let Some(x) = self.raw_data.peek() {
-                        if *x == '/' {
-                            self.raw_data.next();
-                            while let Some(x) = self.raw_data.next() {
-                                if x == '\n' {
-                                    break;
-                                }
-                            }
-                        } else {
-                            res.push(Token::new(TokenType::Operator(Operator::Divide), line_no))
+        while self.step(&mut res) {}
+        res
+    }
+
+    /// Lexes the next character, and the rest of the token it starts if any.
+    /// Returns false once there is nothing left to lex.
+    pub fn step(&mut self, res: &mut Vec<Token>) -> bool {
+        let start = self.mark();
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return false,
+        };
+        match c {
+            '\n' | ' ' | '\t' => {}
+            '+' => res.push(self.token(TokenType::Operator(Operator::Plus), start)),
+            '-' => res.push(self.token(TokenType::Operator(Operator::Minus), start)),
+            '(' => res.push(self.token(TokenType::LeftParen, start)),
+            ')' => res.push(self.token(TokenType::RightParen, start)),
+            '{' => res.push(self.token(TokenType::LeftCurly, start)),
+            '}' => res.push(self.token(TokenType::RightCurly, start)),
+            '/' => {
+                if let Some(x) = self.raw_data.peek() {
+                    if *x == '/' {
+                        self.bump();
+                        while self.raw_data.peek().is_some_and(|x| *x != '\n') {
+                            self.bump();
                         }
+                        self.comments.push(Span {
+                            end: self.pos,
+                            ..start
+                        });
+                    } else {
+                        res.push(self.token(TokenType::Operator(Operator::Divide), start))
                     }
                 }
-                '*' => res.push(Token::new(TokenType::Operator(Operator::Multiply), line_no)),
-                '>' => {
-                    let is_done = if let Some(x) = self.raw_data.peek() {
-                        if *x == '=' {
-                            res.push(Token::new(
-                                TokenType::Operator(Operator::GreaterThanEqual),
-                                line_no,
-                            ));
-                            self.raw_data.next();
-                            true
-                        } else {
-                            false
-                        }
+            }
+            '*' => res.push(self.token(TokenType::Operator(Operator::Multiply), start)),
+            '>' => {
+                let is_done = if let Some(x) = self.raw_data.peek() {
+                    if *x == '=' {
+                        self.bump();
+                        res.push(
+                            self.token(TokenType::Operator(Operator::GreaterThanEqual), start),
+                        );
+                        true
                     } else {
                         false
-                    };
-                    if !is_done {
-                        res.push(Token::new(
-                            TokenType::Operator(Operator::GreaterThan),
-                            line_no,
-                        ));
                     }
+                } else {
+                    false
+                };
+                if !is_done {
+                    res.push(self.token(TokenType::Operator(Operator::GreaterThan), start));
                 }
-                '<' => {
-                    let is_done = if let Some(x) = self.raw_data.peek() {
-                        if *x == '=' {
-                            res.push(Token::new(
-                                TokenType::Operator(Operator::LessThanEqual),
-                                line_no,
-                            ));
-                            self.raw_data.next();
-                            true
-                        } else {
-                            false
-                        }
+            }
+            '<' => {
+                let is_done = if let Some(x) = self.raw_data.peek() {
+                    if *x == '=' {
+                        self.bump();
+                        res.push(self.token(TokenType::Operator(Operator::LessThanEqual), start));
+                        true
                     } else {
                         false
-                    };
-                    if !is_done {
-                        res.push(Token::new(TokenType::Operator(Operator::LessThan), line_no));
                     }
+                } else {
+                    false
+                };
+                if !is_done {
+                    res.push(self.token(TokenType::Operator(Operator::LessThan), start));
                 }
-                ';' => res.push(Token::new(TokenType::SemiColon, line_no)),
-                '=' => {
-                    let is_done = if let Some(x) = self.raw_data.peek() {
-                        if *x == '=' {
-                            res.push(Token::new(TokenType::Operator(Operator::Equality), line_no));
-                            self.raw_data.next();
-                            true
-                        } else {
-                            false
-                        }
+            }
+            ';' => res.push(self.token(TokenType::SemiColon, start)),
+            '=' => {
+                let is_done = if let Some(x) = self.raw_data.peek() {
+                    if *x == '=' {
+                        self.bump();
+                        res.push(self.token(TokenType::Operator(Operator::Equality), start));
+                        true
                     } else {
                         false
-                    };
-                    if !is_done {
-                        res.push(Token::new(TokenType::Operator(Operator::Equal), line_no));
                     }
+                } else {
+                    false
+                };
+                if !is_done {
+                    res.push(self.token(TokenType::Operator(Operator::Equal), start));
                 }
-                n @ '0'..='9' => {
-                    let mut num = String::from(n);
-                    while let Some(c) = self.raw_data.peek() {
-                        if !c.is_digit(10) {
-                            break;
-                        }
-                        num.push(*c);
-                        self.raw_data.next();
+            }
+            n @ '0'..='9' => {
+                let mut num = String::from(n);
+                while let Some(c) = self.raw_data.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
                     }
-                    res.push(Token::new(
-                        TokenType::Number(ordered_float::OrderedFloat(num.parse::<f32>().unwrap())),
-                        line_no,
-                    ))
+                    num.push(*c);
+                    self.bump();
                 }
-                ',' => {}
-                '"' => {
-                    let mut done = false;
-                    let mut str = String::new();
-                    let mut cur = 'a';
-                    while let Some(c) = self.raw_data.next() {
-                        match c {
-                            '"' => {
-                                if cur == '\\' {
-                                    str.push('"');
-                                    cur = 'a';
-                                } else {
-                                    done = true;
-                                    break;
-                                }
+                res.push(self.token(
+                    TokenType::Number(ordered_float::OrderedFloat(num.parse::<f32>().unwrap())),
+                    start,
+                ))
+            }
+            ',' => {}
+            '"' => {
+                let mut done = false;
+                let mut str = String::new();
+                let mut cur = 'a';
+                while let Some(c) = self.bump() {
+                    match c {
+                        '"' => {
+                            if cur == '\\' {
+                                str.push('"');
+                                cur = 'a';
+                            } else {
+                                done = true;
+                                break;
                             }
-                            '\\' => {
-                                if cur == '\\' {
-                                    str.push('\\');
-                                    cur = 'a';
-                                } else {
-                                    cur = '\\';
-                                }
+                        }
+                        '\\' => {
+                            if cur == '\\' {
+                                str.push('\\');
+                                cur = 'a';
+                            } else {
+                                cur = '\\';
                             }
-                            x => {
-                                if cur == '\\' {
-                                    str.push('\\');
-                                }
-                                str.push(x);
-                                cur = x;
+                        }
+                        x => {
+                            if cur == '\\' {
+                                str.push('\\');
                             }
+                            str.push(x);
+                            cur = x;
                         }
                     }
-                    if !done {
-                        println!("Error");
-                        break;
-                    }
-                    res.push(Token::new(TokenType::StringLiteral(str), line_no))
                 }
-                x => {
-                    if !x.is_alphanumeric() {
-                        println!("Invalid token: {}", x);
-                        continue;
-                    }
-                    let mut str = String::from(x);
-                    while let Some(c) = self.raw_data.peek() {
-                        if !(c.is_alphanumeric() || *c == '_') {
-                            break;
-                        }
-                        str.push(*c);
-                        self.raw_data.next();
+                if !done {
+                    self.errors
+                        .push((start, "Unterminated string literal".to_string()));
+                    return false;
+                }
+                res.push(self.token(TokenType::StringLiteral(str), start))
+            }
+            x => {
+                if !x.is_alphanumeric() {
+                    self.errors.push((start, format!("Invalid token: {}", x)));
+                    return true;
+                }
+                let mut str = String::from(x);
+                while let Some(c) = self.raw_data.peek() {
+                    if !(c.is_alphanumeric() || *c == '_') {
+                        break;
                     }
-                    res.push(match str.as_str() {
-                        "print" => Token::new(TokenType::Print, line_no),
-                        "let" => Token::new(TokenType::Let, line_no),
-                        "if" => Token::new(TokenType::If, line_no),
-                        "elif" => Token::new(TokenType::Elif, line_no),
-                        "while" => Token::new(TokenType::While, line_no),
-                        "else" => Token::new(TokenType::Else, line_no),
-                        "fn" => Token::new(TokenType::Fn(None), line_no),
-                        _ => Token::new(TokenType::Identifier(str), line_no),
-                    });
+                    str.push(*c);
+                    self.bump();
                 }
+                let token = match KEYWORDS.iter().find(|(word, _)| *word == str) {
+                    Some((_, token)) => token.clone(),
+                    None => TokenType::Identifier(str),
+                };
+                res.push(self.token(token, start));
             }
         }
-        res
+        true
     }
 }
 
+/// Every operator, for listing them outside the lexer.
+pub const OPERATORS: [Operator; 10] = [
+    Operator::Plus,
+    Operator::Minus,
+    Operator::Multiply,
+    Operator::Divide,
+    Operator::Equal,
+    Operator::LessThan,
+    Operator::LessThanEqual,
+    Operator::GreaterThan,
+    Operator::GreaterThanEqual,
+    Operator::Equality,
+];
+
 #[derive(Eq, Hash, Debug, PartialEq, Clone)]
 pub enum Operator {
     Plus,
@@ -221,15 +301,31 @@ impl fmt::Display for Operator {
     }
 }
 
+/// Location of a token in the source. `start` and `end` are character
+/// offsets, `line` and `col` are 1-based and point at the first character.
+#[derive(Eq, Hash, Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 #[derive(Eq, Hash, Debug, PartialEq, Clone)]
 pub struct Token {
     pub token: TokenType,
-    pub line_no: usize,
+    pub span: Span,
 }
 
 impl Token {
-    fn new(token: TokenType, line_no: usize) -> Self {
-        Self { token, line_no }
+    fn new(token: TokenType, span: Span) -> Self {
+        Self { token, span }
     }
 }
 
@@ -251,6 +347,8 @@ pub enum TokenType {
     LeftCurly,
     RightCurly,
     Fn(Option<FnInfo>),
+    Call(FnInfo),
+    Return,
     Parameters,
 }
 
@@ -0,0 +1,542 @@
+use crate::json::Json;
+use crate::lexer::{Operator, Span, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::{always_returns, collect_bindings, split_if};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/*
+Rule based checks on the parse tree, run by `parser lint`.
+
+Every rule is enabled unless the config file turns it off:
+
+{"rules": {"shadowing": false, "empty-block": true}}
+
+The config is read from --config, or from asdf-lint.json next to the linted
+file when it exists. A single finding is silenced with a comment naming the
+rules, either at the end of the line or on the line before:
+
+// asdf-allow(unused-variable, shadowing)
+*/
+
+pub const RULES: [&str; 7] = [
+    "unused-variable",
+    "unused-parameter",
+    "shadowing",
+    "duplicate-condition",
+    "self-comparison",
+    "empty-block",
+    "missing-return",
+];
+
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "warning at {}: {} [{}]",
+            self.span, self.message, self.rule
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    disabled: HashSet<&'static str>,
+}
+
+fn rule(name: &str) -> Result<&'static str, String> {
+    RULES
+        .iter()
+        .find(|r| **r == name)
+        .copied()
+        .ok_or_else(|| format!("Unknown lint rule {}", name))
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let rules = match Json::parse(text)?.get("rules") {
+            Some(Json::Object(rules)) => rules.clone(),
+            Some(_) => return Err("\"rules\" should map rule names to true or false".to_string()),
+            None => vec![],
+        };
+        for (name, enabled) in rules {
+            let name = rule(&name)?;
+            match enabled {
+                Json::Bool(true) => config.disabled.remove(name),
+                Json::Bool(false) => config.disabled.insert(name),
+                _ => return Err(format!("Rule {} should be true or false", name)),
+            };
+        }
+        Ok(config)
+    }
+}
+
+/// The rules allowed on each line by `asdf-allow` comments.
+fn allowed(source: &str) -> HashMap<usize, Vec<String>> {
+    let mut res: HashMap<usize, Vec<String>> = HashMap::new();
+    for (i, line) in source.lines().enumerate() {
+        let rules = match line
+            .split_once("// asdf-allow(")
+            .and_then(|(_, rest)| rest.split_once(')'))
+        {
+            Some((rules, _)) => rules,
+            None => continue,
+        };
+        let rules: Vec<String> = rules.split(',').map(|r| r.trim().to_string()).collect();
+        let line_number = i + 1;
+        // A comment on a line of its own covers the line after it.
+        if line.trim_start().starts_with("//") {
+            res.entry(line_number + 1)
+                .or_default()
+                .extend(rules.iter().cloned());
+        }
+        res.entry(line_number).or_default().extend(rules);
+    }
+    res
+}
+
+pub fn lint(nodes: &[ParseNode], source: &str, config: &Config) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        diagnostics: vec![],
+    };
+    linter.scope(&[], nodes, &[]);
+    linter.block(nodes);
+    let allowed = allowed(source);
+    let mut res: Vec<Diagnostic> = linter
+        .diagnostics
+        .into_iter()
+        .filter(|d| !config.disabled.contains(d.rule))
+        .filter(|d| {
+            !allowed
+                .get(&d.span.line)
+                .is_some_and(|rules| rules.iter().any(|r| r == d.rule))
+        })
+        .collect();
+    res.sort_by_key(|d| d.span.start);
+    res
+}
+
+/// Whether two trees are the same apart from where they are in the source.
+fn same(a: &ParseNode, b: &ParseNode) -> bool {
+    a.token == b.token
+        && match (&a.extra_info, &b.extra_info) {
+            (Some(a), Some(b)) => same(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+        && a.children.len() == b.children.len()
+        && a.children
+            .iter()
+            .zip(b.children.iter())
+            .all(|(a, b)| same(a, b))
+}
+
+fn function_name(node: &ParseNode) -> Option<&str> {
+    match &node.token {
+        TokenType::Fn(Some(info)) => match &*info.name {
+            TokenType::Identifier(x) => Some(x),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn parameters(node: &ParseNode) -> &[ParseNode] {
+    node.extra_info
+        .as_deref()
+        .map(|p| p.children.as_slice())
+        .unwrap_or_default()
+}
+
+fn param_names(params: &[ParseNode]) -> Vec<String> {
+    params
+        .iter()
+        .filter_map(|p| match &p.token {
+            TokenType::Identifier(x) => Some(x.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Functions defined in `nodes`, not counting those inside them.
+fn functions<'a>(nodes: &'a [ParseNode], out: &mut Vec<&'a ParseNode>) {
+    for node in nodes.iter() {
+        match node.token {
+            TokenType::Fn(_) => out.push(node),
+            TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                functions(&node.children, out)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The first `let` of each name in `nodes`, not counting nested functions.
+fn lets<'a>(nodes: &'a [ParseNode], out: &mut Vec<(&'a str, &'a ParseNode)>) {
+    for node in nodes.iter() {
+        match &node.token {
+            TokenType::Let => {
+                if let Some(ParseNode {
+                    token: TokenType::Identifier(x),
+                    ..
+                }) = node.children.first()
+                {
+                    if !out.iter().any(|(n, _)| n == x) {
+                        out.push((x, node));
+                    }
+                }
+            }
+            TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                lets(&node.children, out)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Names read in `nodes`, including reads by nested functions of names
+/// those functions do not bind themselves.
+fn reads(nodes: &[ParseNode], out: &mut HashSet<String>) {
+    for node in nodes.iter() {
+        match &node.token {
+            TokenType::Fn(_) => {
+                let mut bound = param_names(parameters(node));
+                collect_bindings(&node.children, &mut bound);
+                let mut inner = HashSet::new();
+                reads(&node.children, &mut inner);
+                out.extend(inner.into_iter().filter(|x| !bound.contains(x)));
+                continue;
+            }
+            TokenType::Identifier(x) => {
+                out.insert(x.clone());
+            }
+            TokenType::Call(info) => {
+                if let TokenType::Identifier(x) = &*info.name {
+                    out.insert(x.clone());
+                }
+            }
+            _ => {}
+        }
+        if let Some(extra) = &node.extra_info {
+            reads(std::slice::from_ref(extra), out);
+        }
+        // The target of a let is written, not read.
+        let skip = usize::from(node.token == TokenType::Let).min(node.children.len());
+        reads(&node.children[skip..], out);
+    }
+}
+
+/// Whether `nodes` return a value anywhere, not counting nested functions.
+fn returns_value(nodes: &[ParseNode]) -> bool {
+    nodes.iter().any(|node| match node.token {
+        TokenType::Return => !node.children.is_empty(),
+        TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+            returns_value(&node.children)
+        }
+        _ => false,
+    })
+}
+
+struct Linter {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter {
+    fn report(&mut self, rule: &'static str, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            rule,
+            span,
+            message,
+        });
+    }
+
+    /// Checks the names of a function body, or of the whole program, and
+    /// then of the functions defined in it. `outer` holds the names bound by
+    /// enclosing scopes.
+    fn scope(&mut self, params: &[ParseNode], body: &[ParseNode], outer: &[String]) {
+        let mut used = HashSet::new();
+        reads(body, &mut used);
+        let mut bound = param_names(params);
+        for param in params.iter() {
+            if let TokenType::Identifier(x) = &param.token {
+                if !used.contains(x) {
+                    let message = format!("parameter '{}' is never used", x);
+                    self.report("unused-parameter", param.span, message);
+                }
+                if outer.contains(x) {
+                    let message = format!("parameter '{}' shadows an outer variable", x);
+                    self.report("shadowing", param.span, message);
+                }
+            }
+        }
+        let mut assigned = vec![];
+        lets(body, &mut assigned);
+        for (x, node) in assigned {
+            if !used.contains(x) {
+                let message = format!("variable '{}' is never read", x);
+                self.report("unused-variable", node.span, message);
+            }
+            if outer.contains(&x.to_string()) && !bound.iter().any(|b| b == x) {
+                let message = format!("variable '{}' shadows an outer variable", x);
+                self.report("shadowing", node.span, message);
+            }
+        }
+        collect_bindings(body, &mut bound);
+        let mut nested = vec![];
+        functions(body, &mut nested);
+        let outer: Vec<String> = outer.iter().chain(bound.iter()).cloned().collect();
+        for function in nested {
+            self.scope(parameters(function), &function.children, &outer);
+        }
+    }
+
+    fn block(&mut self, nodes: &[ParseNode]) {
+        for node in nodes.iter() {
+            self.statement(node);
+        }
+    }
+
+    fn statement(&mut self, node: &ParseNode) {
+        match &node.token {
+            TokenType::While if node.children.is_empty() => {
+                self.report("empty-block", node.span, "empty while loop".to_string());
+            }
+            TokenType::If => {
+                let (body, branches) = split_if(node);
+                if body.is_empty() {
+                    self.report("empty-block", node.span, "empty if block".to_string());
+                }
+                let mut conditions: Vec<&ParseNode> =
+                    node.extra_info.as_deref().into_iter().collect();
+                for branch in branches.iter() {
+                    let kind = match branch.token {
+                        TokenType::Elif => "elif",
+                        _ => "else",
+                    };
+                    if branch.children.is_empty() {
+                        self.report("empty-block", branch.span, format!("empty {} block", kind));
+                    }
+                    if let Some(cond) = branch.extra_info.as_deref() {
+                        self.expression(cond);
+                        if let Some(earlier) = conditions.iter().find(|c| same(c, cond)) {
+                            let message = format!(
+                                "condition is the same as the one at {}, this branch never runs",
+                                earlier.span
+                            );
+                            self.report("duplicate-condition", branch.span, message);
+                        }
+                        conditions.push(cond);
+                    }
+                }
+                for branch in branches.iter() {
+                    self.block(&branch.children);
+                }
+            }
+            TokenType::Fn(_) => {
+                let name = function_name(node).unwrap_or_default();
+                if node.children.is_empty() {
+                    let message = format!("function '{}' has an empty body", name);
+                    self.report("empty-block", node.span, message);
+                }
+                if returns_value(&node.children) && !always_returns(&node.children) {
+                    let message =
+                        format!("function '{}' does not return a value on every path", name);
+                    self.report("missing-return", node.span, message);
+                }
+            }
+            _ => {}
+        }
+        if let Some(extra) = &node.extra_info {
+            self.expression(extra);
+        }
+        match node.token {
+            TokenType::If => self.block(split_if(node).0),
+            TokenType::While | TokenType::Fn(_) => self.block(&node.children),
+            _ => {
+                for child in node.children.iter() {
+                    self.expression(child);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, node: &ParseNode) {
+        if let (TokenType::Operator(op), [lhs, rhs]) = (&node.token, node.children.as_slice()) {
+            let always = match op {
+                Operator::Equality | Operator::LessThanEqual | Operator::GreaterThanEqual => {
+                    Some("true")
+                }
+                Operator::LessThan | Operator::GreaterThan => Some("false"),
+                _ => None,
+            };
+            if let Some(always) = always.filter(|_| same(lhs, rhs)) {
+                let message = format!("comparing a value with itself is always {}", always);
+                self.report("self-comparison", node.span, message);
+            }
+        }
+        for child in node.children.iter() {
+            self.expression(child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn warnings(source: &str, config: &Config) -> Vec<String> {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        lint(&nodes, source, config)
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    fn default(source: &str) -> Vec<String> {
+        warnings(source, &Config::default())
+    }
+
+    #[test]
+    fn unused_variable() {
+        assert_eq!(
+            default("let a = 1\nlet b = 2\nprint(b)"),
+            ["warning at 1:1: variable 'a' is never read [unused-variable]"]
+        );
+    }
+
+    #[test]
+    fn unused_parameter() {
+        assert_eq!(
+            default("fn f(a, b) {\n    return b\n}\nprint(f(1, 2))"),
+            ["warning at 1:6: parameter 'a' is never used [unused-parameter]"]
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(
+            default(
+                "let x = 1
+fn f(x) {
+    let y = x
+    fn g() {
+        let y = 2
+        return y
+    }
+    return g() + y
+}
+print(f(x))"
+            ),
+            [
+                "warning at 2:6: parameter 'x' shadows an outer variable [shadowing]",
+                "warning at 5:9: variable 'y' shadows an outer variable [shadowing]",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_condition() {
+        assert_eq!(
+            default("let x = 1\nif (x < 2) {\n    print(1)\n} elif (x < 2) {\n    print(2)\n}"),
+            ["warning at 4:3: condition is the same as the one at 2:7, \
+              this branch never runs [duplicate-condition]"]
+        );
+    }
+
+    #[test]
+    fn self_comparison() {
+        assert_eq!(
+            default("let x = 1\nprint(x == x)\nprint(x < x)\nprint(x < 1)"),
+            [
+                "warning at 2:9: comparing a value with itself is always true [self-comparison]",
+                "warning at 3:9: comparing a value with itself is always false [self-comparison]",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_block() {
+        assert_eq!(
+            default("let x = 1\nwhile (x) {\n}\nif (x) {\n} else {\n}\nfn f() {\n}\nf()"),
+            [
+                "warning at 2:1: empty while loop [empty-block]",
+                "warning at 4:1: empty if block [empty-block]",
+                "warning at 5:3: empty else block [empty-block]",
+                "warning at 7:1: function 'f' has an empty body [empty-block]",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_return() {
+        assert_eq!(
+            default(
+                "fn f(n) {
+    if (n) {
+        return 1
+    }
+}
+fn g(n) {
+    if (n) {
+        return 1
+    } else {
+        return 2
+    }
+}
+print(f(1) + g(1))"
+            ),
+            ["warning at 1:1: function 'f' does not return a value on every path [missing-return]"]
+        );
+    }
+
+    #[test]
+    fn config_disables_rules() {
+        let source = "let a = 1\nlet b = 2\nprint(a == a)";
+        assert_eq!(default(source).len(), 2);
+        let config =
+            Config::parse(r#"{"rules": {"unused-variable": true, "self-comparison": false}}"#)
+                .unwrap();
+        assert_eq!(
+            warnings(source, &config),
+            ["warning at 2:1: variable 'b' is never read [unused-variable]"]
+        );
+    }
+
+    #[test]
+    fn config_rejects_unknown_rules() {
+        assert_eq!(
+            Config::parse(r#"{"rules": {"unused-variables": false}}"#).err(),
+            Some("Unknown lint rule unused-variables".to_string())
+        );
+        assert!(Config::parse(r#"{"rules": {"shadowing": "off"}}"#).is_err());
+        assert!(Config::parse(r#"{"rules": ["shadowing"]}"#).is_err());
+    }
+
+    #[test]
+    fn allow_comments() {
+        // On the same line, on the line before, and naming another rule.
+        let source = "let a = 1 // asdf-allow(unused-variable)
+// asdf-allow(shadowing, unused-variable)
+let b = 2
+let c = 3 // asdf-allow(shadowing)
+// asdf-allow(unused-variable)
+
+let d = 4";
+        assert_eq!(
+            default(source),
+            [
+                "warning at 4:1: variable 'c' is never read [unused-variable]",
+                "warning at 7:1: variable 'd' is never read [unused-variable]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,800 @@
+use crate::format;
+use crate::highlight::{classify, Class};
+use crate::incremental::Tree;
+use crate::json::{object, Json};
+use crate::lexer::{Operator, Span, Token, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::always_returns;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/*
+A language server speaking JSON-RPC over stdin and stdout, one message per
+Content-Length framed body. Documents are synced by edits, which are lexed
+and parsed incrementally, and then analysed whole again.
+
+Names are resolved the way the interpreter scopes them: the top level and
+each function are scopes, and a `let`, parameter or `fn` binds its name in
+the whole of its scope. Types are the set of kinds a binding may hold,
+grown until nothing changes: from the values assigned to it, the arguments
+passed to a parameter and the values a function returns.
+
+Positions on the wire count lines from 0 and columns in UTF-16 code units,
+while spans count characters, so every position goes through a LineIndex.
+*/
+
+const NUMBER: u8 = 1;
+const STRING: u8 = 2;
+const BOOL: u8 = 4;
+const FUNCTION: u8 = 8;
+const NONE: u8 = 16;
+
+fn type_name(ty: u8) -> String {
+    let names: Vec<&str> = [
+        (NUMBER, "number"),
+        (STRING, "string"),
+        (BOOL, "bool"),
+        (FUNCTION, "function"),
+        (NONE, "None"),
+    ]
+    .iter()
+    .filter(|(bit, _)| ty & bit != 0)
+    .map(|(_, name)| *name)
+    .collect();
+    match names.is_empty() {
+        true => "unknown".to_string(),
+        false => names.join(" | "),
+    }
+}
+
+/// Converts between character offsets and LSP positions.
+struct LineIndex {
+    chars: Vec<char>,
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let mut starts = vec![0];
+        starts.extend(
+            chars
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == '\n')
+                .map(|(i, _)| i + 1),
+        );
+        Self { chars, starts }
+    }
+
+    /// The 0-based line and UTF-16 column of `offset`.
+    fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.chars.len());
+        let line = self.starts.partition_point(|s| *s <= offset) - 1;
+        let character = self.chars[self.starts[line]..offset]
+            .iter()
+            .map(|c| c.len_utf16())
+            .sum();
+        (line, character)
+    }
+
+    fn position(&self, offset: usize) -> Json {
+        let (line, character) = self.line_col(offset);
+        object(vec![
+            ("line", Json::Number(line as f64)),
+            ("character", Json::Number(character as f64)),
+        ])
+    }
+
+    fn offset(&self, position: &Json) -> Option<usize> {
+        let line = position.get("line")?.as_f64()? as usize;
+        let character = position.get("character")?.as_f64()? as usize;
+        let start = *self.starts.get(line)?;
+        let mut units = 0;
+        for (i, c) in self.chars[start..].iter().enumerate() {
+            if units >= character || *c == '\n' {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Some(self.chars.len())
+    }
+
+    fn range(&self, start: usize, end: usize) -> Json {
+        object(vec![
+            ("start", self.position(start)),
+            ("end", self.position(end)),
+        ])
+    }
+}
+
+struct Binding {
+    name: String,
+    span: Span,
+    scope: usize,
+    ty: u8,
+    /// Parameters and return type, for a function.
+    function: Option<(Vec<usize>, u8)>,
+}
+
+/// Bindings of a program and what each name in it refers to.
+#[derive(Default)]
+struct Symbols {
+    parents: Vec<Option<usize>>,
+    bindings: Vec<Binding>,
+    references: Vec<(Span, usize)>,
+    /// Scope of the body, binding and name of each function, by the start
+    /// of its `fn` token.
+    functions: HashMap<usize, (usize, usize, Span)>,
+}
+
+impl Symbols {
+    fn new(nodes: &[ParseNode], tokens: &[Token]) -> Self {
+        let mut symbols = Self {
+            parents: vec![None],
+            ..Default::default()
+        };
+        symbols.declare(nodes, 0, tokens);
+        symbols.resolve(nodes, 0);
+        loop {
+            let before: Vec<(u8, Option<u8>)> = symbols
+                .bindings
+                .iter()
+                .map(|b| (b.ty, b.function.as_ref().map(|f| f.1)))
+                .collect();
+            symbols.infer(nodes, None);
+            let after: Vec<(u8, Option<u8>)> = symbols
+                .bindings
+                .iter()
+                .map(|b| (b.ty, b.function.as_ref().map(|f| f.1)))
+                .collect();
+            if before == after {
+                return symbols;
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str, scope: usize) -> Option<usize> {
+        let mut scope = Some(scope);
+        while let Some(s) = scope {
+            if let Some(b) = self
+                .bindings
+                .iter()
+                .position(|b| b.scope == s && b.name == name)
+            {
+                return Some(b);
+            }
+            scope = self.parents[s];
+        }
+        None
+    }
+
+    fn bind(&mut self, name: &str, span: Span, scope: usize, ty: u8) -> usize {
+        let b = match self
+            .bindings
+            .iter()
+            .position(|b| b.scope == scope && b.name == name)
+        {
+            Some(b) => b,
+            None => {
+                self.bindings.push(Binding {
+                    name: name.to_string(),
+                    span,
+                    scope,
+                    ty,
+                    function: None,
+                });
+                self.bindings.len() - 1
+            }
+        };
+        self.references.push((span, b));
+        b
+    }
+
+    fn declare(&mut self, nodes: &[ParseNode], scope: usize, tokens: &[Token]) {
+        for node in nodes.iter() {
+            match &node.token {
+                TokenType::Let => {
+                    if let Some(ParseNode {
+                        token: TokenType::Identifier(x),
+                        span,
+                        ..
+                    }) = node.children.first()
+                    {
+                        self.bind(x, *span, scope, 0);
+                    }
+                }
+                TokenType::Fn(Some(info)) => {
+                    let name = match &*info.name {
+                        TokenType::Identifier(x) => x,
+                        _ => continue,
+                    };
+                    // The tree keeps the span of `fn`, the name is the token after it.
+                    let span = tokens
+                        .iter()
+                        .position(|t| t.span.start == node.span.start)
+                        .and_then(|i| tokens.get(i + 1))
+                        .map(|t| t.span)
+                        .unwrap_or(node.span);
+                    let b = self.bind(name, span, scope, FUNCTION);
+                    let body = self.parents.len();
+                    self.parents.push(Some(scope));
+                    self.functions.insert(node.span.start, (body, b, span));
+                    let mut params = vec![];
+                    for param in node.extra_info.iter().flat_map(|p| p.children.iter()) {
+                        if let TokenType::Identifier(x) = &param.token {
+                            params.push(self.bind(x, param.span, body, 0));
+                        }
+                    }
+                    if self.bindings[b].function.is_none() {
+                        self.bindings[b].function = Some((params, 0));
+                    }
+                    self.declare(&node.children, body, tokens);
+                }
+                TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                    self.declare(&node.children, scope, tokens)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn resolve(&mut self, nodes: &[ParseNode], scope: usize) {
+        for node in nodes.iter() {
+            let name = match &node.token {
+                TokenType::Fn(_) => {
+                    if let Some((body, _, _)) = self.functions.get(&node.span.start) {
+                        self.resolve(&node.children, *body);
+                    }
+                    continue;
+                }
+                TokenType::Identifier(x) => Some(x),
+                TokenType::Call(info) => match &*info.name {
+                    TokenType::Identifier(x) => Some(x),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(b) = name.and_then(|x| self.lookup(x, scope)) {
+                if !self.references.iter().any(|(s, _)| *s == node.span) {
+                    self.references.push((node.span, b));
+                }
+            }
+            if let Some(extra) = &node.extra_info {
+                self.resolve(std::slice::from_ref(extra), scope);
+            }
+            self.resolve(&node.children, scope);
+        }
+    }
+
+    fn binding_at(&self, span: Span) -> Option<usize> {
+        self.references
+            .iter()
+            .find(|(s, _)| *s == span)
+            .map(|(_, b)| *b)
+    }
+
+    fn type_of(&self, node: &ParseNode) -> u8 {
+        match &node.token {
+            TokenType::Number(_) => NUMBER,
+            TokenType::StringLiteral(_) => STRING,
+            TokenType::Identifier(_) => self
+                .binding_at(node.span)
+                .map_or(0, |b| self.bindings[b].ty),
+            TokenType::Call(_) => self
+                .binding_at(node.span)
+                .and_then(|b| self.bindings[b].function.as_ref())
+                .map_or(0, |f| f.1),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [_] => NUMBER,
+                [lhs, rhs] => match op {
+                    Operator::Plus => {
+                        let (l, r) = (self.type_of(lhs), self.type_of(rhs));
+                        (l & r & STRING)
+                            | if (l | r) & STRING == 0 || l & r & NUMBER != 0 {
+                                NUMBER
+                            } else {
+                                0
+                            }
+                    }
+                    Operator::Minus | Operator::Multiply | Operator::Divide => NUMBER,
+                    _ => BOOL,
+                },
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    /// Widens the types of bindings by what `nodes` assign, pass and return.
+    fn infer(&mut self, nodes: &[ParseNode], function: Option<usize>) {
+        for node in nodes.iter() {
+            match &node.token {
+                TokenType::Fn(_) => {
+                    if let Some((_, b, _)) = self.functions.get(&node.span.start).copied() {
+                        self.infer(&node.children, Some(b));
+                        if !always_returns(&node.children) {
+                            self.returns(b, NONE);
+                        }
+                    }
+                    continue;
+                }
+                TokenType::Let => {
+                    if let [target, value] = node.children.as_slice() {
+                        let ty = self.type_of(value);
+                        if let Some(b) = self.binding_at(target.span) {
+                            self.bindings[b].ty |= ty;
+                        }
+                    }
+                }
+                TokenType::Call(_) => {
+                    let params = self
+                        .binding_at(node.span)
+                        .and_then(|b| self.bindings[b].function.as_ref())
+                        .map(|f| f.0.clone())
+                        .unwrap_or_default();
+                    for (param, arg) in params.iter().zip(node.children.iter()) {
+                        self.bindings[*param].ty |= self.type_of(arg);
+                    }
+                }
+                TokenType::Return => {
+                    let ty = node.children.first().map_or(NONE, |v| self.type_of(v));
+                    if let Some(b) = function {
+                        self.returns(b, ty);
+                    }
+                }
+                _ => {}
+            }
+            if let Some(extra) = &node.extra_info {
+                self.infer(std::slice::from_ref(extra), function);
+            }
+            self.infer(&node.children, function);
+        }
+    }
+
+    fn returns(&mut self, b: usize, ty: u8) {
+        if let Some(f) = self.bindings[b].function.as_mut() {
+            f.1 |= ty;
+        }
+    }
+
+    fn describe(&self, b: usize) -> String {
+        let binding = &self.bindings[b];
+        match &binding.function {
+            Some((params, returns)) => {
+                let params: Vec<String> = params.iter().map(|p| self.describe(*p)).collect();
+                format!(
+                    "fn {}({}) -> {}",
+                    binding.name,
+                    params.join(", "),
+                    type_name(*returns)
+                )
+            }
+            None => format!("{}: {}", binding.name, type_name(binding.ty)),
+        }
+    }
+}
+
+/// An open document and what was found analysing it.
+struct Document {
+    tree: Tree,
+    index: LineIndex,
+    symbols: Symbols,
+    diagnostics: Vec<Json>,
+}
+
+impl Document {
+    fn new(tree: Tree) -> Self {
+        let index = LineIndex::new(&tree.text);
+        let mut diagnostics = vec![];
+        let mut diagnostic = |span: Span, severity: f64, message: &str| {
+            diagnostics.push(object(vec![
+                ("range", index.range(span.start, span.end)),
+                ("severity", Json::Number(severity)),
+                ("source", Json::Str("asdf".to_string())),
+                ("message", Json::Str(message.to_string())),
+            ]))
+        };
+        for (span, message) in tree.lex_errors.iter() {
+            diagnostic(*span, 1.0, message);
+        }
+        if let Some((span, message)) = tree.error() {
+            diagnostic(span, 1.0, message);
+        }
+        for (span, message) in tree.warnings() {
+            diagnostic(*span, 2.0, message);
+        }
+        let symbols = match tree.error() {
+            None => Symbols::new(tree.nodes(), &tree.tokens),
+            Some(_) => Symbols::default(),
+        };
+        Self {
+            tree,
+            index,
+            symbols,
+            diagnostics,
+        }
+    }
+
+    fn nodes(&self) -> Option<&[ParseNode]> {
+        match self.tree.error() {
+            None => Some(self.tree.nodes()),
+            Some(_) => None,
+        }
+    }
+
+    /// The reference or declaration under the cursor.
+    fn reference_at(&self, position: &Json) -> Option<(Span, usize)> {
+        let offset = self.index.offset(position)?;
+        self.symbols
+            .references
+            .iter()
+            .find(|(s, _)| s.start <= offset && offset <= s.end)
+            .copied()
+    }
+
+    /// End of the block opened after the token starting at `start`.
+    fn block_end(&self, start: usize) -> usize {
+        let mut depth = 0;
+        for token in self.tree.tokens.iter().skip_while(|t| t.span.start < start) {
+            match token.token {
+                TokenType::LeftCurly => depth += 1,
+                TokenType::RightCurly if depth <= 1 => return token.span.end,
+                TokenType::RightCurly => depth -= 1,
+                _ => {}
+            }
+        }
+        self.index.chars.len()
+    }
+
+    fn symbols(&self, nodes: &[ParseNode]) -> Vec<Json> {
+        let mut res = vec![];
+        for node in nodes.iter() {
+            match &node.token {
+                TokenType::Fn(_) => {
+                    let (b, name) = match self.symbols.functions.get(&node.span.start) {
+                        Some((_, b, name)) => (*b, *name),
+                        None => continue,
+                    };
+                    res.push(object(vec![
+                        (
+                            "name",
+                            Json::Str(self.index.chars[name.start..name.end].iter().collect()),
+                        ),
+                        ("detail", Json::Str(self.symbols.describe(b))),
+                        ("kind", Json::Number(12.0)),
+                        (
+                            "range",
+                            self.index
+                                .range(node.span.start, self.block_end(node.span.start)),
+                        ),
+                        ("selectionRange", self.index.range(name.start, name.end)),
+                        ("children", Json::Array(self.symbols(&node.children))),
+                    ]));
+                }
+                TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                    res.extend(self.symbols(&node.children))
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    exit: bool,
+}
+
+impl Server {
+    /// Handles one message, returning the messages to send back.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|d| d.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        let result = match method {
+            "initialize" => Ok(object(vec![
+                (
+                    "capabilities",
+                    object(vec![
+                        ("textDocumentSync", Json::Number(2.0)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("hoverProvider", Json::Bool(true)),
+                        ("documentSymbolProvider", Json::Bool(true)),
+                        ("documentFormattingProvider", Json::Bool(true)),
+                        (
+                            "semanticTokensProvider",
+                            object(vec![
+                                (
+                                    "legend",
+                                    object(vec![
+                                        (
+                                            "tokenTypes",
+                                            Json::Array(
+                                                Class::ALL
+                                                    .iter()
+                                                    .map(|c| Json::Str(c.name().to_string()))
+                                                    .collect(),
+                                            ),
+                                        ),
+                                        ("tokenModifiers", Json::Array(vec![])),
+                                    ]),
+                                ),
+                                ("full", Json::Bool(true)),
+                            ]),
+                        ),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    object(vec![("name", Json::Str("asdf".to_string()))]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "exit" => {
+                self.exit = true;
+                return vec![];
+            }
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let tree = match method {
+                    "textDocument/didOpen" => {
+                        let text = params.get("textDocument").and_then(|d| d.get("text"));
+                        Tree::new(text.and_then(Json::as_str).unwrap_or(""))
+                    }
+                    _ => {
+                        let changes = params.get("contentChanges").and_then(Json::as_array);
+                        let tree = self.documents.remove(&uri).map(|d| d.tree);
+                        apply(tree.unwrap_or_default(), changes.unwrap_or_default())
+                    }
+                };
+                let document = Document::new(tree);
+                let diagnostics = Json::Array(document.diagnostics.clone());
+                self.documents.insert(uri.clone(), document);
+                return vec![publish(&uri, diagnostics)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, Json::Array(vec![]))];
+            }
+            "textDocument/definition"
+            | "textDocument/hover"
+            | "textDocument/documentSymbol"
+            | "textDocument/formatting"
+            | "textDocument/semanticTokens/full" => match self.documents.get(&uri) {
+                Some(document) => Ok(request(document, method, params, &uri)),
+                None => Err((-32602.0, format!("Unknown document {}", uri))),
+            },
+            _ => Err((-32601.0, format!("Unknown method {}", method))),
+        };
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // Notifications get no reply.
+            None => return vec![],
+        };
+        let outcome = match result {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                object(vec![
+                    ("code", Json::Number(code)),
+                    ("message", Json::Str(message)),
+                ]),
+            ),
+        };
+        vec![object(vec![
+            ("jsonrpc", Json::Str("2.0".to_string())),
+            ("id", id),
+            outcome,
+        ])]
+    }
+}
+
+/// Applies the changes of a `didChange` in order. A change without a range
+/// replaces the whole text.
+fn apply(mut tree: Tree, changes: &[Json]) -> Tree {
+    for change in changes.iter() {
+        let text = change.get("text").and_then(Json::as_str).unwrap_or("");
+        let range = change.get("range").and_then(|range| {
+            let index = LineIndex::new(&tree.text);
+            let start = index.offset(range.get("start")?)?;
+            let end = index.offset(range.get("end")?)?;
+            Some((start, end.max(start)))
+        });
+        match range {
+            Some((start, end)) => tree.edit(start, end, text),
+            None => tree = Tree::new(text),
+        }
+    }
+    tree
+}
+
+fn publish(uri: &str, diagnostics: Json) -> Json {
+    object(vec![
+        ("jsonrpc", Json::Str("2.0".to_string())),
+        (
+            "method",
+            Json::Str("textDocument/publishDiagnostics".to_string()),
+        ),
+        (
+            "params",
+            object(vec![
+                ("uri", Json::Str(uri.to_string())),
+                ("diagnostics", diagnostics),
+            ]),
+        ),
+    ])
+}
+
+/// Answers a request about an open document.
+fn request(document: &Document, method: &str, params: &Json, uri: &str) -> Json {
+    let position = params.get("position").unwrap_or(&Json::Null);
+    let symbols = &document.symbols;
+    match method {
+        "textDocument/definition" => match document.reference_at(position) {
+            Some((_, b)) => {
+                let span = symbols.bindings[b].span;
+                object(vec![
+                    ("uri", Json::Str(uri.to_string())),
+                    ("range", document.index.range(span.start, span.end)),
+                ])
+            }
+            None => Json::Null,
+        },
+        "textDocument/hover" => match document.reference_at(position) {
+            Some((span, b)) => object(vec![
+                (
+                    "contents",
+                    object(vec![
+                        ("kind", Json::Str("markdown".to_string())),
+                        (
+                            "value",
+                            Json::Str(format!("```asdf\n{}\n```", symbols.describe(b))),
+                        ),
+                    ]),
+                ),
+                ("range", document.index.range(span.start, span.end)),
+            ]),
+            None => Json::Null,
+        },
+        "textDocument/semanticTokens/full" => {
+            object(vec![("data", Json::Array(semantic_tokens(document)))])
+        }
+        "textDocument/documentSymbol" => match document.nodes() {
+            Some(nodes) => Json::Array(document.symbols(nodes)),
+            None => Json::Null,
+        },
+        _ => match document.nodes() {
+            Some(nodes)
+                if document
+                    .diagnostics
+                    .iter()
+                    .all(|d| d.get("severity") != Some(&Json::Number(1.0))) =>
+            {
+                let width = params
+                    .get("options")
+                    .and_then(|o| o.get("tabSize"))
+                    .and_then(Json::as_f64)
+                    .map_or(4, |n| n as usize);
+                let text = format::format(nodes, &document.tree.tokens, &document.tree.text, width);
+                Json::Array(vec![object(vec![
+                    ("range", document.index.range(0, document.index.chars.len())),
+                    ("newText", Json::Str(text)),
+                ])])
+            }
+            _ => Json::Null,
+        },
+    }
+}
+
+/// The classes of a document as semantic token data: each token as its line
+/// and column relative to the one before, length and class. Tokens over
+/// several lines are split at the line ends.
+fn semantic_tokens(document: &Document) -> Vec<Json> {
+    let index = &document.index;
+    let mut data = vec![];
+    let mut last = (0, 0);
+    for (span, class) in classify(&document.tree.tokens, &document.tree.comments) {
+        let class = Class::ALL
+            .iter()
+            .position(|c| *c == class)
+            .unwrap_or_default();
+        let mut start = span.start;
+        while start < span.end {
+            let end = index.chars[start..span.end]
+                .iter()
+                .position(|c| *c == '\n')
+                .map_or(span.end, |i| start + i);
+            let length: usize = index.chars[start..end].iter().map(|c| c.len_utf16()).sum();
+            let (line, character) = index.line_col(start);
+            if length > 0 {
+                let delta = match line == last.0 {
+                    true => character - last.1,
+                    false => character,
+                };
+                data.extend(
+                    [line - last.0, delta, length, class, 0]
+                        .iter()
+                        .map(|n| Json::Number(*n as f64)),
+                );
+                last = (line, character);
+            }
+            start = end + 1;
+        }
+    }
+    data
+}
+
+/// Reads one framed message, or None at the end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>().map_err(|e| e.to_string())?);
+            }
+        }
+    }
+    let length = length.ok_or("Message without Content-Length")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    String::from_utf8(body).map(Some).map_err(|e| e.to_string())
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// Serves requests from stdin until the client sends `exit`.
+pub fn run() -> Result<(), String> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        let reply = match Json::parse(&message) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![object(vec![
+                ("jsonrpc", Json::Str("2.0".to_string())),
+                ("id", Json::Null),
+                (
+                    "error",
+                    object(vec![
+                        ("code", Json::Number(-32700.0)),
+                        ("message", Json::Str(e)),
+                    ]),
+                ),
+            ])],
+        };
+        for reply in reply.iter() {
+            write_message(&mut output, reply)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+    match server.shutdown {
+        true => Ok(()),
+        false => Err("Exited without shutdown".to_string()),
+    }
+}
@@ -1,34 +1,400 @@
+mod asdfc;
+mod bytecode;
+mod emit;
+mod format;
+mod highlight;
+mod incremental;
+mod interpret;
+mod ir;
+mod json;
 mod lexer;
+mod lint;
+mod lsp;
+mod optimize;
 mod parser;
-mod utils;
+mod repl;
+mod sexp;
+mod sourcemap;
 mod transpile;
-use std::io::Write;
+mod utils;
+mod value;
+mod vm;
+use std::env;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::time::Instant;
 
-fn main() {
-    let filename = "test.asdf";
+const USAGE: &str =
+    "Usage: parser [--target TARGET] [--indent N] [--module] [--source-map] [-O] [--warn-dead-code] [--emit KIND] [FILE]
+       parser run [--vm] FILE
+       parser compile [--strip] FILE [-o OUT]
+       parser repl
+       parser disasm FILE
+       parser ir [--ssa] [--live] [--dot] FILE
+       parser lint [--config CONFIG] FILE
+       parser lsp
+       parser grammar
+       parser bench FILE
+       parser fuzz [--seed N] [--edits N] FILE
+       parser traceback MAP [TRACEBACK]";
+
+/// Parses an asdf file, or reads a tree written as S-expressions from a
+/// .sexp file.
+fn parse_file(filename: &str) -> Result<Vec<parser::ParseNode>, String> {
+    if filename.ends_with(".sexp") {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        return sexp::read(&text).map_err(|e| format!("{}: {}", filename, e));
+    }
     let mut lexer = lexer::Lexer::from_file(filename);
     let lexed = lexer.lex();
     // println!("{:?}", lexed);
+    for (span, message) in lexer.errors.iter() {
+        println!("{} at {}", message, span);
+    }
     let mut parser = parser::Parser::new(lexed);
-    match parser.parse() {
-        Ok(p) => {
-            println!("Intermediate code => S Expressions\n");
-            for n in p.iter() {
-                println!("{}", crate::utils::get_sexp(n, 0));
+    let res = parser.parse();
+    for (span, message) in parser.warnings.iter() {
+        colour::dark_red_ln!("{} at {}", message, span);
+    }
+    res
+}
+
+fn transpile(args: &[&str]) -> Result<(), String> {
+    let mut target = transpile::TARGETS[0];
+    let mut options = transpile::Options::default();
+    let mut filename = None;
+    let mut source_map = false;
+    let mut optimize = false;
+    let mut warn_dead_code = false;
+    let mut emit = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--target" => target = args.next().ok_or(USAGE)?,
+            "--indent" => {
+                options.indent_width = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or("--indent expects a positive number of spaces")?
             }
-            println!();
-            let transpiler = transpile::Transpiler::new(p);
-            println!("Transpiled code to python\n");
-            match transpiler.transpile() {
-                Ok(t) => {
-                    let mut f = fs::File::create("result.py").expect("Couldn't create file");
-                    write!(f, "{}", t);
-                    println!("Written to result.py");
-                },
-                Err(x) => println!("{}", x)
+            "--module" => options.module = true,
+            "--source-map" => source_map = true,
+            "-O" => {
+                optimize = true;
+                options.optimize = true;
             }
+            "--warn-dead-code" => warn_dead_code = true,
+            "--emit" => emit = Some(*args.next().ok_or(USAGE)?),
+            x if filename.is_none() && !x.starts_with('-') => filename = Some(x),
+            _ => return Err(USAGE.to_string()),
         }
-        Err(x) => println!("{}", x),
+    }
+    let filename = filename.unwrap_or("test.asdf");
+    if let Some(kind) = emit {
+        return dump(filename, kind);
+    }
+    options.source = Some(filename.to_string());
+    let backend = transpile::backend(target, &options)?;
+    let mut p = parse_file(filename)?;
+    if optimize {
+        p = optimize::optimize(p);
+    }
+    if optimize || warn_dead_code {
+        let (pruned, warnings) = optimize::eliminate(p.clone());
+        if warn_dead_code {
+            for warning in warnings.iter() {
+                eprintln!("{}: {}", filename, warning);
+            }
+        }
+        if optimize {
+            p = pruned;
+        }
+    }
+    println!("Intermediate code => S Expressions\n");
+    println!("{}", sexp::print(&p));
+    let transpiler = transpile::Transpiler::new(p, backend);
+    println!("Transpiled code to {}\n", target);
+    for output in transpiler.transpile()? {
+        let mut f = fs::File::create(&output.filename).expect("Couldn't create file");
+        write!(f, "{}", output.contents).map_err(|e| e.to_string())?;
+        println!("Written to {}", output.filename);
+        if source_map && !output.mappings.is_empty() {
+            let map = format!("{}.map", output.filename);
+            let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+            fs::write(&map, sourcemap::encode(&output, filename, &text))
+                .map_err(|e| e.to_string())?;
+            println!("Written to {}", map);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the tokens or tree of a file in one of the formats of `emit`,
+/// instead of transpiling it.
+fn dump(filename: &str, kind: &str) -> Result<(), String> {
+    if !emit::EMITS.contains(&kind) {
+        return Err(format!(
+            "Unknown --emit {}, expected one of: {}",
+            kind,
+            emit::EMITS.join(", ")
+        ));
+    }
+    let source = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let (nodes, warnings) = if filename.ends_with(".sexp") {
+        if kind == "tokens-json" {
+            return Err(format!("{}: tokens-json needs asdf source", filename));
+        }
+        let nodes = sexp::read(&source).map_err(|e| format!("{}: {}", filename, e))?;
+        (nodes, vec![])
+    } else {
+        let mut lexer = lexer::Lexer::new(&source);
+        let tokens = lexer.lex();
+        if kind == "tokens-json" {
+            println!("{}", emit::tokens_json(&tokens, &lexer.errors));
+            return Ok(());
+        }
+        for (span, message) in lexer.errors.iter() {
+            eprintln!("{}: {} at {}", filename, message, span);
+        }
+        let mut parser = parser::Parser::new(tokens);
+        let nodes = parser
+            .parse()
+            .map_err(|e| format!("{}: {} at {}", filename, e, parser.error_span()))?;
+        (nodes, parser.warnings)
+    };
+    match kind {
+        "ast-json" => println!("{}", emit::ast_json(&nodes, &warnings)),
+        "dot" => print!("{}", emit::dot(&nodes)),
+        _ => print!("{}", sexp::print(&nodes)),
+    }
+    Ok(())
+}
+
+/// Points the frames of a Python traceback, read from a file or stdin, at
+/// the asdf source the generated code came from.
+fn traceback(map: &str, traceback: Option<&str>) -> Result<(), String> {
+    let text = fs::read_to_string(map).map_err(|e| format!("{}: {}", map, e))?;
+    let map = sourcemap::SourceMap::parse(&text).map_err(|e| format!("{}: {}", map, e))?;
+    let traceback = match traceback {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| e.to_string())?;
+            text
+        }
+    };
+    print!("{}", sourcemap::translate_traceback(&map, &traceback));
+    Ok(())
+}
+
+fn run(filename: &str) -> Result<(), String> {
+    let p = parse_file(filename)?;
+    interpret::Interpreter::new().run(&p)
+}
+
+/// Loads a precompiled .asdfc file, or compiles the source file otherwise.
+fn load_program(filename: &str) -> Result<bytecode::Program, String> {
+    let bytes = fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    if bytes.starts_with(&asdfc::MAGIC) {
+        asdfc::read(&bytes).map_err(|e| format!("{}: {}", filename, e))
+    } else {
+        let p = parse_file(filename)?;
+        bytecode::Compiler::new().compile(&p)
+    }
+}
+
+fn run_vm(filename: &str) -> Result<(), String> {
+    let program = load_program(filename)?;
+    vm::Vm::new(&program).run()
+}
+
+fn compile(args: &[&str]) -> Result<(), String> {
+    let mut debug = true;
+    let mut filename = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--strip" => debug = false,
+            "-o" => out = Some(args.next().ok_or(USAGE)?.to_string()),
+            x if filename.is_none() && !x.starts_with('-') => filename = Some(x),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let filename = filename.ok_or(USAGE)?;
+    let out = out.unwrap_or_else(|| {
+        Path::new(filename)
+            .with_extension("asdfc")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let p = parse_file(filename)?;
+    let program = bytecode::Compiler::new().compile(&p)?;
+    fs::write(&out, asdfc::write(&program, debug)).map_err(|e| format!("{}: {}", out, e))?;
+    println!("Written to {}", out);
+    Ok(())
+}
+
+fn disasm(filename: &str) -> Result<(), String> {
+    let program = load_program(filename)?;
+    print!("{}", program);
+    Ok(())
+}
+
+/// Prints the three-address code of a program, or its control flow graph,
+/// and warns about variables that may be read before they are assigned.
+fn ir(args: &[&str]) -> Result<(), String> {
+    let mut ssa = false;
+    let mut live = false;
+    let mut dot = false;
+    let mut filename = None;
+    for arg in args.iter() {
+        match *arg {
+            "--ssa" => ssa = true,
+            "--live" => live = true,
+            "--dot" => dot = true,
+            x if filename.is_none() && !x.starts_with('-') => filename = Some(x),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let filename = filename.ok_or(USAGE)?;
+    let p = parse_file(filename)?;
+    let mut module = ir::Lowerer::new().lower(&p)?;
+    for (span, message) in ir::dataflow::uninitialized(&module) {
+        eprintln!("{}: warning at {}: {}", filename, span, message);
+    }
+    if ssa {
+        module = module.to_ssa();
+    }
+    if dot {
+        print!("{}", module.dot());
+    } else {
+        print!("{}", module.listing(live));
+    }
+    Ok(())
+}
+
+/// Reports what the lint rules find. The config defaults to asdf-lint.json
+/// next to the file.
+fn lint(args: &[&str]) -> Result<(), String> {
+    let mut config = None;
+    let mut filename = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--config" => config = Some(args.next().ok_or(USAGE)?.to_string()),
+            x if filename.is_none() && !x.starts_with('-') => filename = Some(x),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let filename = filename.ok_or(USAGE)?;
+    let config = config.or_else(|| {
+        let path = Path::new(filename).with_file_name("asdf-lint.json");
+        path.exists().then(|| path.to_string_lossy().into_owned())
+    });
+    let config = match config {
+        Some(path) => {
+            let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+            lint::Config::parse(&text).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => lint::Config::default(),
+    };
+    let source = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let p = parse_file(filename)?;
+    let diagnostics = lint::lint(&p, &source, &config);
+    for diagnostic in diagnostics.iter() {
+        println!("{}: {}", filename, diagnostic);
+    }
+    match diagnostics.len() {
+        0 => Ok(()),
+        1 => Err("1 problem found".to_string()),
+        n => Err(format!("{} problems found", n)),
+    }
+}
+
+/// Prints a TextMate grammar for asdf, for editors without a language
+/// server.
+fn grammar() -> Result<(), String> {
+    println!("{}", highlight::textmate());
+    Ok(())
+}
+
+/// Checks incremental parsing against full parses of randomly edited copies
+/// of a file.
+fn fuzz(args: &[&str]) -> Result<(), String> {
+    let mut seed = 1;
+    let mut edits = 1000;
+    let mut filename = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--seed" => seed = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?,
+            "--edits" => edits = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?,
+            x if filename.is_none() && !x.starts_with('-') => filename = Some(x),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let filename = filename.ok_or(USAGE)?;
+    let source = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    incremental::fuzz(&source, seed, edits)?;
+    println!("{} edits matched a full parse", edits);
+    Ok(())
+}
+
+/// Runs the program once through each execution engine and reports timings.
+fn bench(filename: &str) -> Result<(), String> {
+    let p = parse_file(filename)?;
+
+    let start = Instant::now();
+    interpret::Interpreter::new().run(&p)?;
+    let interpreted = start.elapsed();
+
+    let start = Instant::now();
+    let program = bytecode::Compiler::new().compile(&p)?;
+    let compiled = start.elapsed();
+    vm::Vm::new(&program).run()?;
+    let executed = start.elapsed();
+
+    println!();
+    println!("interpreter: {:>10.3?}", interpreted);
+    println!(
+        "vm:          {:>10.3?} (compile {:.3?})",
+        executed, compiled
+    );
+    println!(
+        "speedup:     {:>9.2}x",
+        interpreted.as_secs_f64() / executed.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let res = match args.as_slice() {
+        ["run", filename] if filename.ends_with(".asdfc") => run_vm(filename),
+        ["run", filename] => run(filename),
+        ["run", "--vm", filename] => run_vm(filename),
+        ["compile", rest @ ..] => compile(rest),
+        ["repl"] => repl::Repl::new().run().map_err(|e| e.to_string()),
+        ["disasm", filename] => disasm(filename),
+        ["bench", filename] => bench(filename),
+        ["fuzz", rest @ ..] => fuzz(rest),
+        ["ir", rest @ ..] => ir(rest),
+        ["lint", rest @ ..] => lint(rest),
+        ["lsp"] => lsp::run(),
+        ["grammar"] => grammar(),
+        ["traceback", map] => traceback(map, None),
+        ["traceback", map, file] => traceback(map, Some(file)),
+        _ => transpile(&args),
+    };
+    if let Err(x) = res {
+        println!("{}", x);
+        process::exit(1);
     }
 }
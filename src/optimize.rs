@@ -0,0 +1,723 @@
+use crate::ir::dataflow::dead_stores;
+use crate::ir::Lowerer;
+use crate::lexer::{Operator, Span, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+use ordered_float::OrderedFloat;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/*
+Constant folding over the parse tree, run before code generation with -O.
+
+- Arithmetic on number literals and `+` on string literals is computed.
+  Number literals are f32 while the interpreter computes in f64, so a
+  result is only folded when the literal can hold it exactly. Division by
+  zero is left for the runtime to report.
+- The tree has no boolean literal, so comparisons are only folded where
+  just their truthiness matters: in `if`, `elif` and `while` conditions,
+  where they become 1 or 0.
+- `x * 1`, `1 * x`, `x / 1`, `x + 0`, `0 + x` and `x - 0` become `x` when
+  `x` is known to be a number (or to fail before producing anything else),
+  as the operation could otherwise raise a type error. The only observable
+  difference is that `-0 + 0` keeps the sign of the zero.
+- A variable bound exactly once, directly in the body of its function or at
+  the top level, to a literal is replaced by the literal in every later
+  statement, including the bodies of functions defined after it.
+
+Dead code elimination runs after folding:
+
+- `if` and `elif` branches whose condition is a falsy literal are removed,
+  and a truthy literal turns its branch into the last one. An `if` left
+  with nothing but an unconditional branch is replaced by its body.
+- `while` loops whose condition is a falsy literal are removed.
+- Statements following a `return` in the same block are removed.
+- Functions that are never called or referenced from live code are
+  removed. A function only used by other unused functions is unused too.
+
+Each removal is reported as a warning, which is shown with
+--warn-dead-code. Without -O the tree is not folded first, so only
+conditions that are literals in the source are reported, and the program
+is compiled as written.
+
+With -O the C and WebAssembly backends also leave out the `let` statements
+the IR finds to be dead stores, see ir/dataflow.rs. This is not reported,
+as such a store may well be written on purpose.
+*/
+
+type Constants = HashMap<String, TokenType>;
+
+pub fn optimize(nodes: Vec<ParseNode>) -> Vec<ParseNode> {
+    body(nodes, &Constants::new(), &[])
+}
+
+fn is_literal(node: &ParseNode) -> bool {
+    matches!(
+        node.token,
+        TokenType::Number(_) | TokenType::StringLiteral(_)
+    )
+}
+
+fn number(node: &ParseNode) -> Option<f64> {
+    match node.token {
+        TokenType::Number(n) => Some(n.into_inner() as f64),
+        _ => None,
+    }
+}
+
+fn leaf(token: TokenType, node: &ParseNode) -> ParseNode {
+    ParseNode {
+        token,
+        extra_info: None,
+        children: vec![],
+        span: node.span,
+    }
+}
+
+/// A number literal for `n`, if the literal type can represent it exactly.
+fn number_literal(n: f64, node: &ParseNode) -> Option<ParseNode> {
+    let narrow = n as f32;
+    if narrow as f64 == n && n.is_finite() {
+        Some(leaf(TokenType::Number(OrderedFloat(narrow)), node))
+    } else {
+        None
+    }
+}
+
+/// `let` statements of a function body, including nested blocks but not
+/// nested functions.
+fn collect_lets<'a>(nodes: &'a [ParseNode], out: &mut Vec<(&'a str, &'a ParseNode)>) {
+    for node in nodes.iter() {
+        match &node.token {
+            TokenType::Let => {
+                if let [target, value] = node.children.as_slice() {
+                    if let TokenType::Identifier(id) = &target.token {
+                        out.push((id, value));
+                    }
+                }
+            }
+            TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                collect_lets(&node.children, out)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Variables of a function body that only ever hold numbers. Starts from
+/// every variable bound by `let` and drops those with a binding that may
+/// not be a number until nothing changes, so `let i = i + 1` stays numeric.
+fn numeric_variables(nodes: &[ParseNode], bindings: &[String]) -> HashSet<String> {
+    let mut lets = vec![];
+    collect_lets(nodes, &mut lets);
+    let let_count = |name: &str| lets.iter().filter(|(n, _)| *n == name).count();
+    let mut numeric: HashSet<String> = bindings
+        .iter()
+        .filter(|b| bindings.iter().filter(|o| o == b).count() == let_count(b))
+        .cloned()
+        .collect();
+    loop {
+        let before = numeric.len();
+        for (name, value) in lets.iter() {
+            if !is_numeric(value, &numeric) {
+                numeric.remove(*name);
+            }
+        }
+        if numeric.len() == before {
+            return numeric;
+        }
+    }
+}
+
+/// Whether `node` evaluates to a number whenever it evaluates at all.
+fn is_numeric(node: &ParseNode, numeric: &HashSet<String>) -> bool {
+    match (&node.token, node.children.as_slice()) {
+        (TokenType::Number(_), _) => true,
+        (TokenType::Identifier(x), _) => numeric.contains(x),
+        (TokenType::Operator(Operator::Plus), [lhs, rhs]) => {
+            is_numeric(lhs, numeric) && is_numeric(rhs, numeric)
+        }
+        (TokenType::Operator(Operator::Minus | Operator::Multiply | Operator::Divide), [_, _]) => {
+            true
+        }
+        (TokenType::Operator(Operator::Plus | Operator::Minus), [_]) => true,
+        _ => false,
+    }
+}
+
+struct Scope<'a> {
+    constants: Constants,
+    numeric: &'a HashSet<String>,
+}
+
+/// Optimizes the body of a function, or the whole program. Constants of the
+/// enclosing scope stay visible unless the body binds the same name.
+fn body(nodes: Vec<ParseNode>, outer: &Constants, params: &[String]) -> Vec<ParseNode> {
+    let mut bindings = vec![];
+    collect_bindings(&nodes, &mut bindings);
+    let numeric = numeric_variables(&nodes, &bindings);
+    let mut scope = Scope {
+        constants: outer
+            .iter()
+            .filter(|(k, _)| !bindings.contains(k) && !params.contains(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        numeric: &numeric,
+    };
+    let mut res = vec![];
+    for node in nodes {
+        let node = scope.statement(node);
+        if let (TokenType::Let, [target, value]) = (&node.token, node.children.as_slice()) {
+            if let TokenType::Identifier(name) = &target.token {
+                if is_literal(value) && bindings.iter().filter(|b| *b == name).count() == 1 {
+                    scope.constants.insert(name.clone(), value.token.clone());
+                }
+            }
+        }
+        res.push(node);
+    }
+    res
+}
+
+impl<'a> Scope<'a> {
+    fn block(&self, nodes: Vec<ParseNode>) -> Vec<ParseNode> {
+        nodes.into_iter().map(|n| self.statement(n)).collect()
+    }
+
+    fn statement(&self, node: ParseNode) -> ParseNode {
+        let ParseNode {
+            token,
+            extra_info,
+            children,
+            span,
+        } = node;
+        let (extra_info, children) = match &token {
+            TokenType::Let | TokenType::Print | TokenType::Return | TokenType::Call(_) => (
+                extra_info,
+                children
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        // The target of a let is not an expression.
+                        if token == TokenType::Let && i == 0 {
+                            c
+                        } else {
+                            self.expression(c)
+                        }
+                    })
+                    .collect(),
+            ),
+            TokenType::While | TokenType::If | TokenType::Elif => (
+                extra_info.map(|c| Box::new(self.condition(*c))),
+                self.block(children),
+            ),
+            TokenType::Else => (extra_info, self.block(children)),
+            TokenType::Fn(_) => {
+                let params: Vec<String> = extra_info
+                    .iter()
+                    .flat_map(|p| p.children.iter())
+                    .filter_map(|p| match &p.token {
+                        TokenType::Identifier(x) => Some(x.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let children = body(children, &self.constants, &params);
+                (extra_info, children)
+            }
+            _ => (extra_info, children),
+        };
+        ParseNode {
+            token,
+            extra_info,
+            children,
+            span,
+        }
+    }
+
+    /// Folds an expression whose value is only tested for truthiness.
+    fn condition(&self, node: ParseNode) -> ParseNode {
+        let node = self.expression(node);
+        let truth = match (&node.token, node.children.as_slice()) {
+            (TokenType::Operator(op), [lhs, rhs]) if is_literal(lhs) && is_literal(rhs) => {
+                match (op, &lhs.token, &rhs.token) {
+                    (Operator::Equality, TokenType::Number(l), TokenType::Number(r)) => {
+                        Some(l.into_inner() as f64 == r.into_inner() as f64)
+                    }
+                    (Operator::Equality, l, r) => Some(l == r),
+                    (op, TokenType::Number(l), TokenType::Number(r)) => {
+                        compare(op, &(l.into_inner() as f64), &(r.into_inner() as f64))
+                    }
+                    (op, TokenType::StringLiteral(l), TokenType::StringLiteral(r)) => {
+                        compare(op, l, r)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match truth {
+            Some(truth) => leaf(
+                TokenType::Number(OrderedFloat(if truth { 1.0 } else { 0.0 })),
+                &node,
+            ),
+            None => node,
+        }
+    }
+
+    fn expression(&self, node: ParseNode) -> ParseNode {
+        match &node.token {
+            TokenType::Identifier(x) => match self.constants.get(x) {
+                Some(value) => leaf(value.clone(), &node),
+                None => node,
+            },
+            TokenType::Call(_) => ParseNode {
+                children: node
+                    .children
+                    .into_iter()
+                    .map(|c| self.expression(c))
+                    .collect(),
+                ..node
+            },
+            TokenType::Operator(_) => {
+                let node = ParseNode {
+                    children: node
+                        .children
+                        .into_iter()
+                        .map(|c| self.expression(c))
+                        .collect(),
+                    ..node
+                };
+                self.fold(node)
+            }
+            _ => node,
+        }
+    }
+
+    /// Folds an operator whose operands have already been folded.
+    fn fold(&self, node: ParseNode) -> ParseNode {
+        let op = match &node.token {
+            TokenType::Operator(op) => op.clone(),
+            _ => return node,
+        };
+        match node.children.as_slice() {
+            [operand] => match (op, number(operand)) {
+                (Operator::Minus, Some(n)) => number_literal(-n, &node),
+                (Operator::Plus, Some(n)) => number_literal(n, &node),
+                _ => None,
+            },
+            [lhs, rhs] => match (number(lhs), number(rhs)) {
+                (Some(l), Some(r)) => match op {
+                    Operator::Plus => number_literal(l + r, &node),
+                    Operator::Minus => number_literal(l - r, &node),
+                    Operator::Multiply => number_literal(l * r, &node),
+                    Operator::Divide if r != 0.0 => number_literal(l / r, &node),
+                    _ => None,
+                },
+                (l, r) => match (&op, &lhs.token, &rhs.token) {
+                    (Operator::Plus, TokenType::StringLiteral(a), TokenType::StringLiteral(b)) => {
+                        Some(leaf(TokenType::StringLiteral(format!("{}{}", a, b)), &node))
+                    }
+                    (Operator::Multiply | Operator::Divide, _, _) if r == Some(1.0) => {
+                        self.identity(lhs)
+                    }
+                    (Operator::Multiply, _, _) if l == Some(1.0) => self.identity(rhs),
+                    (Operator::Plus | Operator::Minus, _, _) if r == Some(0.0) => {
+                        self.identity(lhs)
+                    }
+                    (Operator::Plus, _, _) if l == Some(0.0) => self.identity(rhs),
+                    _ => None,
+                },
+            },
+            _ => None,
+        }
+        .unwrap_or(node)
+    }
+
+    fn identity(&self, operand: &ParseNode) -> Option<ParseNode> {
+        if is_numeric(operand, self.numeric) {
+            Some(operand.clone())
+        } else {
+            None
+        }
+    }
+}
+
+fn compare<T: PartialOrd>(op: &Operator, l: &T, r: &T) -> Option<bool> {
+    match op {
+        Operator::LessThan => Some(l < r),
+        Operator::LessThanEqual => Some(l <= r),
+        Operator::GreaterThan => Some(l > r),
+        Operator::GreaterThanEqual => Some(l >= r),
+        _ => None,
+    }
+}
+
+/// Code removed by dead code elimination.
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "warning at {}: {}", self.span, self.message)
+    }
+}
+
+fn warn(warnings: &mut Vec<Warning>, span: Span, message: impl Into<String>) {
+    warnings.push(Warning {
+        span,
+        message: message.into(),
+    });
+}
+
+pub fn eliminate(nodes: Vec<ParseNode>) -> (Vec<ParseNode>, Vec<Warning>) {
+    let mut warnings = vec![];
+    let nodes = prune(nodes, &mut warnings);
+    let used = used_functions(&nodes);
+    let nodes = remove_functions(nodes, &used, &mut warnings);
+    warnings.sort_by_key(|w| w.span.start);
+    (nodes, warnings)
+}
+
+/// The truthiness of a condition, if it is a literal.
+fn constant(node: &ParseNode) -> Option<bool> {
+    match &node.token {
+        TokenType::Number(n) => Some(n.into_inner() != 0.0),
+        TokenType::StringLiteral(s) => Some(!s.is_empty()),
+        _ => None,
+    }
+}
+
+fn prune(nodes: Vec<ParseNode>, warnings: &mut Vec<Warning>) -> Vec<ParseNode> {
+    let mut res = vec![];
+    let mut nodes = nodes.into_iter();
+    while let Some(node) = nodes.next() {
+        res.extend(prune_statement(node, warnings));
+        // The statement may also have been replaced by a body ending in one.
+        if res.last().is_some_and(|n| n.token == TokenType::Return) {
+            if let Some(next) = nodes.next() {
+                warn(warnings, next.span, "unreachable code after return");
+            }
+            break;
+        }
+    }
+    res
+}
+
+/// Prunes a statement, which may leave nothing or the statements of a body
+/// that always runs.
+fn prune_statement(node: ParseNode, warnings: &mut Vec<Warning>) -> Vec<ParseNode> {
+    match &node.token {
+        TokenType::While => {
+            if node.extra_info.as_deref().and_then(constant) == Some(false) {
+                warn(warnings, node.span, "loop condition is always false");
+                return vec![];
+            }
+            vec![ParseNode {
+                children: prune(node.children, warnings),
+                ..node
+            }]
+        }
+        TokenType::If => prune_if(node, warnings),
+        TokenType::Fn(_) => vec![ParseNode {
+            children: prune(node.children, warnings),
+            ..node
+        }],
+        _ => vec![node],
+    }
+}
+
+fn prune_if(node: ParseNode, warnings: &mut Vec<Warning>) -> Vec<ParseNode> {
+    let ParseNode {
+        extra_info,
+        mut children,
+        span,
+        ..
+    } = node;
+    let split = children
+        .iter()
+        .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+        .unwrap_or(children.len());
+    let branches = children.split_off(split);
+    // Every branch as its condition, body and span; None for an else.
+    let mut arms = std::iter::once((extra_info, children, span)).chain(
+        branches
+            .into_iter()
+            .map(|b| (b.extra_info, b.children, b.span)),
+    );
+    let mut kept = vec![];
+    while let Some((cond, body, span)) = arms.next() {
+        match cond.as_deref().map(constant) {
+            Some(Some(false)) => warn(warnings, span, "condition is always false"),
+            Some(Some(true)) | None => {
+                kept.push((None, prune(body, warnings), span));
+                for (_, _, span) in arms.by_ref() {
+                    warn(warnings, span, "branch is never taken");
+                }
+            }
+            Some(None) => kept.push((cond, prune(body, warnings), span)),
+        }
+    }
+    let mut kept = kept.into_iter();
+    let (cond, mut body, span) = match kept.next() {
+        Some(first) => first,
+        None => return vec![],
+    };
+    if cond.is_none() {
+        return body;
+    }
+    body.extend(kept.map(|(cond, children, span)| ParseNode {
+        token: if cond.is_some() {
+            TokenType::Elif
+        } else {
+            TokenType::Else
+        },
+        extra_info: cond,
+        children,
+        span,
+    }));
+    vec![ParseNode {
+        token: TokenType::If,
+        extra_info: cond,
+        children: body,
+        span,
+    }]
+}
+
+fn function_name(node: &ParseNode) -> Option<&str> {
+    match &node.token {
+        TokenType::Fn(Some(info)) => match &*info.name {
+            TokenType::Identifier(x) => Some(x),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Names read or called by `nodes`, not descending into nested functions.
+fn references<'a>(nodes: &'a [ParseNode], out: &mut Vec<&'a str>) {
+    for node in nodes.iter() {
+        match &node.token {
+            TokenType::Fn(_) => continue,
+            TokenType::Identifier(x) => out.push(x),
+            TokenType::Call(info) => {
+                if let TokenType::Identifier(x) = &*info.name {
+                    out.push(x);
+                }
+            }
+            _ => {}
+        }
+        if let Some(extra) = &node.extra_info {
+            references(std::slice::from_ref(extra), out);
+        }
+        // The target of a let is not a use.
+        let skip = usize::from(node.token == TokenType::Let);
+        references(&node.children[skip.min(node.children.len())..], out);
+    }
+}
+
+fn collect_functions<'a>(nodes: &'a [ParseNode], out: &mut Vec<&'a ParseNode>) {
+    for node in nodes.iter() {
+        if function_name(node).is_some() {
+            out.push(node);
+        }
+        collect_functions(&node.children, out);
+    }
+}
+
+/// Names of the functions reachable from the top level. Functions are
+/// matched by name only, so a function sharing its name with a used one is
+/// kept as well.
+fn used_functions(nodes: &[ParseNode]) -> HashSet<String> {
+    let mut functions = vec![];
+    collect_functions(nodes, &mut functions);
+    let mut pending = vec![];
+    references(nodes, &mut pending);
+    let mut used = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if !used.insert(name.to_string()) {
+            continue;
+        }
+        for function in functions.iter() {
+            if function_name(function) == Some(name) {
+                references(&function.children, &mut pending);
+            }
+        }
+    }
+    used
+}
+
+fn remove_functions(
+    nodes: Vec<ParseNode>,
+    used: &HashSet<String>,
+    warnings: &mut Vec<Warning>,
+) -> Vec<ParseNode> {
+    let mut res = vec![];
+    for node in nodes {
+        if let Some(name) = function_name(&node) {
+            if !used.contains(name) {
+                warn(
+                    warnings,
+                    node.span,
+                    format!("function '{}' is never called", name),
+                );
+                continue;
+            }
+        }
+        res.push(ParseNode {
+            children: remove_functions(node.children, used, warnings),
+            ..node
+        });
+    }
+    res
+}
+
+/// `nodes` without their dead stores. Programs that cannot be lowered are
+/// left as they are, for the backend to report.
+pub fn remove_dead_stores(nodes: &[ParseNode]) -> Vec<ParseNode> {
+    fn remove(nodes: &[ParseNode], dead: &HashSet<Span>) -> Vec<ParseNode> {
+        nodes
+            .iter()
+            .filter(|n| !(n.token == TokenType::Let && dead.contains(&n.span)))
+            .map(|n| match n.token {
+                TokenType::Let => n.clone(),
+                _ => ParseNode {
+                    token: n.token.clone(),
+                    extra_info: n.extra_info.clone(),
+                    children: remove(&n.children, dead),
+                    span: n.span,
+                },
+            })
+            .collect()
+    }
+    match Lowerer::new().lower(nodes) {
+        Ok(module) => remove(nodes, &dead_stores(&module)),
+        Err(_) => nodes.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sexp;
+
+    fn run(nodes: &[ParseNode]) -> (Vec<String>, Result<(), String>) {
+        let mut interpreter = Interpreter::collecting();
+        let result = interpreter.run(nodes);
+        (interpreter.output().to_vec(), result)
+    }
+
+    /// Checks that `source` prints the same and fails the same way with and
+    /// without -O, and returns the optimized tree.
+    fn same_behaviour(source: &str) -> String {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let expected = run(&nodes);
+        let (optimized, _) = eliminate(optimize(nodes));
+        assert_eq!(run(&optimized), expected, "{}", source);
+        sexp::print(&optimized)
+    }
+
+    #[test]
+    fn folds_only_exact_results() {
+        let tree = same_behaviour("print(7 / 2) print(1 / 3) print(16777216 + 1)");
+        assert!(tree.contains("(print 3.5)"), "{}", tree);
+        assert!(tree.contains("(/ 1 3)"), "{}", tree);
+        assert!(tree.contains("(+ 16777216 1)"), "{}", tree);
+        same_behaviour("print(10000000 * 10000000) print(2 / 3 * 3) print(1 / 0)");
+    }
+
+    #[test]
+    fn keeps_identities_on_non_numbers() {
+        same_behaviour("let s = \"a\" print(s + 0)");
+        same_behaviour("let s = \"ab\" print(s * 1)");
+        same_behaviour("fn f(x) { return x / 1 } print(f(4)) print(f(\"a\"))");
+        let tree = same_behaviour("let n = 5 let m = n * 2 print(m + 0) print(1 * m)");
+        assert!(
+            !tree.contains("(+ m 0)") && !tree.contains("(* 1 m)"),
+            "{}",
+            tree
+        );
+    }
+
+    #[test]
+    fn drops_branches_after_a_true_elif() {
+        let source = "fn f() { print(\"f\") return 1 }
+            let x = 1
+            if (0) { print(1) } elif (x) { print(2) } elif (1) { print(3) } elif (f()) { print(4) } else { print(5) }
+            if (x == 0) { print(6) } elif (1 < 2) { print(7) } elif (f()) { print(8) }
+            if (x == 1) { print(9) } elif (0) { print(10) } else { print(11) }";
+        let tree = same_behaviour(source);
+        assert!(
+            !tree.contains("(print 4)") && !tree.contains("(print 8)"),
+            "{}",
+            tree
+        );
+    }
+
+    #[test]
+    fn warns_for_every_dropped_branch() {
+        let source = "let x = 1 if (x) { print(1) } elif (1) { print(2) } elif (x) { print(3) } else { print(4) }";
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let (_, warnings) = eliminate(nodes);
+        let messages: Vec<_> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "warning at 1:53: branch is never taken",
+                "warning at 1:75: branch is never taken"
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_dead_code() {
+        same_behaviour(
+            "fn unused() { print(\"no\") }
+            fn f(n) { if (n) { return 1 } return 2 print(n) }
+            while (0) { print(0) }
+            print(f(0)) print(f(1))",
+        );
+    }
+
+    #[test]
+    fn removes_stores_nobody_reads() {
+        let nodes = Parser::new(
+            Lexer::new(
+                "let a = 1 let a = 2 print(a)
+            let b = \"unused\"
+            fn f() { print(c) } let c = 3 f()
+            fn g(n) { let k = 0 if (n) { let k = 1 } print(k) }
+            fn h(n) { let n = 5 print(x) let x = 1 let x = 2 return 1 }
+            g(1) h(1)",
+            )
+            .lex(),
+        )
+        .parse()
+        .unwrap();
+        let kept = sexp::print(&remove_dead_stores(&nodes));
+        for dead in ["(let a 1)", "(let b", "(let n 5)", "(let x 2)"] {
+            assert!(!kept.contains(dead), "{} in {}", dead, kept);
+        }
+        for live in [
+            "(let a 2)",
+            "(let c 3)",
+            "(let k 0)",
+            "(let k 1)",
+            "(let x 1)",
+        ] {
+            assert!(kept.contains(live), "{} not in {}", live, kept);
+        }
+    }
+
+    #[test]
+    fn keeps_stores_that_may_fail() {
+        let source = "let a = 2 * \"s\" let b = f() let a = 1 print(a)";
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let kept = sexp::print(&remove_dead_stores(&nodes));
+        assert_eq!(kept, sexp::print(&nodes));
+    }
+}
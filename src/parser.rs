@@ -1,43 +1,77 @@
-use crate::lexer::{FnInfo, Operator, Token, TokenType};
+use crate::lexer::{FnInfo, Operator, Span, Token, TokenType};
 use std::collections::HashSet;
 
 /*
 program := statement
 statement := decl statement | decl
-decl := var_dec | whileLoop | ifStatement
+decl := var_dec | whileLoop | ifStatement | print | fnDecl | call | return
 whileLoop := while (expr) {statement}
 ifStatement := if (expr) {statement} | if (expr) {statement} elseBlock
 elseBlock := elif (expr) {statement} | elif (expr) {statement} elseBlock | else {statement}
 var_dec := let iden equal val
 val := expr | string
-expr := parsed with pratt parser
+fnDecl := fn iden (iden*) {statement}
+call := iden (expr*)
+return := return | return expr
+expr := parsed with pratt parser, primaries are numbers, strings, identifiers and calls
 */
 
 pub struct Parser {
     tokens: Vec<Token>,
     cur: usize,
+    /// Identifiers read before anything declared them.
+    pub warnings: Vec<(Span, String)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParseNode {
     pub token: TokenType,
     pub extra_info: Option<Box<ParseNode>>,
     pub children: Vec<ParseNode>,
+    pub span: Span,
 }
 
 impl ParseNode {
-    fn new(token: TokenType, extra_info: Option<Box<ParseNode>>, children: Vec<ParseNode>) -> Self {
+    fn new(
+        token: TokenType,
+        extra_info: Option<Box<ParseNode>>,
+        children: Vec<ParseNode>,
+        span: Span,
+    ) -> Self {
         Self {
             token,
             extra_info,
             children,
+            span,
         }
     }
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, cur: 0 }
+        Self {
+            tokens,
+            cur: 0,
+            warnings: vec![],
+        }
+    }
+
+    /// A parser over `tokens` that starts at the token at index `cur`.
+    pub fn resume(tokens: Vec<Token>, cur: usize) -> Self {
+        Self {
+            tokens,
+            cur,
+            warnings: vec![],
+        }
+    }
+
+    /// Index of the next token to parse.
+    pub fn position(&self) -> usize {
+        self.cur
+    }
+
+    pub fn into_tokens(self) -> Vec<Token> {
+        self.tokens
     }
 
     fn peek(&self) -> Option<TokenType> {
@@ -48,6 +82,24 @@ impl Parser {
         self.tokens.get(self.cur + offset).map(|t| t.token.clone())
     }
 
+    /// Span of the next token, or of the last one once the input is exhausted.
+    fn span(&self) -> Span {
+        self.tokens
+            .get(self.cur)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.span)
+            .unwrap_or_default()
+    }
+
+    /// Span of the token the parser stopped at, for reporting an error.
+    pub fn error_span(&self) -> Span {
+        self.tokens
+            .get(self.cur.saturating_sub(1))
+            .or_else(|| self.tokens.last())
+            .map(|t| t.span)
+            .unwrap_or_default()
+    }
+
     fn next_with_line(&mut self) -> Option<Token> {
         let res = self.tokens.get(self.cur).cloned();
         self.cur += 1;
@@ -57,11 +109,7 @@ impl Parser {
     fn next(&mut self) -> Option<TokenType> {
         let res = self.tokens.get(self.cur).cloned();
         self.advance();
-        if let Some(t) = res {
-            Some(t.token)
-        } else {
-            None
-        }
+        res.map(|t| t.token)
     }
 
     fn advance(&mut self) {
@@ -69,10 +117,36 @@ impl Parser {
     }
 
     pub fn parse(&mut self) -> Result<Vec<ParseNode>, String> {
-        let mut env = HashSet::new();
-        let res = self.parse_statement(&mut env)?;
+        self.parse_in(&mut HashSet::new())
+    }
+
+    /// Parses a program whose top level declarations are added to `env`, so
+    /// names declared by an earlier parse are known to a later one.
+    pub fn parse_in(&mut self, env: &mut HashSet<TokenType>) -> Result<Vec<ParseNode>, String> {
+        let res = self.parse_statement(env)?;
+        if self.peek().is_none() {
+            Ok(res)
+        } else {
+            Err("Invalid parse".to_string())
+        }
+    }
+
+    /// Parses the next top level statement, as `parse_in` would, or returns
+    /// None at the end of input.
+    pub fn parse_top_level(
+        &mut self,
+        env: &mut HashSet<TokenType>,
+    ) -> Option<Result<ParseNode, String>> {
+        match self.peek()? {
+            TokenType::RightCurly => Some(Err("Invalid parse".to_string())),
+            _ => Some(self.parse_decl(env)),
+        }
+    }
+
+    /// Parses input consisting of a single expression.
+    pub fn parse_expression(&mut self, env: &HashSet<TokenType>) -> Result<ParseNode, String> {
+        let res = self.parse_expr(0, env)?;
         if self.peek().is_none() {
-            println!();
             Ok(res)
         } else {
             Err("Invalid parse".to_string())
@@ -92,6 +166,7 @@ impl Parser {
     }
 
     fn parse_decl(&mut self, env: &mut HashSet<TokenType>) -> Result<ParseNode, String> {
+        let span = self.span();
         match self.next() {
             Some(t) => match t {
                 TokenType::Let => {
@@ -101,29 +176,19 @@ impl Parser {
                     ) = (self.peek(), self.peek_n(1))
                     {
                         env.insert(TokenType::Identifier(id.clone()));
+                        let id_span = self.span();
                         self.advance();
                         self.advance();
-                        if let Some(TokenType::StringLiteral(x)) = self.peek() {
-                            self.advance();
-                            Ok(ParseNode::new(
-                                TokenType::Let,
-                                None,
-                                vec![
-                                    ParseNode::new(TokenType::Identifier(id), None, vec![]),
-                                    ParseNode::new(TokenType::StringLiteral(x), None, vec![]),
-                                ],
-                            ))
-                        } else {
-                            let node = self.parse_expr(0, env)?;
-                            Ok(ParseNode::new(
-                                TokenType::Let,
-                                None,
-                                vec![
-                                    ParseNode::new(TokenType::Identifier(id), None, vec![]),
-                                    node,
-                                ],
-                            ))
-                        }
+                        let node = self.parse_expr(0, env)?;
+                        Ok(ParseNode::new(
+                            TokenType::Let,
+                            None,
+                            vec![
+                                ParseNode::new(TokenType::Identifier(id), None, vec![], id_span),
+                                node,
+                            ],
+                            span,
+                        ))
                     } else {
                         Err("Invalid variable declaration".to_string())
                     }
@@ -139,6 +204,7 @@ impl Parser {
                                         TokenType::While,
                                         Some(Box::new(node)),
                                         nodes,
+                                        span,
                                     ))
                                 } else {
                                     Err("Missing right curly in while loop".to_string())
@@ -168,6 +234,7 @@ impl Parser {
                         }
                         let mut res = vec![];
                         while let Some(TokenType::Elif) = self.peek() {
+                            let elif_span = self.span();
                             self.advance();
                             if self.next() != Some(TokenType::LeftParen) {
                                 return Err(
@@ -187,12 +254,14 @@ impl Parser {
                                     TokenType::Elif,
                                     Some(Box::new(elif_cond)),
                                     statements,
+                                    elif_span,
                                 ))
                             } else {
                                 return Err("Elif block not closed".to_string());
                             }
                         }
                         if self.peek() == Some(TokenType::Else) {
+                            let else_span = self.span();
                             self.advance();
                             if self.next() != Some(TokenType::LeftCurly) {
                                 return Err("Else bloc must start with curly braces".to_string());
@@ -201,13 +270,14 @@ impl Parser {
                             if self.next() != Some(TokenType::RightCurly) {
                                 return Err("Else block not closed".to_string());
                             }
-                            res.push(ParseNode::new(TokenType::Else, None, statements))
+                            res.push(ParseNode::new(TokenType::Else, None, statements, else_span))
                         }
                         nodes.append(&mut res);
                         Ok(ParseNode::new(
                             TokenType::If,
                             Some(Box::new(if_cond)),
                             nodes,
+                            span,
                         ))
                     } else {
                         Err("If condition must start with an open paranthesis".to_string())
@@ -221,7 +291,12 @@ impl Parser {
                     if self.next() != Some(TokenType::RightParen) {
                         return Err("Right paranthesis missing in function call".to_string());
                     }
-                    Ok(ParseNode::new(TokenType::Print, None, vec![print_node]))
+                    Ok(ParseNode::new(
+                        TokenType::Print,
+                        None,
+                        vec![print_node],
+                        span,
+                    ))
                 }
                 TokenType::Fn(_) => {
                     if let Some(TokenType::Identifier(id)) = self.next() {
@@ -229,14 +304,20 @@ impl Parser {
                             return Err("Open paranthesis missing".to_string());
                         }
                         let mut parameters = vec![];
-                        let mut new_env = HashSet::<TokenType>::new();
+                        let parameters_span = self.span();
+                        // The body can use the function itself and every
+                        // name declared around it so far.
+                        env.insert(TokenType::Identifier(id.clone()));
+                        let mut new_env = env.clone();
                         while let Some(TokenType::Identifier(id)) = self.peek() {
+                            let param_span = self.span();
                             self.advance();
                             new_env.insert(TokenType::Identifier(id.clone()));
                             parameters.push(ParseNode::new(
                                 TokenType::Identifier(id),
                                 None,
                                 vec![],
+                                param_span,
                             ));
                         }
                         if self.next() != Some(TokenType::RightParen) {
@@ -255,8 +336,10 @@ impl Parser {
                                 TokenType::Parameters,
                                 None,
                                 parameters,
+                                parameters_span,
                             ))),
                             nodes,
+                            span,
                         ))
                         // Ok(ParseNode::new(
                         //     TokenType::Identifier(id),
@@ -267,27 +350,68 @@ impl Parser {
                         Err("Identifier missing".to_string())
                     }
                 }
+                TokenType::Identifier(id) if self.peek() == Some(TokenType::LeftParen) => {
+                    self.parse_call(id, span, env)
+                }
+                TokenType::Return => {
+                    let value = match self.peek() {
+                        Some(TokenType::Number(_))
+                        | Some(TokenType::StringLiteral(_))
+                        | Some(TokenType::Identifier(_))
+                        | Some(TokenType::Operator(_)) => vec![self.parse_expr(0, env)?],
+                        _ => vec![],
+                    };
+                    Ok(ParseNode::new(TokenType::Return, None, value, span))
+                }
                 _ => Err(format!("Invalid token {:?}", t)),
             },
             _ => Err("Couldn't be parsed".to_string()),
         }
     }
 
-    fn infix_binding_power(op: &Operator) -> (u8, u8) {
+    /// Parses the argument list of a call whose name has already been consumed.
+    fn parse_call(
+        &mut self,
+        id: String,
+        span: Span,
+        env: &HashSet<TokenType>,
+    ) -> Result<ParseNode, String> {
+        if self.next() != Some(TokenType::LeftParen) {
+            return Err("Left paranthesis missing in function call".to_string());
+        }
+        let mut args = vec![];
+        while let Some(t) = self.peek() {
+            if t == TokenType::RightParen {
+                break;
+            }
+            args.push(self.parse_expr(0, env)?);
+        }
+        if self.next() != Some(TokenType::RightParen) {
+            return Err("Right paranthesis missing in function call".to_string());
+        }
+        Ok(ParseNode::new(
+            TokenType::Call(FnInfo::new(TokenType::Identifier(id))),
+            None,
+            args,
+            span,
+        ))
+    }
+
+    fn infix_binding_power(op: &Operator) -> Result<(u8, u8), String> {
         match op {
-            Operator::Equality => (9, 10),
-            Operator::LessThan | Operator::LessThanEqual => (11, 12),
-            Operator::GreaterThan | Operator::GreaterThanEqual => (13, 14),
-            Operator::Plus | Operator::Minus => (15, 16),
-            Operator::Multiply | Operator::Divide => (17, 18),
-            _ => panic!("Bad"),
+            Operator::Equality => Ok((9, 10)),
+            Operator::LessThan | Operator::LessThanEqual => Ok((11, 12)),
+            Operator::GreaterThan | Operator::GreaterThanEqual => Ok((13, 14)),
+            Operator::Plus | Operator::Minus => Ok((15, 16)),
+            Operator::Multiply | Operator::Divide => Ok((17, 18)),
+            _ => Err(format!("Invalid operator {} in expression", op)),
         }
     }
 
-    fn prefix_binding_power(op: &Operator) -> u8 {
+    fn prefix_binding_power(op: &Operator) -> Result<u8, String> {
         match op {
-            Operator::Plus | Operator::Minus => 5,
-            _ => panic!("Bad operator {:?}", op),
+            Operator::Plus | Operator::Minus => Ok(5),
+            _ => Err(format!("Invalid prefix operator {}", op)),
         }
     }
 
@@ -295,43 +419,54 @@ impl Parser {
         let mut lhs = match self.next_with_line() {
             Some(
                 t @ Token {
-                    token: TokenType::Number(_),
-                    line_no: _,
+                    token: TokenType::Number(_) | TokenType::StringLiteral(_),
+                    ..
                 },
-            ) => ParseNode::new(t.token, None, vec![]),
+            ) => ParseNode::new(t.token, None, vec![], t.span),
+            Some(Token {
+                token: TokenType::Identifier(id),
+                span,
+            }) if self.peek() == Some(TokenType::LeftParen) => self.parse_call(id, span, env)?,
             Some(
                 t @ Token {
                     token: TokenType::Identifier(_),
-                    line_no: _,
+                    ..
                 },
             ) => {
-                if env.get(&t.token).is_none() {
-                    colour::dark_red_ln!("{:?} used before declaration", t);
+                if let (None, TokenType::Identifier(id)) = (env.get(&t.token), &t.token) {
+                    let message = format!("'{}' used before declaration", id);
+                    self.warnings.push((t.span, message));
                 }
-                ParseNode::new(t.token, None, vec![])
+                ParseNode::new(t.token, None, vec![], t.span)
             }
             Some(Token {
                 token: TokenType::Operator(op),
-                line_no: _,
+                span,
             }) => {
-                let r_bp = Parser::prefix_binding_power(&op);
+                let r_bp = Parser::prefix_binding_power(&op)?;
                 let rhs = self.parse_expr(r_bp, env)?;
-                ParseNode::new(TokenType::Operator(op), None, vec![rhs])
+                ParseNode::new(TokenType::Operator(op), None, vec![rhs], span)
+            }
+            t => {
+                return Err(format!(
+                    "Expected an expression, found {:?}",
+                    t.map(|t| t.token)
+                ))
             }
-            _ => panic!("Bad lhs"),
         };
         while let Some(op) = self.peek() {
+            let span = self.span();
             let op = match op {
                 TokenType::Operator(x) => x,
                 _ => break,
             };
-            let (l_bp, r_bp) = Parser::infix_binding_power(&op);
+            let (l_bp, r_bp) = Parser::infix_binding_power(&op)?;
             if l_bp < cur_bp {
                 break;
             }
             self.advance();
             let rhs = self.parse_expr(r_bp, env)?;
-            lhs = ParseNode::new(TokenType::Operator(op.clone()), None, vec![lhs, rhs]);
+            lhs = ParseNode::new(TokenType::Operator(op.clone()), None, vec![lhs, rhs], span);
         }
         Ok(lhs)
     }
@@ -0,0 +1,202 @@
+use crate::interpret::{Interpreter, Value};
+use crate::lexer::{Lexer, Token, TokenType};
+use crate::parser::{ParseNode, Parser};
+use crate::sexp;
+use crate::transpile::{self, Transpiler};
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "Enter statements or expressions. Blocks continue until every { is closed.
+  :ast     S expressions of the last input
+  :tokens  lexer tokens of the last input
+  :py      Python transpilation of the last input
+  :help    show this message
+  :quit    exit";
+
+enum Input {
+    Expression(ParseNode),
+    Statements(Vec<ParseNode>),
+}
+
+pub struct Repl {
+    interpreter: Interpreter,
+    env: HashSet<TokenType>,
+    last: String,
+}
+
+/// Number of `{` not yet closed by a `}`.
+fn open_blocks(tokens: &[Token]) -> isize {
+    tokens.iter().fold(0, |depth, t| match t.token {
+        TokenType::LeftCurly => depth + 1,
+        TokenType::RightCurly => depth - 1,
+        _ => depth,
+    })
+}
+
+/// Anything that does not start with a keyword is read as an expression so
+/// its value can be echoed.
+fn parse(tokens: Vec<Token>, env: &mut HashSet<TokenType>) -> Result<Input, String> {
+    let is_expression = matches!(
+        tokens.first().map(|t| &t.token),
+        Some(TokenType::Number(_))
+            | Some(TokenType::StringLiteral(_))
+            | Some(TokenType::Identifier(_))
+            | Some(TokenType::Operator(_))
+    );
+    let mut parser = Parser::new(tokens);
+    let res = if is_expression {
+        parser.parse_expression(env).map(Input::Expression)
+    } else {
+        parser.parse_in(env).map(Input::Statements)
+    };
+    for (span, message) in parser.warnings.iter() {
+        colour::dark_red_ln!("{} at {}", message, span);
+    }
+    res
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+            env: HashSet::new(),
+            last: String::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        println!("asdf repl, :help for commands");
+        loop {
+            print!(">> ");
+            io::stdout().flush()?;
+            let mut source = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            match source.trim() {
+                "" => continue,
+                ":quit" | ":q" => break,
+                ":help" => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                ":tokens" | ":ast" | ":py" => {
+                    self.command(source.trim());
+                    continue;
+                }
+                x if x.starts_with(':') => {
+                    println!("Unknown command {}, :help for commands", x);
+                    continue;
+                }
+                _ => {}
+            }
+            while open_blocks(&Lexer::new(&source).lex()) > 0 {
+                print!(".. ");
+                io::stdout().flush()?;
+                match lines.next() {
+                    Some(line) => {
+                        source.push('\n');
+                        source.push_str(&line?);
+                    }
+                    None => return Ok(()),
+                }
+            }
+            self.eval(&source);
+            self.last = source;
+        }
+        println!();
+        Ok(())
+    }
+
+    fn eval(&mut self, source: &str) {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.lex();
+        for (span, message) in lexer.errors.iter() {
+            println!("{} at {}", message, span);
+        }
+        let res = match parse(tokens, &mut self.env) {
+            Ok(Input::Expression(node)) => match self.interpreter.evaluate(&node) {
+                Ok(Value::None) => Ok(()),
+                Ok(value) => {
+                    println!("{}", value);
+                    Ok(())
+                }
+                Err(x) => Err(x),
+            },
+            Ok(Input::Statements(nodes)) => self.interpreter.run(&nodes),
+            Err(x) => Err(x),
+        };
+        if let Err(x) = res {
+            colour::dark_red_ln!("{}", x);
+        }
+    }
+
+    fn command(&self, command: &str) {
+        match self.show(command) {
+            Ok(text) => print!("{}", text),
+            Err(x) => {
+                colour::dark_red_ln!("{}", x);
+            }
+        }
+    }
+
+    /// What `command` prints about the last input.
+    fn show(&self, command: &str) -> Result<String, String> {
+        let tokens = Lexer::new(&self.last).lex();
+        if command == ":tokens" {
+            return Ok(tokens.iter().map(|t| format!("{:?}\n", t)).collect());
+        }
+        // Reparse against a copy so inspecting an input does not declare
+        // anything twice.
+        let nodes = match parse(tokens, &mut self.env.clone())? {
+            // Backends only take statements, so an expression is shown as
+            // printing its value, which is what entering it does.
+            Input::Expression(node) if command == ":py" => {
+                vec![ParseNode {
+                    token: TokenType::Print,
+                    extra_info: None,
+                    span: node.span,
+                    children: vec![node],
+                }]
+            }
+            Input::Expression(node) => vec![node],
+            Input::Statements(nodes) => nodes,
+        };
+        if command == ":ast" {
+            return Ok(sexp::print(&nodes));
+        }
+        let python = transpile::backend("python", &transpile::Options::default())
+            .expect("Python backend is built in");
+        let outputs = Transpiler::new(nodes, python).transpile()?;
+        Ok(outputs
+            .into_iter()
+            .map(|output| format!("{}\n", output.contents))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(input: &str, command: &str) -> Result<String, String> {
+        let mut repl = Repl::new();
+        repl.eval("let x = 1");
+        repl.last = input.to_string();
+        repl.show(command)
+    }
+
+    #[test]
+    fn python_of_an_expression() {
+        assert_eq!(show("x + 1", ":py").unwrap(), "print(x + 1)\n\n");
+        assert_eq!(show("x + 1", ":ast").unwrap(), "(+ x 1)\n");
+    }
+
+    #[test]
+    fn python_of_statements() {
+        let python = show("let y = x * 2 print(y)", ":py").unwrap();
+        assert_eq!(python, "y = x * 2\nprint(y)\n\n");
+    }
+}
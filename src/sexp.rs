@@ -0,0 +1,514 @@
+use crate::json::quote;
+use crate::lexer::{FnInfo, Span, TokenType, KEYWORDS, OPERATORS};
+use crate::parser::ParseNode;
+use ordered_float::OrderedFloat;
+
+/*
+An S-expression form of the tree, which the printer writes and the reader
+turns back into the same tree. A program is a sequence of statements:
+
+    (let NAME EXPR)
+    (print EXPR)
+    (return) | (return EXPR)
+    (call NAME EXPR...)
+    (while EXPR STATEMENT...)
+    (if EXPR STATEMENT... (elif EXPR STATEMENT...)... [(else STATEMENT...)])
+    (fn NAME (NAME...) STATEMENT...)
+
+and expressions are numbers, strings in double quotes with the escapes of
+JSON, names, calls and operators applied to one or two operands, like
+(+ a 1) or (- x). `;` starts a comment that runs to the end of the line.
+
+Read trees have spans into the S-expression text: a form is located at its
+parenthesis and a name or literal at itself, except that a call is located
+at its name as it is when parsed from asdf.
+*/
+
+/// Writes `nodes` one statement per line, with bodies indented below.
+pub fn print(nodes: &[ParseNode]) -> String {
+    let mut out = String::new();
+    for node in nodes.iter() {
+        write(&mut out, node, 0);
+        out.push('\n');
+    }
+    out
+}
+
+fn write(out: &mut String, node: &ParseNode, level: usize) {
+    let head = match &node.token {
+        TokenType::Number(n) => return out.push_str(&n.to_string()),
+        TokenType::StringLiteral(s) => return out.push_str(&quote(s)),
+        TokenType::Identifier(x) => return out.push_str(x),
+        TokenType::Operator(op) => op.to_string(),
+        TokenType::Call(info) => format!("call {}", name(&info.name)),
+        TokenType::Let => "let".to_string(),
+        TokenType::Print => "print".to_string(),
+        TokenType::Return => "return".to_string(),
+        TokenType::While => "while".to_string(),
+        TokenType::If => "if".to_string(),
+        TokenType::Elif => "elif".to_string(),
+        TokenType::Else => "else".to_string(),
+        TokenType::Fn(info) => {
+            let params: Vec<&str> = node
+                .extra_info
+                .iter()
+                .flat_map(|p| p.children.iter())
+                .map(|p| name(&p.token))
+                .collect();
+            let info = info.as_ref().map_or("", |info| name(&info.name));
+            format!("fn {} ({})", info, params.join(" "))
+        }
+        token => format!("{:?}", token),
+    };
+    out.push('(');
+    out.push_str(&head);
+    let block = matches!(
+        node.token,
+        TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else | TokenType::Fn(_)
+    );
+    if let (Some(cond), false) = (&node.extra_info, matches!(node.token, TokenType::Fn(_))) {
+        out.push(' ');
+        write(out, cond, level);
+    }
+    for child in node.children.iter() {
+        match block {
+            true => {
+                out.push('\n');
+                out.push_str(&"  ".repeat(level + 1));
+                write(out, child, level + 1);
+            }
+            false => {
+                out.push(' ');
+                write(out, child, level);
+            }
+        }
+    }
+    out.push(')');
+}
+
+fn name(token: &TokenType) -> &str {
+    match token {
+        TokenType::Identifier(x) => x,
+        _ => "",
+    }
+}
+
+/// An S-expression as read, before it is checked against the grammar.
+enum Sexp {
+    Atom(String, Span),
+    Str(String, Span),
+    List(Vec<Sexp>, Span),
+}
+
+impl Sexp {
+    fn span(&self) -> Span {
+        match self {
+            Sexp::Atom(_, span) | Sexp::Str(_, span) | Sexp::List(_, span) => *span,
+        }
+    }
+}
+
+fn error<T>(span: Span, message: &str) -> Result<T, String> {
+    Err(format!("S-expression error at {}: {}", span, message))
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn mark(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    /// The text from `start` up to the current position.
+    fn since(&self, start: Span) -> Span {
+        Span {
+            end: self.pos,
+            ..start
+        }
+    }
+
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ';' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                c if c.is_whitespace() => {}
+                _ => return,
+            }
+            self.bump();
+        }
+    }
+
+    /// Reads the next S-expression, or None at the end of input or a `)`.
+    fn sexp(&mut self) -> Result<Option<Sexp>, String> {
+        self.skip_blank();
+        let start = self.mark();
+        Ok(Some(match self.peek() {
+            None | Some(')') => return Ok(None),
+            Some('(') => {
+                self.bump();
+                let mut items = vec![];
+                while let Some(item) = self.sexp()? {
+                    items.push(item);
+                }
+                if self.bump() != Some(')') {
+                    return error(start, "unclosed (");
+                }
+                Sexp::List(items, self.since(start))
+            }
+            Some('"') => {
+                self.bump();
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        None => return error(start, "unterminated string"),
+                        Some('"') => break,
+                        Some('\\') => match self.bump() {
+                            Some('n') => s.push('\n'),
+                            Some('r') => s.push('\r'),
+                            Some('t') => s.push('\t'),
+                            Some('u') => {
+                                let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                    Some(c) => s.push(c),
+                                    None => return error(start, "invalid \\u escape"),
+                                }
+                            }
+                            Some(c) => s.push(c),
+                            None => return error(start, "unterminated string"),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
+                Sexp::Str(s, self.since(start))
+            }
+            Some(_) => {
+                let mut atom = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';') {
+                        break;
+                    }
+                    atom.push(c);
+                    self.bump();
+                }
+                Sexp::Atom(atom, self.since(start))
+            }
+        }))
+    }
+}
+
+fn leaf(token: TokenType, span: Span) -> ParseNode {
+    ParseNode {
+        token,
+        extra_info: None,
+        children: vec![],
+        span,
+    }
+}
+
+/// A name that the lexer would read as an identifier.
+fn identifier(sexp: &Sexp) -> Result<ParseNode, String> {
+    match sexp {
+        Sexp::Atom(x, span) => {
+            let mut chars = x.chars();
+            let valid = chars.next().is_some_and(|c| c.is_alphabetic())
+                && chars.all(|c| c.is_alphanumeric() || c == '_')
+                && !KEYWORDS.iter().any(|(word, _)| word == x);
+            match valid {
+                true => Ok(leaf(TokenType::Identifier(x.clone()), *span)),
+                false => error(*span, &format!("'{}' is not a name", x)),
+            }
+        }
+        _ => error(sexp.span(), "expected a name"),
+    }
+}
+
+fn expression(sexp: &Sexp) -> Result<ParseNode, String> {
+    match sexp {
+        Sexp::Str(s, span) => Ok(leaf(TokenType::StringLiteral(s.clone()), *span)),
+        // Folding constants can leave negative numbers in a tree.
+        Sexp::Atom(x, span)
+            if x.strip_prefix('-')
+                .unwrap_or(x)
+                .starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            match x.parse::<f32>() {
+                Ok(n) => Ok(leaf(TokenType::Number(OrderedFloat(n)), *span)),
+                Err(_) => error(*span, &format!("invalid number {}", x)),
+            }
+        }
+        Sexp::Atom(..) => identifier(sexp),
+        Sexp::List(items, span) => {
+            let (head, args) = match items.split_first() {
+                Some((Sexp::Atom(head, _), args)) => (head.as_str(), args),
+                _ => return error(*span, "expected an operator or call"),
+            };
+            if head == "call" {
+                return call(args, *span);
+            }
+            let op = match OPERATORS.iter().find(|op| op.to_string() == head) {
+                Some(op) => op.clone(),
+                None => return error(*span, &format!("unknown operator {}", head)),
+            };
+            let valid = match args.len() {
+                1 => matches!(head, "+" | "-"),
+                2 => head != "=",
+                _ => false,
+            };
+            if !valid {
+                return error(
+                    *span,
+                    &format!("{} can't take {} operands", head, args.len()),
+                );
+            }
+            Ok(ParseNode {
+                token: TokenType::Operator(op),
+                extra_info: None,
+                children: args.iter().map(expression).collect::<Result<_, _>>()?,
+                span: *span,
+            })
+        }
+    }
+}
+
+fn call(args: &[Sexp], span: Span) -> Result<ParseNode, String> {
+    let (callee, args) = match args.split_first() {
+        Some(split) => split,
+        None => return error(span, "call needs a function name"),
+    };
+    let callee = identifier(callee)?;
+    Ok(ParseNode {
+        token: TokenType::Call(FnInfo::new(callee.token)),
+        extra_info: None,
+        children: args.iter().map(expression).collect::<Result<_, _>>()?,
+        span: callee.span,
+    })
+}
+
+fn statements(sexps: &[Sexp]) -> Result<Vec<ParseNode>, String> {
+    sexps.iter().map(statement).collect()
+}
+
+fn statement(sexp: &Sexp) -> Result<ParseNode, String> {
+    let (items, span) = match sexp {
+        Sexp::List(items, span) => (items, *span),
+        _ => return error(sexp.span(), "expected a statement"),
+    };
+    let (head, args) = match items.split_first() {
+        Some((Sexp::Atom(head, _), args)) => (head.as_str(), args),
+        _ => return error(span, "expected a statement"),
+    };
+    let node = |token, extra_info: Option<ParseNode>, children| ParseNode {
+        token,
+        extra_info: extra_info.map(Box::new),
+        children,
+        span,
+    };
+    match (head, args) {
+        ("let", [target, value]) => Ok(node(
+            TokenType::Let,
+            None,
+            vec![identifier(target)?, expression(value)?],
+        )),
+        ("print", [value]) => Ok(node(TokenType::Print, None, vec![expression(value)?])),
+        ("return", []) => Ok(node(TokenType::Return, None, vec![])),
+        ("return", [value]) => Ok(node(TokenType::Return, None, vec![expression(value)?])),
+        ("call", _) => call(args, span),
+        ("while", [cond, body @ ..]) => Ok(node(
+            TokenType::While,
+            Some(expression(cond)?),
+            statements(body)?,
+        )),
+        ("if", [cond, rest @ ..]) => {
+            let split = rest
+                .iter()
+                .position(|s| branch(s).is_some())
+                .unwrap_or(rest.len());
+            let (body, branches) = rest.split_at(split);
+            let mut children = statements(body)?;
+            for (i, sexp) in branches.iter().enumerate() {
+                let last = i + 1 == branches.len();
+                match (branch(sexp), sexp) {
+                    (Some("elif"), Sexp::List(items, span)) => match &items[1..] {
+                        [cond, body @ ..] => children.push(ParseNode {
+                            token: TokenType::Elif,
+                            extra_info: Some(Box::new(expression(cond)?)),
+                            children: statements(body)?,
+                            span: *span,
+                        }),
+                        _ => return error(*span, "elif needs a condition"),
+                    },
+                    (Some("else"), Sexp::List(items, span)) if last => children.push(ParseNode {
+                        token: TokenType::Else,
+                        extra_info: None,
+                        children: statements(&items[1..])?,
+                        span: *span,
+                    }),
+                    (Some("else"), _) => return error(sexp.span(), "else must be the last branch"),
+                    _ => return error(sexp.span(), "expected elif or else"),
+                }
+            }
+            Ok(node(TokenType::If, Some(expression(cond)?), children))
+        }
+        ("fn", [name, Sexp::List(params, params_span), body @ ..]) => {
+            let name = identifier(name)?;
+            let parameters = ParseNode {
+                token: TokenType::Parameters,
+                extra_info: None,
+                children: params.iter().map(identifier).collect::<Result<_, _>>()?,
+                span: *params_span,
+            };
+            Ok(node(
+                TokenType::Fn(Some(FnInfo::new(name.token))),
+                Some(parameters),
+                statements(body)?,
+            ))
+        }
+        ("elif" | "else", _) => error(span, &format!("{} outside if", head)),
+        ("let" | "print" | "return" | "while" | "if" | "fn", _) => {
+            error(span, &format!("malformed {}", head))
+        }
+        _ => error(span, "expected a statement"),
+    }
+}
+
+/// The head of an `elif` or `else` form.
+fn branch(sexp: &Sexp) -> Option<&'static str> {
+    match sexp {
+        Sexp::List(items, _) => match items.first() {
+            Some(Sexp::Atom(head, _)) if head == "elif" => Some("elif"),
+            Some(Sexp::Atom(head, _)) if head == "else" => Some("else"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reads a program written as S-expressions.
+pub fn read(text: &str) -> Result<Vec<ParseNode>, String> {
+    let mut reader = Reader {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+    };
+    let mut nodes = vec![];
+    while let Some(sexp) = reader.sexp()? {
+        nodes.push(statement(&sexp)?);
+    }
+    match reader.peek() {
+        Some(_) => error(reader.mark(), "unexpected )"),
+        None => Ok(nodes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    const PROGRAM: &str = "let s = \"tab\\there \\\"quoted\\\"\"
+fn fib(n) {
+    if (n < 2) {
+        return n
+    } elif (n == 2) {
+        return 1
+    } else {
+        return fib(n - 1) + fib(n - 2)
+    }
+}
+let i = 0
+while (i < 3) {
+    print(fib(i) * -1)
+    let i = i + 1
+}
+fn nothing() {
+    return
+}
+nothing()
+print(s)
+";
+
+    #[test]
+    fn round_trip() {
+        let nodes = Parser::new(Lexer::new(PROGRAM).lex()).parse().unwrap();
+        let printed = print(&nodes);
+        assert!(printed.contains("\n    (elif (== n 2)\n"), "{}", printed);
+        assert_eq!(print(&read(&printed).unwrap()), printed);
+    }
+
+    #[test]
+    fn reader_errors() {
+        assert_eq!(
+            read("(print 1)\n(let x (+ 1 2)").unwrap_err(),
+            "S-expression error at 2:1: unclosed ("
+        );
+        assert_eq!(
+            read("(print 1))").unwrap_err(),
+            "S-expression error at 1:10: unexpected )"
+        );
+        assert_eq!(
+            read("(if x (else (print 1)) (elif y))").unwrap_err(),
+            "S-expression error at 1:7: else must be the last branch"
+        );
+        assert_eq!(
+            read("(let while 1)").unwrap_err(),
+            "S-expression error at 1:6: 'while' is not a name"
+        );
+        assert_eq!(
+            read("(fn f (x return))").unwrap_err(),
+            "S-expression error at 1:10: 'return' is not a name"
+        );
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        let nodes = read("(print \"caf\\u00e9 \\u03c0\")").unwrap();
+        assert_eq!(
+            nodes[0].children[0].token,
+            TokenType::StringLiteral("café π".to_string())
+        );
+        for text in [
+            "(print \"\\u12\")",
+            "(print \"\\ud800\")",
+            "(print \"\\uzzzz\")",
+        ] {
+            assert_eq!(
+                read(text).unwrap_err(),
+                "S-expression error at 1:8: invalid \\u escape",
+                "{}",
+                text
+            );
+        }
+    }
+}
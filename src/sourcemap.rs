@@ -0,0 +1,331 @@
+use crate::json::Json;
+use crate::transpile::Output;
+use std::path::Path;
+
+/*
+Source Map revision 3, as understood by browsers and Node:
+
+{"version":3,"file":OUT,"sources":[SRC],"names":[],"mappings":M}
+
+M lists generated lines separated by `;`, each holding `,` separated
+segments. A segment is four base64 VLQ numbers: the generated column, the
+source index, the source line and the source column, all 0-based and
+relative to the same field of the previous segment. The generated column
+restarts at 0 on every line. Columns count UTF-16 code units, as in
+JavaScript strings, while asdf spans count characters, so source columns
+are converted using the source text.
+*/
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_vlq(value: i64, out: &mut String) {
+    let mut rest = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = rest & 0x1f;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 0x20;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
+
+fn decode_vlq(segment: &str) -> Result<Vec<i64>, String> {
+    let mut values = vec![];
+    let mut value = 0;
+    let mut shift = 0;
+    for c in segment.bytes() {
+        let digit = BASE64
+            .iter()
+            .position(|b| *b == c)
+            .ok_or_else(|| format!("Invalid base64 digit {:?} in mappings", c as char))?
+            as i64;
+        value |= (digit & 0x1f) << shift;
+        if digit & 0x20 != 0 {
+            shift += 5;
+            continue;
+        }
+        values.push(if value & 1 == 1 {
+            -(value >> 1)
+        } else {
+            value >> 1
+        });
+        value = 0;
+        shift = 0;
+    }
+    Ok(values)
+}
+
+/// The UTF-16 length of the first `chars` characters of `line`.
+fn utf16_col(line: &str, chars: usize) -> usize {
+    line.chars().take(chars).map(char::len_utf16).sum()
+}
+
+/// The number of characters of `line` in its first `units` UTF-16 code
+/// units.
+fn char_col(line: &str, units: usize) -> usize {
+    let mut seen = 0;
+    line.chars()
+        .take_while(|c| {
+            seen += c.len_utf16();
+            seen <= units
+        })
+        .count()
+}
+
+/// Writes the source map of `output`, which was generated from the file
+/// `source` containing `text`.
+pub fn encode(output: &Output, source: &str, text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut mappings = String::new();
+    let mut line = 0;
+    let mut first = true;
+    let (mut prev_col, mut prev_line, mut prev_src_col) = (0, 0, 0);
+    for mapping in output.mappings.iter() {
+        while line < mapping.line {
+            mappings.push(';');
+            line += 1;
+            first = true;
+            prev_col = 0;
+        }
+        if !first {
+            mappings.push(',');
+        }
+        first = false;
+        let src_line = mapping.span.line.saturating_sub(1) as i64;
+        let src_col = utf16_col(
+            lines.get(src_line as usize).unwrap_or(&""),
+            mapping.span.col.saturating_sub(1),
+        ) as i64;
+        encode_vlq(mapping.col as i64 - prev_col, &mut mappings);
+        encode_vlq(0, &mut mappings);
+        encode_vlq(src_line - prev_line, &mut mappings);
+        encode_vlq(src_col - prev_src_col, &mut mappings);
+        prev_col = mapping.col as i64;
+        prev_line = src_line;
+        prev_src_col = src_col;
+    }
+    Json::Object(vec![
+        ("version".to_string(), Json::Number(3.0)),
+        ("file".to_string(), Json::Str(output.filename.clone())),
+        (
+            "sources".to_string(),
+            Json::Array(vec![Json::Str(source.to_string())]),
+        ),
+        ("names".to_string(), Json::Array(vec![])),
+        ("mappings".to_string(), Json::Str(mappings)),
+    ])
+    .to_string()
+}
+
+/// A position in one of the sources, 1-based. The column counts UTF-16 code
+/// units like the map itself.
+pub struct Location<'a> {
+    pub source: &'a str,
+    pub line: usize,
+    pub col: usize,
+}
+
+pub struct SourceMap {
+    pub file: String,
+    sources: Vec<String>,
+    /// Segments of each generated line as (column, source, line, column),
+    /// all 0-based and absolute.
+    lines: Vec<Vec<(usize, usize, usize, usize)>>,
+}
+
+impl SourceMap {
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let json = Json::parse(text)?;
+        if json.get("version") != Some(&Json::Number(3.0)) {
+            return Err("Only version 3 source maps are supported".to_string());
+        }
+        let file = json
+            .get("file")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let sources = json
+            .get("sources")
+            .and_then(Json::as_array)
+            .ok_or("Source map has no sources")?
+            .iter()
+            .map(|s| s.as_str().map(str::to_string).ok_or("Invalid source name"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mappings = json
+            .get("mappings")
+            .and_then(Json::as_str)
+            .ok_or("Source map has no mappings")?;
+        let mut lines = vec![];
+        let (mut source, mut src_line, mut src_col) = (0i64, 0i64, 0i64);
+        for line in mappings.split(';') {
+            let mut segments = vec![];
+            let mut col = 0i64;
+            for segment in line.split(',').filter(|s| !s.is_empty()) {
+                let fields = decode_vlq(segment)?;
+                col += fields.first().ok_or("Truncated mapping segment")?;
+                // Segments without a source position do not map anywhere.
+                if let [_, s, l, c, ..] = fields[..] {
+                    source += s;
+                    src_line += l;
+                    src_col += c;
+                    if col < 0 || source < 0 || src_line < 0 || src_col < 0 {
+                        return Err(format!("Invalid mapping segment {}", segment));
+                    }
+                    segments.push((
+                        col as usize,
+                        source as usize,
+                        src_line as usize,
+                        src_col as usize,
+                    ));
+                }
+            }
+            lines.push(segments);
+        }
+        Ok(SourceMap {
+            file,
+            sources,
+            lines,
+        })
+    }
+
+    /// The source location of a 1-based generated line. Lines without a
+    /// segment of their own, such as the continuation of a statement, map to
+    /// the closest segment above them.
+    pub fn lookup(&self, line: usize) -> Option<Location<'_>> {
+        let index = line.checked_sub(1)?.min(self.lines.len().checked_sub(1)?);
+        let (_, source, line, col) = self.lines[..=index]
+            .iter()
+            .rev()
+            .find_map(|segments| segments.first())?;
+        Some(Location {
+            source: self.sources.get(*source)?,
+            line: line + 1,
+            col: col + 1,
+        })
+    }
+}
+
+/// Rewrites the frames of a Python traceback that point into the generated
+/// file so they point at the asdf source instead. The code line Python
+/// prints under each frame is replaced by the asdf line when the source
+/// can be read, and any `^` markers below it are dropped since they
+/// underline the generated code.
+pub fn translate_traceback(map: &SourceMap, traceback: &str) -> String {
+    let generated = Path::new(&map.file).file_name();
+    let mut out = vec![];
+    let mut lines = traceback.lines().peekable();
+    while let Some(line) = lines.next() {
+        let frame = line
+            .trim_start()
+            .strip_prefix("File \"")
+            .and_then(|rest| rest.split_once("\", line "))
+            .and_then(|(file, rest)| {
+                let digits = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let number = rest[..digits].parse::<usize>().ok()?;
+                Some((file, number, &rest[digits..]))
+            });
+        let location = match frame {
+            Some((file, number, rest)) if Path::new(file).file_name() == generated => {
+                map.lookup(number).map(|l| (l, rest))
+            }
+            _ => None,
+        };
+        let (location, rest) = match location {
+            Some(found) => found,
+            None => {
+                out.push(line.to_string());
+                continue;
+            }
+        };
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let source = std::fs::read_to_string(location.source).ok();
+        let source_line = source
+            .as_ref()
+            .and_then(|s| s.lines().nth(location.line - 1));
+        // Report the column in characters, like asdf's own errors.
+        let col = match source_line {
+            Some(source_line) => char_col(source_line, location.col - 1) + 1,
+            None => location.col,
+        };
+        out.push(format!(
+            "{}File \"{}\", line {}, column {}{}",
+            indent, location.source, location.line, col, rest
+        ));
+        let code_indent = format!("{}  ", indent);
+        let mut replaced = false;
+        while let Some(next) =
+            lines.next_if(|l| l.starts_with(&code_indent) && !l.trim_start().starts_with("File \""))
+        {
+            let is_marker = next.trim().chars().all(|c| matches!(c, '^' | '~' | ' '));
+            if is_marker && source_line.is_some() {
+                continue;
+            }
+            match source_line {
+                Some(source_line) if !replaced => {
+                    out.push(format!("{}{}", code_indent, source_line.trim()));
+                    replaced = true;
+                }
+                Some(_) => {}
+                None => out.push(next.to_string()),
+            }
+        }
+    }
+    let mut res = out.join("\n");
+    if traceback.ends_with('\n') {
+        res.push('\n');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Span;
+    use crate::transpile::Mapping;
+
+    #[test]
+    fn columns_count_utf16_code_units() {
+        // The print statement starts at character 13 but UTF-16 unit 15,
+        // since each emoji is a surrogate pair.
+        let text = "let s = \"😀😀\" print(s)\n";
+        let print = Span {
+            start: 13,
+            end: 21,
+            line: 1,
+            col: 14,
+        };
+        let output = Output {
+            filename: "result.mjs".to_string(),
+            contents: "let s;\ns = \"😀😀\"; console.log(s);\n".to_string(),
+            mappings: vec![Mapping {
+                line: 1,
+                col: 0,
+                span: print,
+            }],
+        };
+        let encoded = encode(&output, "s.asdf", text);
+        assert!(encoded.contains("\"mappings\":\";AAAe\""), "{}", encoded);
+        let map = SourceMap::parse(&encoded).unwrap();
+        let location = map.lookup(2).unwrap();
+        assert_eq!((location.line, location.col), (1, 16));
+        assert_eq!(char_col("let s = \"😀😀\" print(s)", location.col - 1), 13);
+    }
+
+    #[test]
+    fn utf16_conversions() {
+        assert_eq!(utf16_col("aé😀b", 4), 5);
+        assert_eq!(utf16_col("aé😀b", 2), 2);
+        assert_eq!(char_col("aé😀b", 4), 3);
+        assert_eq!(char_col("aé😀b", 3), 2);
+    }
+}
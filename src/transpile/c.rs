@@ -0,0 +1,660 @@
+use super::{
+    arities, collect_functions, has_return, identifier, signature, Backend, CodeWriter, Options,
+    Output,
+};
+use crate::lexer::{Operator, TokenType};
+use crate::optimize::remove_dead_stores;
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+use std::collections::HashMap;
+
+const RUNTIME_HEADER: &str = "asdf_runtime.h";
+
+/// Dynamically typed values and the operations on them, shipped next to the
+/// generated source so the output only needs a C99 compiler and libc.
+const RUNTIME: &str = r#"#ifndef ASDF_RUNTIME_H
+#define ASDF_RUNTIME_H
+
+/* Only standard C names, which asdf identifiers are renamed around, not
+   POSIX or GNU extensions like index(). */
+#define _ISOC99_SOURCE
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef enum { ASDF_NONE, ASDF_NUMBER, ASDF_STRING, ASDF_BOOL } asdf_kind;
+
+typedef struct {
+    asdf_kind kind;
+    union {
+        double number;
+        const char *string;
+        int boolean;
+    } as;
+} asdf_value;
+
+static inline asdf_value asdf_none(void) {
+    asdf_value v;
+    v.kind = ASDF_NONE;
+    v.as.number = 0;
+    return v;
+}
+
+static inline asdf_value asdf_number(double n) {
+    asdf_value v;
+    v.kind = ASDF_NUMBER;
+    v.as.number = n;
+    return v;
+}
+
+static inline asdf_value asdf_string(const char *s) {
+    asdf_value v;
+    v.kind = ASDF_STRING;
+    v.as.string = s;
+    return v;
+}
+
+static inline asdf_value asdf_bool(int b) {
+    asdf_value v;
+    v.kind = ASDF_BOOL;
+    v.as.boolean = b != 0;
+    return v;
+}
+
+static inline const char *asdf_type_name(asdf_value v) {
+    switch (v.kind) {
+    case ASDF_NUMBER: return "number";
+    case ASDF_STRING: return "string";
+    case ASDF_BOOL: return "bool";
+    default: return "None";
+    }
+}
+
+static inline void asdf_type_error(const char *op, asdf_value a, asdf_value b) {
+    fprintf(stderr, "Runtime error: unsupported operand types for %s: '%s' and '%s'\n",
+            op, asdf_type_name(a), asdf_type_name(b));
+    exit(1);
+}
+
+static inline int asdf_truthy(asdf_value v) {
+    switch (v.kind) {
+    case ASDF_NUMBER: return v.as.number != 0;
+    case ASDF_STRING: return v.as.string[0] != '\0';
+    case ASDF_BOOL: return v.as.boolean;
+    default: return 0;
+    }
+}
+
+static inline asdf_value asdf_add(asdf_value a, asdf_value b) {
+    if (a.kind == ASDF_NUMBER && b.kind == ASDF_NUMBER) {
+        return asdf_number(a.as.number + b.as.number);
+    }
+    if (a.kind == ASDF_STRING && b.kind == ASDF_STRING) {
+        size_t la = strlen(a.as.string), lb = strlen(b.as.string);
+        char *s = malloc(la + lb + 1);
+        if (s == NULL) {
+            fprintf(stderr, "Runtime error: out of memory\n");
+            exit(1);
+        }
+        memcpy(s, a.as.string, la);
+        memcpy(s + la, b.as.string, lb + 1);
+        return asdf_string(s);
+    }
+    asdf_type_error("+", a, b);
+    return asdf_none();
+}
+
+#define ASDF_ARITHMETIC(name, op) \
+    static inline asdf_value name(asdf_value a, asdf_value b) { \
+        if (a.kind != ASDF_NUMBER || b.kind != ASDF_NUMBER) { \
+            asdf_type_error(#op, a, b); \
+        } \
+        return asdf_number(a.as.number op b.as.number); \
+    }
+
+ASDF_ARITHMETIC(asdf_sub, -)
+ASDF_ARITHMETIC(asdf_mul, *)
+
+static inline asdf_value asdf_div(asdf_value a, asdf_value b) {
+    if (a.kind != ASDF_NUMBER || b.kind != ASDF_NUMBER) {
+        asdf_type_error("/", a, b);
+    }
+    if (b.as.number == 0) {
+        fprintf(stderr, "Runtime error: division by zero\n");
+        exit(1);
+    }
+    return asdf_number(a.as.number / b.as.number);
+}
+
+#define ASDF_COMPARISON(name, op) \
+    static inline asdf_value name(asdf_value a, asdf_value b) { \
+        if (a.kind == ASDF_NUMBER && b.kind == ASDF_NUMBER) { \
+            return asdf_bool(a.as.number op b.as.number); \
+        } \
+        if (a.kind == ASDF_STRING && b.kind == ASDF_STRING) { \
+            return asdf_bool(strcmp(a.as.string, b.as.string) op 0); \
+        } \
+        asdf_type_error(#op, a, b); \
+        return asdf_none(); \
+    }
+
+ASDF_COMPARISON(asdf_lt, <)
+ASDF_COMPARISON(asdf_le, <=)
+ASDF_COMPARISON(asdf_gt, >)
+ASDF_COMPARISON(asdf_ge, >=)
+
+static inline asdf_value asdf_eq(asdf_value a, asdf_value b) {
+    if (a.kind != b.kind) {
+        return asdf_bool(0);
+    }
+    switch (a.kind) {
+    case ASDF_NUMBER: return asdf_bool(a.as.number == b.as.number);
+    case ASDF_STRING: return asdf_bool(strcmp(a.as.string, b.as.string) == 0);
+    case ASDF_BOOL: return asdf_bool(a.as.boolean == b.as.boolean);
+    default: return asdf_bool(1);
+    }
+}
+
+static inline asdf_value asdf_neg(asdf_value a) {
+    if (a.kind != ASDF_NUMBER) {
+        asdf_type_error("-", a, a);
+    }
+    return asdf_number(-a.as.number);
+}
+
+static inline asdf_value asdf_pos(asdf_value a) {
+    if (a.kind != ASDF_NUMBER) {
+        asdf_type_error("+", a, a);
+    }
+    return a;
+}
+
+/* Prints whole numbers without a fraction and everything else with the
+   fewest digits that read back to the same value. */
+static inline void asdf_print(asdf_value v) {
+    char buf[32];
+    int precision;
+    switch (v.kind) {
+    case ASDF_NUMBER:
+        if (v.as.number == (long long)v.as.number && v.as.number < 1e16 && v.as.number > -1e16) {
+            printf("%lld\n", (long long)v.as.number);
+            break;
+        }
+        for (precision = 1; precision < 17; precision++) {
+            snprintf(buf, sizeof buf, "%.*g", precision, v.as.number);
+            if (strtod(buf, NULL) == v.as.number) {
+                break;
+            }
+        }
+        snprintf(buf, sizeof buf, "%.*g", precision, v.as.number);
+        printf("%s\n", buf);
+        break;
+    case ASDF_STRING: printf("%s\n", v.as.string); break;
+    case ASDF_BOOL: printf("%s\n", v.as.boolean ? "True" : "False"); break;
+    default: printf("None\n"); break;
+    }
+}
+
+#endif
+"#;
+
+/// C keywords, everything declared by the standard headers a program is
+/// likely to include, and the runtime header's own names.
+#[rustfmt::skip]
+const RESERVED: &[&str] = &[
+    // Keywords, up to C23
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long",
+    "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct",
+    "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "_Bool",
+    "_Complex", "_Imaginary", "_Alignas", "_Alignof", "_Atomic", "_Generic",
+    "_Noreturn", "_Static_assert", "_Thread_local", "alignas", "alignof", "bool",
+    "constexpr", "false", "nullptr", "static_assert", "thread_local", "true", "typeof",
+    "typeof_unqual", "main",
+    // stdio.h
+    "FILE", "fpos_t", "size_t", "NULL", "_IOFBF", "_IOLBF", "_IONBF", "BUFSIZ", "EOF",
+    "FOPEN_MAX", "FILENAME_MAX", "L_tmpnam", "SEEK_CUR", "SEEK_END", "SEEK_SET",
+    "TMP_MAX", "stderr", "stdin", "stdout", "remove", "rename", "tmpfile", "tmpnam",
+    "fclose", "fflush", "fopen", "freopen", "setbuf", "setvbuf", "fprintf", "fscanf",
+    "printf", "scanf", "snprintf", "sprintf", "sscanf", "vfprintf", "vfscanf",
+    "vprintf", "vscanf", "vsnprintf", "vsprintf", "vsscanf", "fgetc", "fgets", "fputc",
+    "fputs", "getc", "getchar", "gets", "putc", "putchar", "puts", "ungetc", "fread",
+    "fwrite", "fgetpos", "fseek", "fsetpos", "ftell", "rewind", "clearerr", "feof",
+    "ferror", "perror",
+    // stdlib.h
+    "wchar_t", "div_t", "ldiv_t", "lldiv_t", "EXIT_FAILURE", "EXIT_SUCCESS", "RAND_MAX",
+    "MB_CUR_MAX", "atof", "atoi", "atol", "atoll", "strtod", "strtof", "strtold",
+    "strtol", "strtoll", "strtoul", "strtoull", "rand", "srand", "aligned_alloc",
+    "calloc", "free", "malloc", "realloc", "abort", "atexit", "at_quick_exit", "exit",
+    "_Exit", "quick_exit", "getenv", "system", "bsearch", "qsort", "abs", "labs",
+    "llabs", "div", "ldiv", "lldiv", "mblen", "mbtowc", "wctomb", "mbstowcs",
+    "wcstombs",
+    // string.h
+    "memcpy", "memmove", "strcpy", "strncpy", "strcat", "strncat", "memcmp", "strcmp",
+    "strcoll", "strncmp", "strxfrm", "memchr", "strchr", "strcspn", "strpbrk",
+    "strrchr", "strspn", "strstr", "strtok", "memset", "strerror", "strlen", "strdup",
+    "strndup",
+    // math.h, with the float and long double variants of every function
+    "float_t", "double_t", "HUGE_VAL", "HUGE_VALF", "HUGE_VALL", "INFINITY", "NAN",
+    "FP_INFINITE", "FP_NAN", "FP_NORMAL", "FP_SUBNORMAL", "FP_ZERO", "FP_FAST_FMA",
+    "FP_ILOGB0", "FP_ILOGBNAN", "MATH_ERRNO", "MATH_ERREXCEPT", "math_errhandling",
+    "fpclassify", "isfinite", "isinf", "isnan", "isnormal", "signbit", "isgreater",
+    "isgreaterequal", "isless", "islessequal", "islessgreater", "isunordered", "acos",
+    "asin", "atan", "atan2", "cos", "sin", "tan", "acosh", "asinh", "atanh", "cosh",
+    "sinh", "tanh", "exp", "exp2", "expm1", "frexp", "ilogb", "ldexp", "log", "log10",
+    "log1p", "log2", "logb", "modf", "scalbn", "scalbln", "cbrt", "fabs", "hypot",
+    "pow", "sqrt", "erf", "erfc", "lgamma", "tgamma", "ceil", "floor", "nearbyint",
+    "rint", "lrint", "llrint", "round", "lround", "llround", "trunc", "fmod",
+    "remainder", "remquo", "copysign", "nan", "nextafter", "nexttoward", "fdim", "fmax",
+    "fmin", "fma", "acosf", "asinf", "atanf", "atan2f", "cosf", "sinf", "tanf",
+    "acoshf", "asinhf", "atanhf", "coshf", "sinhf", "tanhf", "expf", "exp2f", "expm1f",
+    "frexpf", "ilogbf", "ldexpf", "logf", "log10f", "log1pf", "log2f", "logbf", "modff",
+    "scalbnf", "scalblnf", "cbrtf", "fabsf", "hypotf", "powf", "sqrtf", "erff", "erfcf",
+    "lgammaf", "tgammaf", "ceilf", "floorf", "nearbyintf", "rintf", "lrintf", "llrintf",
+    "roundf", "lroundf", "llroundf", "truncf", "fmodf", "remainderf", "remquof",
+    "copysignf", "nanf", "nextafterf", "nexttowardf", "fdimf", "fmaxf", "fminf", "fmaf",
+    "acosl", "asinl", "atanl", "atan2l", "cosl", "sinl", "tanl", "acoshl", "asinhl",
+    "atanhl", "coshl", "sinhl", "tanhl", "expl", "exp2l", "expm1l", "frexpl", "ilogbl",
+    "ldexpl", "logl", "log10l", "log1pl", "log2l", "logbl", "modfl", "scalbnl",
+    "scalblnl", "cbrtl", "fabsl", "hypotl", "powl", "sqrtl", "erfl", "erfcl", "lgammal",
+    "tgammal", "ceill", "floorl", "nearbyintl", "rintl", "lrintl", "llrintl", "roundl",
+    "lroundl", "llroundl", "truncl", "fmodl", "remainderl", "remquol", "copysignl",
+    "nanl", "nextafterl", "nexttowardl", "fdiml", "fmaxl", "fminl", "fmal",
+    // The runtime header
+    "ASDF_RUNTIME_H", "ASDF_NONE", "ASDF_NUMBER", "ASDF_STRING", "ASDF_BOOL",
+    "ASDF_ARITHMETIC", "ASDF_COMPARISON", "asdf_value", "asdf_kind", "asdf_none",
+    "asdf_number", "asdf_string", "asdf_bool", "asdf_type_name", "asdf_type_error",
+    "asdf_truthy", "asdf_add", "asdf_sub", "asdf_mul", "asdf_div", "asdf_neg",
+    "asdf_pos", "asdf_eq", "asdf_lt", "asdf_le", "asdf_gt", "asdf_ge", "asdf_print",
+];
+
+/// Emits a C99 translation unit plus the runtime header it includes. Every
+/// asdf function becomes a top level C function with a prototype, and the
+/// top level statements become `main`.
+pub struct C {
+    indent_width: usize,
+    optimize: bool,
+}
+
+impl C {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            indent_width: options.indent_width,
+            optimize: options.optimize,
+        }
+    }
+}
+
+impl Backend for C {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String> {
+        let nodes = &match self.optimize {
+            true => remove_dead_stores(nodes),
+            false => nodes.to_vec(),
+        };
+        let mut functions = vec![];
+        collect_functions(nodes, &mut functions);
+        let arities = arities("C", &functions)?;
+        if has_return(nodes) {
+            return Err("'return' outside function".to_string());
+        }
+        let mut bindings = vec![];
+        collect_bindings(nodes, &mut bindings);
+        let mut globals: Vec<String> = vec![];
+        for binding in bindings {
+            if !globals.contains(&binding) && !arities.contains_key(binding.as_str()) {
+                globals.push(binding);
+            }
+        }
+
+        let generator = Generator {
+            arities: &arities,
+            globals: &globals,
+        };
+        let mut w = CodeWriter::new(self.indent_width);
+        w.line(&format!("#include \"{}\"", RUNTIME_HEADER));
+        w.blank();
+        for global in globals.iter() {
+            w.line(&format!("static asdf_value {};", global));
+        }
+        if !globals.is_empty() {
+            w.blank();
+        }
+        for function in functions.iter() {
+            w.line(&format!("{};", generator.prototype(function)?));
+        }
+        if !functions.is_empty() {
+            w.blank();
+        }
+        for function in functions.iter() {
+            generator.function(function, &mut w)?;
+            w.blank();
+        }
+        w.line("int main(void) {");
+        w.indent();
+        generator.block(nodes, &[], &mut w)?;
+        w.line("return 0;");
+        w.dedent();
+        w.line("}");
+        Ok(vec![
+            w.finish("result.c"),
+            Output::new(RUNTIME_HEADER, RUNTIME.to_string()),
+        ])
+    }
+
+    fn reserved(&self) -> &'static [&'static str] {
+        RESERVED
+    }
+}
+
+/// Quotes a string as a C literal. Octal escapes are used for control
+/// characters since hex escapes would swallow following hex digits.
+fn quote(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            '?' => res.push_str("\\?"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                res.push_str(&format!("\\{:03o}", c as u32))
+            }
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+struct Generator<'a> {
+    arities: &'a HashMap<&'a str, usize>,
+    globals: &'a [String],
+}
+
+impl<'a> Generator<'a> {
+    fn prototype(&self, node: &ParseNode) -> Result<String, String> {
+        let (name, params) = signature(node)?;
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params
+                .iter()
+                .map(|p| format!("asdf_value {}", p))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        Ok(format!("static asdf_value {}({})", name, params))
+    }
+
+    fn function(&self, node: &ParseNode, w: &mut CodeWriter) -> Result<(), String> {
+        let (_, params) = signature(node)?;
+        let mut locals: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+        let mut bindings = vec![];
+        collect_bindings(&node.children, &mut bindings);
+        w.mark(node.span);
+        w.line(&format!("{} {{", self.prototype(node)?));
+        w.indent();
+        for binding in bindings {
+            if !locals.contains(&binding) && !self.arities.contains_key(binding.as_str()) {
+                w.line(&format!("asdf_value {} = asdf_none();", binding));
+                locals.push(binding);
+            }
+        }
+        self.block(&node.children, &locals, w)?;
+        w.line("return asdf_none();");
+        w.dedent();
+        w.line("}");
+        Ok(())
+    }
+
+    fn block(
+        &self,
+        nodes: &[ParseNode],
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        for node in nodes.iter() {
+            self.statement(node, locals, w)?;
+        }
+        Ok(())
+    }
+
+    /// Emits `nodes` one level deeper than the current line.
+    fn body(
+        &self,
+        nodes: &[ParseNode],
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        w.indent();
+        self.block(nodes, locals, w)?;
+        w.dedent();
+        Ok(())
+    }
+
+    fn statement(
+        &self,
+        node: &ParseNode,
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        // Hoisted to the top level.
+        if let TokenType::Fn(_) = node.token {
+            return Ok(());
+        }
+        w.mark(node.span);
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let id = self.variable(id, locals)?;
+                let value =
+                    self.expression(node.children.get(1).ok_or("Invalid state")?, locals)?;
+                w.line(&format!("{} = {};", id, value));
+            }
+            TokenType::Print => {
+                let value =
+                    self.expression(node.children.first().ok_or("Invalid state")?, locals)?;
+                w.line(&format!("asdf_print({});", value));
+            }
+            TokenType::While => {
+                let cond =
+                    self.expression(node.extra_info.as_ref().ok_or("Invalid state")?, locals)?;
+                w.line(&format!("while (asdf_truthy({})) {{", cond));
+                self.body(&node.children, locals, w)?;
+                w.line("}");
+            }
+            TokenType::If => {
+                let cond =
+                    self.expression(node.extra_info.as_ref().ok_or("Invalid state")?, locals)?;
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                w.line(&format!("if (asdf_truthy({})) {{", cond));
+                self.body(body, locals, w)?;
+                for branch in branches.iter() {
+                    w.mark(branch.span);
+                    match &branch.extra_info {
+                        Some(cond) => w.line(&format!(
+                            "}} else if (asdf_truthy({})) {{",
+                            self.expression(cond, locals)?
+                        )),
+                        None => w.line("} else {"),
+                    }
+                    self.body(&branch.children, locals, w)?;
+                }
+                w.line("}");
+            }
+            TokenType::Call(_) => w.line(&format!("{};", self.expression(node, locals)?)),
+            TokenType::Return => match node.children.first() {
+                Some(value) => w.line(&format!("return {};", self.expression(value, locals)?)),
+                None => w.line("return asdf_none();"),
+            },
+            t => return Err(format!("{:?} is not a statement", t)),
+        }
+        Ok(())
+    }
+
+    fn variable<'b>(&self, name: &'b str, locals: &[String]) -> Result<&'b str, String> {
+        if locals.iter().any(|l| l == name) || self.globals.iter().any(|g| g == name) {
+            Ok(name)
+        } else if self.arities.contains_key(name) {
+            Err(format!(
+                "C backend can only call functions by name, {} is used as a value",
+                name
+            ))
+        } else {
+            Err(format!(
+                "C backend: {} is not declared in this function or at the top level",
+                name
+            ))
+        }
+    }
+
+    fn expression(&self, node: &ParseNode, locals: &[String]) -> Result<String, String> {
+        match &node.token {
+            TokenType::Identifier(x) => Ok(self.variable(x, locals)?.to_string()),
+            TokenType::Number(x) => Ok(format!("asdf_number({})", x)),
+            TokenType::StringLiteral(x) => Ok(format!("asdf_string({})", quote(x))),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => {
+                    let func = match op {
+                        Operator::Minus => "asdf_neg",
+                        Operator::Plus => "asdf_pos",
+                        _ => return Err(format!("Invalid prefix operator {}", op)),
+                    };
+                    Ok(format!("{}({})", func, self.expression(operand, locals)?))
+                }
+                [lhs, rhs] => {
+                    let func = match op {
+                        Operator::Plus => "asdf_add",
+                        Operator::Minus => "asdf_sub",
+                        Operator::Multiply => "asdf_mul",
+                        Operator::Divide => "asdf_div",
+                        Operator::Equality => "asdf_eq",
+                        Operator::LessThan => "asdf_lt",
+                        Operator::LessThanEqual => "asdf_le",
+                        Operator::GreaterThan => "asdf_gt",
+                        Operator::GreaterThanEqual => "asdf_ge",
+                        Operator::Equal => return Err("Invalid operator = in expression".into()),
+                    };
+                    Ok(format!(
+                        "{}({}, {})",
+                        func,
+                        self.expression(lhs, locals)?,
+                        self.expression(rhs, locals)?
+                    ))
+                }
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let name = identifier(&info.name)?;
+                let arity = match self.arities.get(name) {
+                    Some(arity) => *arity,
+                    None => return Err(format!("C backend: {} is not a function", name)),
+                };
+                if arity != node.children.len() {
+                    return Err(format!(
+                        "{}() takes {} arguments but {} were given",
+                        name,
+                        arity,
+                        node.children.len()
+                    ));
+                }
+                let args = node
+                    .children
+                    .iter()
+                    .map(|a| self.expression(a, locals))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("{}({})", name, args.join(", ")))
+            }
+            t => Err(format!("{:?} is not an expression", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::transpile::{backend, transpile_source, Options, Transpiler};
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn dead_stores_only_with_optimize() {
+        let source = "let x = 1\nlet x = 2\nprint(x)";
+        let c = |optimize| {
+            let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+            let options = Options {
+                optimize,
+                ..Options::default()
+            };
+            let backend = backend("c", &options).unwrap();
+            Transpiler::new(nodes, backend).transpile().unwrap()[0]
+                .contents
+                .clone()
+        };
+        assert!(c(false).contains("x = asdf_number(1);"));
+        let optimized = c(true);
+        assert!(!optimized.contains("x = asdf_number(1);"), "{}", optimized);
+        assert!(optimized.contains("x = asdf_number(2);"), "{}", optimized);
+    }
+
+    #[test]
+    fn libc_names() {
+        let source = r#"
+fn abs(x) {
+    if (x < 0) {
+        return 0 - x
+    }
+    return x
+}
+fn puts(s) {
+    print(s)
+}
+let atoi = 3
+let sqrtf = abs(0 - 4)
+let FILE = "file"
+let ASDF_NONE = 1
+fn index(a) {
+    return a + atoi
+}
+puts(FILE)
+print(sqrtf + index(ASDF_NONE))
+"#;
+        let outputs = transpile_source("c", source);
+        let code = &outputs[0].contents;
+        for renamed in ["abs_(", "puts_(", "atoi_", "sqrtf_", "FILE_", "ASDF_NONE_"] {
+            assert!(code.contains(renamed), "{} not renamed", renamed);
+        }
+        if Command::new("cc").arg("--version").output().is_err() {
+            eprintln!("skipping, cc is not installed");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("asdf-c-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for output in outputs.iter() {
+            fs::write(dir.join(&output.filename), &output.contents).unwrap();
+        }
+        let build = Command::new("cc")
+            .args(["-std=c99", "-o"])
+            .arg(dir.join("result"))
+            .arg(dir.join("result.c"))
+            .output()
+            .unwrap();
+        let run = Command::new(dir.join("result")).output();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            build.status.success(),
+            "{}",
+            String::from_utf8_lossy(&build.stderr)
+        );
+        assert_eq!(String::from_utf8(run.unwrap().stdout).unwrap(), "file\n8\n");
+    }
+}
@@ -0,0 +1,367 @@
+use super::{identifier, Backend, CodeWriter, Options, Output};
+use crate::json::quote;
+use crate::lexer::{Operator, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+
+/// JavaScript reserved words and the globals the output relies on.
+const RESERVED: [&str; 52] = [
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "console",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    "NaN",
+    "Infinity",
+];
+
+/// Emits an ES2020 module. Top level functions are exported so the module
+/// can be imported, the remaining top level statements run on import.
+pub struct JavaScript {
+    indent_width: usize,
+}
+
+impl JavaScript {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            indent_width: options.indent_width,
+        }
+    }
+}
+
+impl Backend for JavaScript {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String> {
+        let mut w = CodeWriter::new(self.indent_width);
+        JavaScript::block(nodes, true, &[], &mut w)?;
+        Ok(vec![w.finish("result.mjs")])
+    }
+
+    fn reserved(&self) -> &'static [&'static str] {
+        &RESERVED
+    }
+}
+
+impl JavaScript {
+    /// Emits the body of a function or module. asdf variables live as long
+    /// as the enclosing function, so they are declared up front instead of
+    /// where JavaScript's block scoping would hide them.
+    fn block(
+        nodes: &[ParseNode],
+        top_level: bool,
+        params: &[&str],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        let mut bindings = vec![];
+        collect_bindings(nodes, &mut bindings);
+        let mut declared: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+        for binding in bindings.iter() {
+            let is_function = nodes.iter().any(|n| match &n.token {
+                TokenType::Fn(Some(info)) => *info.name == TokenType::Identifier(binding.clone()),
+                _ => false,
+            });
+            let count = bindings.iter().filter(|b| *b == binding).count();
+            if !(declared.contains(binding) || is_function && count == 1) {
+                declared.push(binding.clone());
+            }
+        }
+        if declared.len() > params.len() {
+            w.line(&format!("let {};", declared[params.len()..].join(", ")));
+        }
+        for node in nodes.iter() {
+            JavaScript::statement(node, top_level, &declared, w)?;
+        }
+        Ok(())
+    }
+
+    /// Emits the statements of a nested block, which share the variables
+    /// declared by the enclosing function.
+    fn body(nodes: &[ParseNode], declared: &[String], w: &mut CodeWriter) -> Result<(), String> {
+        w.indent();
+        for node in nodes.iter() {
+            JavaScript::statement(node, false, declared, w)?;
+        }
+        w.dedent();
+        Ok(())
+    }
+
+    fn statement(
+        node: &ParseNode,
+        top_level: bool,
+        declared: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        w.mark(node.span);
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let value = JavaScript::expression(node.children.get(1).ok_or("Invalid state")?)?;
+                w.line(&format!("{} = {};", id, value));
+            }
+            TokenType::Print => {
+                let value = JavaScript::expression(node.children.first().ok_or("Invalid state")?)?;
+                w.line(&format!("console.log({});", value));
+            }
+            TokenType::While => {
+                let cond =
+                    JavaScript::expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                w.line(&format!("while ({}) {{", cond));
+                JavaScript::body(&node.children, declared, w)?;
+                w.line("}");
+            }
+            TokenType::If => {
+                let cond =
+                    JavaScript::expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                w.line(&format!("if ({}) {{", cond));
+                JavaScript::body(body, declared, w)?;
+                for branch in branches.iter() {
+                    w.mark(branch.span);
+                    match &branch.extra_info {
+                        Some(cond) => w.line(&format!(
+                            "}} else if ({}) {{",
+                            JavaScript::expression(cond)?
+                        )),
+                        None => w.line("} else {"),
+                    }
+                    JavaScript::body(&branch.children, declared, w)?;
+                }
+                w.line("}");
+            }
+            TokenType::Fn(Some(info)) => {
+                let name = identifier(&info.name)?;
+                let params = node
+                    .extra_info
+                    .as_ref()
+                    .ok_or("Invalid state")?
+                    .children
+                    .iter()
+                    .map(|p| identifier(&p.token))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let signature = format!("function {}({}) {{", name, params.join(", "));
+                let (open, close) = if declared.iter().any(|d| d == name) {
+                    (format!("{} = {}", name, signature), "};")
+                } else if top_level {
+                    (format!("export {}", signature), "}")
+                } else {
+                    (signature, "}")
+                };
+                w.line(&open);
+                w.indent();
+                JavaScript::block(&node.children, false, &params, w)?;
+                w.dedent();
+                w.line(close);
+            }
+            TokenType::Call(_) => w.line(&format!("{};", JavaScript::expression(node)?)),
+            TokenType::Return => match node.children.first() {
+                Some(value) => w.line(&format!("return {};", JavaScript::expression(value)?)),
+                None => w.line("return;"),
+            },
+            t => return Err(format!("{:?} is not a statement", t)),
+        }
+        Ok(())
+    }
+
+    /// Operands that are themselves operations are parenthesised, so the
+    /// tree shape survives regardless of JavaScript's precedence rules.
+    fn operand(node: &ParseNode) -> Result<String, String> {
+        let res = JavaScript::expression(node)?;
+        match node.token {
+            TokenType::Operator(_) => Ok(format!("({})", res)),
+            _ => Ok(res),
+        }
+    }
+
+    fn expression(node: &ParseNode) -> Result<String, String> {
+        match &node.token {
+            TokenType::Identifier(x) => Ok(x.clone()),
+            TokenType::Number(x) => Ok(x.to_string()),
+            TokenType::StringLiteral(x) => Ok(quote(x)),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => Ok(format!("{}{}", op, JavaScript::operand(operand)?)),
+                [lhs, rhs] => {
+                    let op = match op {
+                        Operator::Equality => "===".to_string(),
+                        op => op.to_string(),
+                    };
+                    Ok(format!(
+                        "{} {} {}",
+                        JavaScript::operand(lhs)?,
+                        op,
+                        JavaScript::operand(rhs)?
+                    ))
+                }
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let args = node
+                    .children
+                    .iter()
+                    .map(JavaScript::expression)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("{}({})", identifier(&info.name)?, args.join(", ")))
+            }
+            t => Err(format!("{:?} is not an expression", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile::transpile_source;
+
+    fn js(source: &str) -> String {
+        transpile_source("js", source).remove(0).contents
+    }
+
+    #[test]
+    fn fib() {
+        let source = "
+fn fib(n) {
+    if (n < 2) {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+print(fib(10))
+";
+        assert_eq!(
+            js(source),
+            "\
+export function fib(n) {
+    if (n < 2) {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+console.log(fib(10));
+"
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        let source = r#"let s = "say \"hi\" \\ \n
+next	line"
+print(s + "!")"#;
+        assert_eq!(
+            js(source),
+            r#"let s;
+s = "say \"hi\" \\ \\n\nnext\tline";
+console.log(s + "!");
+"#
+        );
+    }
+
+    #[test]
+    fn elif_chain() {
+        let source = r#"
+fn sign(x) {
+    if (x < 0) {
+        return -1
+    } elif (x == 0) {
+        return 0
+    } elif (x > 100) {
+        print("big")
+    } else {
+        return 1
+    }
+    let y = x
+    return y
+}
+print(sign(-3))
+"#;
+        assert_eq!(
+            js(source),
+            r#"export function sign(x) {
+    let y;
+    if (x < 0) {
+        return -1;
+    } else if (x === 0) {
+        return 0;
+    } else if (x > 100) {
+        console.log("big");
+    } else {
+        return 1;
+    }
+    y = x;
+    return y;
+}
+console.log(sign(-3));
+"#
+        );
+    }
+
+    #[test]
+    fn reserved_names() {
+        let source = "
+let class = 1
+let class_ = 2
+fn new(this) {
+    return this + class
+}
+print(new(class_))
+";
+        assert_eq!(
+            js(source),
+            "\
+let class__, class_;
+class__ = 1;
+class_ = 2;
+export function new_(this_) {
+    return this_ + class__;
+}
+console.log(new_(class_));
+"
+        );
+    }
+}
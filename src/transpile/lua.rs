@@ -0,0 +1,477 @@
+use super::{has_return, identifier, Backend, CodeWriter, Options, Output};
+use crate::lexer::{Operator, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+
+/// Lua reserved words and the helpers from the prelude.
+const RESERVED: [&str; 25] = [
+    "and",
+    "break",
+    "do",
+    "else",
+    "elseif",
+    "end",
+    "false",
+    "for",
+    "function",
+    "goto",
+    "if",
+    "in",
+    "local",
+    "nil",
+    "not",
+    "or",
+    "repeat",
+    "return",
+    "then",
+    "true",
+    "until",
+    "while",
+    "asdf_truthy",
+    "asdf_add",
+    "asdf_print",
+];
+
+/// Helpers for the places where Lua disagrees with asdf: `0` and `""` are
+/// truthy in Lua, strings are joined with `..` where that isn't known when
+/// compiling, and values print the way the interpreter prints them.
+const PRELUDE: &str = r#"local function asdf_truthy(v)
+    return v ~= nil and v ~= false and v ~= 0 and v ~= ""
+end
+
+local function asdf_add(a, b)
+    if type(a) == "string" and type(b) == "string" then
+        return a .. b
+    end
+    return a + b
+end
+
+local function asdf_print(v)
+    if v == nil then
+        print("None")
+    elseif v == true then
+        print("True")
+    elseif v == false then
+        print("False")
+    elseif type(v) ~= "number" then
+        print(v)
+    elseif v == math.floor(v) then
+        print(string.format("%.0f", v))
+    else
+        local s
+        for precision = 1, 17 do
+            s = string.format("%." .. precision .. "g", v)
+            if tonumber(s) == v then
+                break
+            end
+        end
+        print(s)
+    end
+end
+"#;
+
+/// Emits a Lua 5.4 script. Variables are declared `local` at the top of the
+/// function that binds them, which gives the same function level scoping
+/// as asdf, and functions close over their enclosing scope as in the
+/// interpreter. Numbers are emitted as floats so arithmetic never switches
+/// to Lua's integer subtype.
+pub struct Lua {
+    indent_width: usize,
+}
+
+impl Lua {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            indent_width: options.indent_width,
+        }
+    }
+}
+
+impl Backend for Lua {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String> {
+        if has_return(nodes) {
+            return Err("'return' outside function".to_string());
+        }
+        let mut w = CodeWriter::new(self.indent_width);
+        w.push(PRELUDE.to_string());
+        Lua::block(nodes, &[], &mut w)?;
+        Ok(vec![w.finish("result.lua")])
+    }
+
+    fn reserved(&self) -> &'static [&'static str] {
+        &RESERVED
+    }
+}
+
+/// Quotes a string as a Lua literal. Control characters use three digit
+/// decimal escapes so a following digit is not read as part of them.
+fn quote(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_ascii_control() => res.push_str(&format!("\\{:03}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// What an expression evaluates to, where that is known without running it.
+#[derive(PartialEq)]
+enum Kind {
+    Number,
+    Str,
+    Unknown,
+}
+
+fn kind(node: &ParseNode) -> Kind {
+    match (&node.token, node.children.as_slice()) {
+        (TokenType::Number(_), _) => Kind::Number,
+        (TokenType::StringLiteral(_), _) => Kind::Str,
+        (TokenType::Operator(Operator::Plus | Operator::Minus), [operand])
+            if kind(operand) == Kind::Number =>
+        {
+            Kind::Number
+        }
+        (TokenType::Operator(Operator::Plus), [lhs, rhs]) => match (kind(lhs), kind(rhs)) {
+            (Kind::Number, Kind::Number) => Kind::Number,
+            (Kind::Str, Kind::Str) => Kind::Str,
+            _ => Kind::Unknown,
+        },
+        (
+            TokenType::Operator(Operator::Minus | Operator::Multiply | Operator::Divide),
+            [lhs, rhs],
+        ) if kind(lhs) == Kind::Number && kind(rhs) == Kind::Number => Kind::Number,
+        _ => Kind::Unknown,
+    }
+}
+
+fn number(n: f64) -> String {
+    if n.is_nan() {
+        "(0 / 0)".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "math.huge" } else { "-math.huge" }.to_string()
+    } else {
+        format!("{:?}", n)
+    }
+}
+
+impl Lua {
+    /// Emits the body of a function or of the script, declaring every
+    /// variable it binds up front.
+    fn block(nodes: &[ParseNode], params: &[&str], w: &mut CodeWriter) -> Result<(), String> {
+        let mut bindings = vec![];
+        collect_bindings(nodes, &mut bindings);
+        let mut declared: Vec<String> = vec![];
+        for binding in bindings {
+            if !declared.contains(&binding) && !params.contains(&binding.as_str()) {
+                declared.push(binding);
+            }
+        }
+        if !declared.is_empty() {
+            w.line(&format!("local {}", declared.join(", ")));
+        }
+        Lua::statements(nodes, w)
+    }
+
+    fn statements(nodes: &[ParseNode], w: &mut CodeWriter) -> Result<(), String> {
+        for (i, node) in nodes.iter().enumerate() {
+            Lua::statement(node, i + 1 == nodes.len(), w)?;
+        }
+        Ok(())
+    }
+
+    /// Emits `nodes` one level deeper than the current line.
+    fn body(nodes: &[ParseNode], w: &mut CodeWriter) -> Result<(), String> {
+        w.indent();
+        Lua::statements(nodes, w)?;
+        w.dedent();
+        Ok(())
+    }
+
+    fn statement(node: &ParseNode, last: bool, w: &mut CodeWriter) -> Result<(), String> {
+        w.mark(node.span);
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let value = Lua::expression(node.children.get(1).ok_or("Invalid state")?)?;
+                w.line(&format!("{} = {}", id, value));
+            }
+            TokenType::Print => {
+                let value = Lua::expression(node.children.first().ok_or("Invalid state")?)?;
+                w.line(&format!("asdf_print({})", value));
+            }
+            TokenType::While => {
+                let cond = Lua::condition(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                w.line(&format!("while {} do", cond));
+                Lua::body(&node.children, w)?;
+                w.line("end");
+            }
+            TokenType::If => {
+                let cond = Lua::condition(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                w.line(&format!("if {} then", cond));
+                Lua::body(body, w)?;
+                for branch in branches.iter() {
+                    w.mark(branch.span);
+                    match &branch.extra_info {
+                        Some(cond) => w.line(&format!("elseif {} then", Lua::condition(cond)?)),
+                        None => w.line("else"),
+                    }
+                    Lua::body(&branch.children, w)?;
+                }
+                w.line("end");
+            }
+            TokenType::Fn(Some(info)) => {
+                let params = node
+                    .extra_info
+                    .as_ref()
+                    .ok_or("Invalid state")?
+                    .children
+                    .iter()
+                    .map(|p| identifier(&p.token))
+                    .collect::<Result<Vec<_>, _>>()?;
+                w.line(&format!(
+                    "function {}({})",
+                    identifier(&info.name)?,
+                    params.join(", ")
+                ));
+                w.indent();
+                Lua::block(&node.children, &params, w)?;
+                w.dedent();
+                w.line("end");
+            }
+            TokenType::Call(_) => w.line(&Lua::expression(node)?),
+            // Lua only allows `return` as the last statement of a block.
+            TokenType::Return => {
+                let res = match node.children.first() {
+                    Some(value) => format!("return {}", Lua::expression(value)?),
+                    None => "return".to_string(),
+                };
+                if last {
+                    w.line(&res);
+                } else {
+                    w.line(&format!("do {} end", res));
+                }
+            }
+            t => return Err(format!("{:?} is not a statement", t)),
+        }
+        Ok(())
+    }
+
+    /// Comparisons already produce Lua booleans, anything else goes through
+    /// asdf's truthiness rules.
+    fn condition(node: &ParseNode) -> Result<String, String> {
+        match (&node.token, node.children.len()) {
+            (
+                TokenType::Operator(
+                    Operator::Equality
+                    | Operator::LessThan
+                    | Operator::LessThanEqual
+                    | Operator::GreaterThan
+                    | Operator::GreaterThanEqual,
+                ),
+                2,
+            ) => Lua::expression(node),
+            _ => Ok(format!("asdf_truthy({})", Lua::expression(node)?)),
+        }
+    }
+
+    /// Operands that are themselves operations are parenthesised, so the
+    /// tree shape survives regardless of Lua's precedence rules.
+    fn operand(node: &ParseNode) -> Result<String, String> {
+        let res = Lua::expression(node)?;
+        match node.token {
+            TokenType::Operator(_) => Ok(format!("({})", res)),
+            _ => Ok(res),
+        }
+    }
+
+    fn expression(node: &ParseNode) -> Result<String, String> {
+        match &node.token {
+            TokenType::Identifier(x) => Ok(x.clone()),
+            TokenType::Number(x) => Ok(number(x.into_inner() as f64)),
+            TokenType::StringLiteral(x) => Ok(quote(x)),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                // Lua has no unary plus.
+                [operand] if *op == Operator::Plus => Lua::operand(operand),
+                [operand] => Ok(format!("{}{}", op, Lua::operand(operand)?)),
+                [lhs, rhs] if *op == Operator::Plus && kind(node) == Kind::Str => {
+                    Ok(format!("{} .. {}", Lua::operand(lhs)?, Lua::operand(rhs)?))
+                }
+                [lhs, rhs] if *op == Operator::Plus && kind(node) == Kind::Unknown => Ok(format!(
+                    "asdf_add({}, {})",
+                    Lua::expression(lhs)?,
+                    Lua::expression(rhs)?
+                )),
+                [lhs, rhs] => Ok(format!(
+                    "{} {} {}",
+                    Lua::operand(lhs)?,
+                    op,
+                    Lua::operand(rhs)?
+                )),
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let args = node
+                    .children
+                    .iter()
+                    .map(Lua::expression)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("{}({})", identifier(&info.name)?, args.join(", ")))
+            }
+            t => Err(format!("{:?} is not an expression", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PRELUDE;
+    use crate::interpret::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::transpile::transpile_source;
+    use std::fs;
+    use std::process::Command;
+
+    const CLOSURES: &str = "
+fn counter() {
+    let count = 0
+    fn next() {
+        return count + 1
+    }
+    let count = 10
+    return next
+}
+let next = counter()
+print(next())
+";
+
+    const ELIF_CHAIN: &str = r#"
+fn sign(x) {
+    if (x < 0) {
+        return -1
+    } elif (x == 0) {
+        return 0
+    } elif (x) {
+        print("positive")
+    } else {
+        return 1
+    }
+    return x
+}
+print(sign(-3))
+"#;
+
+    const STRINGS: &str = r#"
+let greeting = "hello" + ", " + "world"
+let total = 1 + 2
+print(greeting + "!")
+print(total + -1)
+"#;
+
+    /// The generated script without the prelude and the blank line after it.
+    fn lua(source: &str) -> String {
+        let contents = transpile_source("lua", source).remove(0).contents;
+        contents
+            .strip_prefix(&format!("{}\n", PRELUDE))
+            .expect("script should start with the prelude")
+            .to_string()
+    }
+
+    #[test]
+    fn closures() {
+        assert_eq!(
+            lua(CLOSURES),
+            "\
+local counter, next
+function counter()
+    local count, next
+    count = 0.0
+    function next()
+        return asdf_add(count, 1.0)
+    end
+    count = 10.0
+    return next
+end
+next = counter()
+asdf_print(next())
+"
+        );
+    }
+
+    #[test]
+    fn elif_chain() {
+        assert_eq!(
+            lua(ELIF_CHAIN),
+            r#"local sign
+function sign(x)
+    if x < 0.0 then
+        return -1.0
+    elseif x == 0.0 then
+        return 0.0
+    elseif asdf_truthy(x) then
+        asdf_print("positive")
+    else
+        return 1.0
+    end
+    return x
+end
+asdf_print(sign(-3.0))
+"#
+        );
+    }
+
+    #[test]
+    fn string_concatenation() {
+        // `..` and `+` where the operands are known, the prelude otherwise.
+        assert_eq!(
+            lua(STRINGS),
+            r#"local greeting, total
+greeting = ("hello" .. ", ") .. "world"
+total = 1.0 + 2.0
+asdf_print(asdf_add(greeting, "!"))
+asdf_print(asdf_add(total, -1.0))
+"#
+        );
+    }
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        if Command::new("lua").arg("-v").output().is_err() {
+            eprintln!("skipping, lua is not installed");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("asdf-lua-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("result.lua");
+        for source in [CLOSURES, ELIF_CHAIN, STRINGS] {
+            let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+            let mut interpreter = Interpreter::collecting();
+            interpreter.run(&nodes).unwrap();
+            let mut expected = interpreter.output().join("\n");
+            expected.push('\n');
+            fs::write(&path, transpile_source("lua", source).remove(0).contents).unwrap();
+            let output = Command::new("lua").arg(&path).output().unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                expected,
+                "{}",
+                source
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
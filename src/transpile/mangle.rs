@@ -0,0 +1,97 @@
+use crate::lexer::{FnInfo, TokenType};
+use crate::parser::ParseNode;
+use std::collections::{BTreeSet, HashMap};
+
+/// Renames every identifier that is reserved in the target language by
+/// appending underscores until the name is neither reserved nor used
+/// elsewhere in the program, so `class` becomes `class_`. Characters the
+/// target doesn't allow are spelled by their code point first, so `café`
+/// becomes `caf_ue9`. The renaming only depends on the set of names in the
+/// program, which keeps it stable from one run to the next. Returns the
+/// renamed program and the new name of each renamed identifier.
+pub fn mangle(
+    nodes: &[ParseNode],
+    reserved: &[&str],
+    allowed: &dyn Fn(char) -> bool,
+) -> (Vec<ParseNode>, HashMap<String, String>) {
+    let mut names = BTreeSet::new();
+    for node in nodes.iter() {
+        collect_names(node, &mut names);
+    }
+    let mut renames = HashMap::new();
+    for name in names.iter() {
+        let spelled: String = name
+            .chars()
+            .map(|c| {
+                if allowed(c) {
+                    c.to_string()
+                } else {
+                    format!("_u{:x}", c as u32)
+                }
+            })
+            .collect();
+        if spelled == *name && !reserved.contains(&name.as_str()) {
+            continue;
+        }
+        let mut candidate = if spelled == *name {
+            format!("{}_", name)
+        } else {
+            spelled
+        };
+        while reserved.contains(&candidate.as_str())
+            || names.contains(&candidate)
+            || renames.values().any(|r| *r == candidate)
+        {
+            candidate.push('_');
+        }
+        renames.insert(name.clone(), candidate);
+    }
+    let nodes = nodes.iter().map(|n| rename(n, &renames)).collect();
+    (nodes, renames)
+}
+
+fn collect_names(node: &ParseNode, out: &mut BTreeSet<String>) {
+    match &node.token {
+        TokenType::Identifier(x) => {
+            out.insert(x.clone());
+        }
+        TokenType::Fn(Some(info)) | TokenType::Call(info) => {
+            if let TokenType::Identifier(x) = &*info.name {
+                out.insert(x.clone());
+            }
+        }
+        _ => {}
+    }
+    if let Some(extra) = &node.extra_info {
+        collect_names(extra, out);
+    }
+    for child in node.children.iter() {
+        collect_names(child, out);
+    }
+}
+
+fn rename_token(token: &TokenType, renames: &HashMap<String, String>) -> TokenType {
+    match token {
+        TokenType::Identifier(x) => match renames.get(x) {
+            Some(r) => TokenType::Identifier(r.clone()),
+            None => token.clone(),
+        },
+        TokenType::Fn(Some(info)) => {
+            TokenType::Fn(Some(FnInfo::new(rename_token(&info.name, renames))))
+        }
+        TokenType::Call(info) => TokenType::Call(FnInfo::new(rename_token(&info.name, renames))),
+        t => t.clone(),
+    }
+}
+
+fn rename(node: &ParseNode, renames: &HashMap<String, String>) -> ParseNode {
+    ParseNode {
+        token: rename_token(&node.token, renames),
+        extra_info: node
+            .extra_info
+            .as_ref()
+            .map(|e| Box::new(rename(e, renames))),
+        children: node.children.iter().map(|c| rename(c, renames)).collect(),
+        span: node.span,
+    }
+}
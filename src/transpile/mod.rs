@@ -0,0 +1,293 @@
+mod c;
+mod js;
+mod lua;
+mod mangle;
+mod python;
+mod rust;
+mod wat;
+
+use crate::lexer::{Span, TokenType};
+use crate::parser::ParseNode;
+use std::collections::HashMap;
+
+/// Names accepted by `--target`, the first one is the default.
+pub const TARGETS: [&str; 6] = ["python", "js", "c", "rust", "wat", "lua"];
+
+/// A generated file, relative to the directory the compiler writes into.
+#[derive(Debug)]
+pub struct Output {
+    pub filename: String,
+    pub contents: String,
+    /// Where the code for each statement starts, sorted by position.
+    pub mappings: Vec<Mapping>,
+}
+
+impl Output {
+    pub fn new(filename: &str, contents: String) -> Self {
+        Self {
+            filename: filename.to_string(),
+            contents,
+            mappings: vec![],
+        }
+    }
+}
+
+/// Links a 0-based line and column of generated code to the asdf source
+/// it came from. The column counts UTF-16 code units, as source maps do.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub line: usize,
+    pub col: usize,
+    pub span: Span,
+}
+
+/// A code generator for one target language. Backends only see the parse
+/// tree, so adding a language does not touch the lexer or parser.
+pub trait Backend {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String>;
+
+    /// Like `generate`, for backends whose output has to mention the names
+    /// the program used, such as exports: `renames` maps every identifier
+    /// that was renamed to its new name.
+    fn generate_renamed(
+        &self,
+        nodes: &[ParseNode],
+        _renames: &HashMap<String, String>,
+    ) -> Result<Vec<Output>, String> {
+        self.generate(nodes)
+    }
+
+    /// Identifiers the output cannot use for asdf names, because the target
+    /// language reserves them or the generated code needs them itself.
+    /// They are renamed before `generate` is called.
+    fn reserved(&self) -> &'static [&'static str];
+
+    /// Whether `c` can appear in an identifier of the target language.
+    /// Names with other characters are spelled in ASCII before `generate`
+    /// is called.
+    fn identifier_char(&self, _c: char) -> bool {
+        true
+    }
+}
+
+/// Code generation settings chosen on the command line.
+pub struct Options {
+    /// Spaces per indentation level in the generated code.
+    pub indent_width: usize,
+    /// Emit Python as an importable module with a `main` function instead
+    /// of a flat script.
+    pub module: bool,
+    /// Path of the file being compiled, mentioned in generated headers.
+    pub source: Option<String>,
+    /// Whether -O was given, so backends may rewrite the program.
+    pub optimize: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            module: false,
+            source: None,
+            optimize: false,
+        }
+    }
+}
+
+pub fn backend(target: &str, options: &Options) -> Result<Box<dyn Backend>, String> {
+    match target {
+        "python" => Ok(Box::new(python::Python::new(options))),
+        "js" => Ok(Box::new(js::JavaScript::new(options))),
+        "c" => Ok(Box::new(c::C::new(options))),
+        "rust" => Ok(Box::new(rust::Rust::new(options))),
+        "wat" => Ok(Box::new(wat::Wat::new(options))),
+        "lua" => Ok(Box::new(lua::Lua::new(options))),
+        _ => Err(format!(
+            "Unknown target {}, expected one of {}",
+            target,
+            TARGETS.join(", ")
+        )),
+    }
+}
+
+/// Collects lines of output, indenting each one by the current nesting
+/// level, so generators never compute indentation themselves. It also
+/// remembers which statement each line was generated for.
+pub struct CodeWriter {
+    lines: Vec<String>,
+    level: usize,
+    width: usize,
+    mappings: Vec<Mapping>,
+    pending: Option<Span>,
+}
+
+impl CodeWriter {
+    pub fn new(width: usize) -> Self {
+        Self {
+            lines: vec![],
+            level: 0,
+            width,
+            mappings: vec![],
+            pending: None,
+        }
+    }
+
+    /// Attributes the next line written to `span`.
+    pub fn mark(&mut self, span: Span) {
+        self.pending = Some(span);
+    }
+
+    pub fn line(&mut self, text: &str) {
+        self.push(format!("{}{}", " ".repeat(self.level * self.width), text));
+    }
+
+    /// Writes text that is already indented, which may span several lines.
+    pub fn push(&mut self, text: String) {
+        for line in text.split('\n') {
+            if let Some(span) = self.pending.take() {
+                self.mappings.push(Mapping {
+                    line: self.lines.len(),
+                    col: line[..line.len() - line.trim_start().len()]
+                        .encode_utf16()
+                        .count(),
+                    span,
+                });
+            }
+            self.lines.push(line.to_string());
+        }
+    }
+
+    /// Writes an empty line, without indentation.
+    pub fn blank(&mut self) {
+        self.lines.push(String::new());
+    }
+
+    /// Writes the lines of `other` after the ones written so far, for output
+    /// whose header depends on what the rest of the file needed.
+    pub fn append(&mut self, other: CodeWriter) {
+        let offset = self.lines.len();
+        self.mappings
+            .extend(other.mappings.into_iter().map(|m| Mapping {
+                line: m.line + offset,
+                ..m
+            }));
+        self.lines.extend(other.lines);
+    }
+
+    pub fn indent(&mut self) {
+        self.level += 1;
+    }
+
+    pub fn dedent(&mut self) {
+        self.level -= 1;
+    }
+
+    /// The lines written so far as the file `filename`, each terminated by a
+    /// newline.
+    pub fn finish(self, filename: &str) -> Output {
+        let mut contents = self.lines.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        Output {
+            filename: filename.to_string(),
+            contents,
+            mappings: self.mappings,
+        }
+    }
+}
+
+pub fn identifier(token: &TokenType) -> Result<&str, String> {
+    match token {
+        TokenType::Identifier(x) => Ok(x),
+        _ => Err("Invalid state".to_string()),
+    }
+}
+
+/// Every `fn` node in the program, nested ones included, in source order.
+pub fn collect_functions<'a>(nodes: &'a [ParseNode], out: &mut Vec<&'a ParseNode>) {
+    for node in nodes.iter() {
+        if let TokenType::Fn(Some(_)) = node.token {
+            out.push(node);
+        }
+        collect_functions(&node.children, out);
+    }
+}
+
+/// Whether `nodes` return outside of any function body.
+pub fn has_return(nodes: &[ParseNode]) -> bool {
+    nodes.iter().any(|n| match n.token {
+        TokenType::Return => true,
+        TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+            has_return(&n.children)
+        }
+        _ => false,
+    })
+}
+
+pub fn signature(node: &ParseNode) -> Result<(&str, Vec<&str>), String> {
+    let name = match &node.token {
+        TokenType::Fn(Some(info)) => identifier(&info.name)?,
+        _ => return Err("Invalid state".to_string()),
+    };
+    let params = node
+        .extra_info
+        .as_ref()
+        .ok_or("Invalid state")?
+        .children
+        .iter()
+        .map(|p| identifier(&p.token))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((name, params))
+}
+
+/// Maps function names to their number of parameters, for backends that
+/// hoist every function to the top level and so need unique names.
+pub fn arities<'a>(
+    target: &str,
+    functions: &[&'a ParseNode],
+) -> Result<HashMap<&'a str, usize>, String> {
+    let mut arities = HashMap::new();
+    for function in functions.iter() {
+        let (name, params) = signature(function)?;
+        if arities.insert(name, params.len()).is_some() {
+            return Err(format!(
+                "{} backend requires unique function names, {} is defined twice",
+                target, name
+            ));
+        }
+    }
+    Ok(arities)
+}
+
+pub struct Transpiler {
+    nodes: Vec<ParseNode>,
+    backend: Box<dyn Backend>,
+}
+
+impl Transpiler {
+    pub fn new(nodes: Vec<ParseNode>, backend: Box<dyn Backend>) -> Self {
+        Self { nodes, backend }
+    }
+
+    pub fn transpile(&self) -> Result<Vec<Output>, String> {
+        let (nodes, renames) = mangle::mangle(&self.nodes, self.backend.reserved(), &|c| {
+            self.backend.identifier_char(c)
+        });
+        self.backend.generate_renamed(&nodes, &renames)
+    }
+}
+
+/// Transpiles `source` for `target` with the default options.
+#[cfg(test)]
+pub fn transpile_source(target: &str, source: &str) -> Vec<Output> {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    let nodes = Parser::new(Lexer::new(source).lex())
+        .parse()
+        .expect("test program should parse");
+    let backend = backend(target, &Options::default()).expect("unknown target");
+    Transpiler::new(nodes, backend)
+        .transpile()
+        .expect("test program should transpile")
+}
@@ -0,0 +1,401 @@
+use super::{identifier, Backend, CodeWriter, Options, Output};
+use crate::json::quote;
+use crate::lexer::TokenType;
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+
+/// Python keywords, the builtins generated code is most likely to shadow and
+/// the entry point of modules.
+const RESERVED: [&str; 78] = [
+    "False",
+    "None",
+    "True",
+    "and",
+    "as",
+    "assert",
+    "async",
+    "await",
+    "break",
+    "class",
+    "continue",
+    "def",
+    "del",
+    "elif",
+    "else",
+    "except",
+    "finally",
+    "for",
+    "from",
+    "global",
+    "if",
+    "import",
+    "in",
+    "is",
+    "lambda",
+    "nonlocal",
+    "not",
+    "or",
+    "pass",
+    "raise",
+    "return",
+    "try",
+    "while",
+    "with",
+    "yield",
+    "abs",
+    "all",
+    "any",
+    "bool",
+    "dict",
+    "dir",
+    "divmod",
+    "enumerate",
+    "exit",
+    "filter",
+    "float",
+    "format",
+    "hash",
+    "id",
+    "input",
+    "int",
+    "isinstance",
+    "iter",
+    "len",
+    "list",
+    "map",
+    "max",
+    "min",
+    "next",
+    "object",
+    "open",
+    "ord",
+    "chr",
+    "pow",
+    "quit",
+    "range",
+    "repr",
+    "reversed",
+    "round",
+    "set",
+    "sorted",
+    "str",
+    "sum",
+    "super",
+    "tuple",
+    "type",
+    "zip",
+    "main",
+];
+
+pub struct Python {
+    indent_width: usize,
+    module: bool,
+    source: Option<String>,
+}
+
+impl Python {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            indent_width: options.indent_width,
+            module: options.module,
+            source: options.source.clone(),
+        }
+    }
+
+    /// Emits the program as a module that can be imported without running
+    /// it. Top level functions stay at module level, everything else moves
+    /// into `main`, which declares the variables it binds `global` so the
+    /// functions still see them.
+    fn module(&self, nodes: &[ParseNode], w: &mut CodeWriter) -> Result<(), String> {
+        match &self.source {
+            Some(source) => w.line(&format!("# Generated from {} by asdf.", source)),
+            None => w.line("# Generated by asdf."),
+        }
+        let mut bindings = vec![];
+        collect_bindings(nodes, &mut bindings);
+        // A function bound more than once has to stay in order with the
+        // other statements that bind it.
+        let (functions, statements): (Vec<_>, Vec<_>) = nodes.iter().partition(|n| {
+            function_name(n).is_some_and(|f| bindings.iter().filter(|b| *b == f).count() == 1)
+        });
+        let mut globals: Vec<String> = vec![];
+        for binding in bindings.iter() {
+            let hoisted = functions.iter().any(|f| function_name(f) == Some(binding));
+            if !hoisted && !globals.contains(binding) {
+                globals.push(binding.clone());
+            }
+        }
+        for function in functions {
+            w.blank();
+            w.blank();
+            Python::statement(function, w)?;
+        }
+        w.blank();
+        w.blank();
+        w.line("def main():");
+        w.indent();
+        if !globals.is_empty() {
+            w.line(&format!("global {}", globals.join(", ")));
+        } else if statements.is_empty() {
+            w.line("pass");
+        }
+        for statement in statements {
+            Python::statement(statement, w)?;
+        }
+        w.dedent();
+        w.blank();
+        w.blank();
+        w.line("if __name__ == \"__main__\":");
+        w.indent();
+        w.line("main()");
+        w.dedent();
+        Ok(())
+    }
+}
+
+fn function_name(node: &ParseNode) -> Option<&str> {
+    match &node.token {
+        TokenType::Fn(Some(info)) => identifier(&info.name).ok(),
+        _ => None,
+    }
+}
+
+impl Backend for Python {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String> {
+        let mut w = CodeWriter::new(self.indent_width);
+        if self.module {
+            self.module(nodes, &mut w)?;
+        } else {
+            for node in nodes.iter() {
+                Python::statement(node, &mut w)?;
+            }
+        }
+        Ok(vec![w.finish("result.py")])
+    }
+
+    fn reserved(&self) -> &'static [&'static str] {
+        &RESERVED
+    }
+}
+
+impl Python {
+    /// Emits an indented suite. Python does not allow empty blocks, so those
+    /// get a `pass`.
+    fn block(nodes: &[ParseNode], w: &mut CodeWriter) -> Result<(), String> {
+        w.indent();
+        if nodes.is_empty() {
+            w.line("pass");
+        }
+        for node in nodes.iter() {
+            Python::statement(node, w)?;
+        }
+        w.dedent();
+        Ok(())
+    }
+
+    fn statement(node: &ParseNode, w: &mut CodeWriter) -> Result<(), String> {
+        w.mark(node.span);
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let value = Python::expression(node.children.get(1).ok_or("Invalid state")?)?;
+                w.line(&format!("{} = {}", id, value));
+            }
+            TokenType::Print => {
+                let value = Python::expression(node.children.first().ok_or("Invalid state")?)?;
+                w.line(&format!("print({})", value));
+            }
+            TokenType::While => {
+                let cond = Python::expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                w.line(&format!("while {}:", cond));
+                Python::block(&node.children, w)?;
+            }
+            TokenType::If => {
+                let cond = Python::expression(node.extra_info.as_ref().ok_or("Invalid state")?)?;
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                w.line(&format!("if {}:", cond));
+                Python::block(body, w)?;
+                for branch in branches.iter() {
+                    w.mark(branch.span);
+                    match &branch.extra_info {
+                        Some(cond) => w.line(&format!("elif {}:", Python::expression(cond)?)),
+                        None => w.line("else:"),
+                    }
+                    Python::block(&branch.children, w)?;
+                }
+            }
+            TokenType::Fn(Some(info)) => {
+                let params = node
+                    .extra_info
+                    .as_ref()
+                    .ok_or("Invalid state")?
+                    .children
+                    .iter()
+                    .map(|p| identifier(&p.token))
+                    .collect::<Result<Vec<_>, _>>()?;
+                w.line(&format!(
+                    "def {}({}):",
+                    identifier(&info.name)?,
+                    params.join(", ")
+                ));
+                Python::block(&node.children, w)?;
+            }
+            TokenType::Fn(None) => return Err("Name of function is missing".to_string()),
+            TokenType::Call(_) => w.line(&Python::expression(node)?),
+            TokenType::Return => match node.children.first() {
+                Some(value) => w.line(&format!("return {}", Python::expression(value)?)),
+                None => w.line("return"),
+            },
+            t => return Err(format!("{:?} is not a statement", t)),
+        }
+        Ok(())
+    }
+
+    /// Operands that are themselves operations are parenthesised, so the
+    /// tree shape survives regardless of Python's precedence rules.
+    fn operand(node: &ParseNode) -> Result<String, String> {
+        let res = Python::expression(node)?;
+        match node.token {
+            TokenType::Operator(_) => Ok(format!("({})", res)),
+            _ => Ok(res),
+        }
+    }
+
+    fn expression(node: &ParseNode) -> Result<String, String> {
+        match &node.token {
+            TokenType::Identifier(x) => Ok(x.clone()),
+            TokenType::Number(x) => Ok(x.to_string()),
+            TokenType::StringLiteral(x) => Ok(quote(x)),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => Ok(format!("{}{}", op, Python::operand(operand)?)),
+                [lhs, rhs] => Ok(format!(
+                    "{} {} {}",
+                    Python::operand(lhs)?,
+                    op,
+                    Python::operand(rhs)?
+                )),
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let args = node
+                    .children
+                    .iter()
+                    .map(Python::expression)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("{}({})", identifier(&info.name)?, args.join(", ")))
+            }
+            t => Err(format!("{:?} is not an expression", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::transpile::{backend, Options, Transpiler};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    const PROGRAMS: [&str; 3] = [
+        "
+fn outer(a) {
+    fn inner(b) {
+        if (b) {
+        } elif (b < 2) {
+            while (b) {
+                while (b > 3) {
+                }
+                let b = b - 1
+            }
+        } else {
+        }
+        return b
+    }
+    return inner(a)
+}
+fn empty() {
+}
+while (0) {
+}
+if (1) {
+    print(outer(3))
+} else {
+    empty()
+}
+",
+        r#"
+let s = "a \"quoted\" \\ string"
+let class = s + "!"
+print(class)
+"#,
+        "
+fn fib(n) {
+    if (n < 2) {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+let i = 0
+while (i < 10) {
+    print(fib(i))
+    let i = i + 1
+}
+",
+    ];
+
+    fn python(source: &str, options: &Options) -> String {
+        let nodes = Parser::new(Lexer::new(source).lex())
+            .parse()
+            .expect("test program should parse");
+        let backend = backend("python", options).unwrap();
+        Transpiler::new(nodes, backend)
+            .transpile()
+            .expect("test program should transpile")
+            .remove(0)
+            .contents
+    }
+
+    #[test]
+    fn parses_with_python_ast() {
+        let check = "import ast, sys; ast.parse(sys.stdin.read())";
+        if Command::new("python3").arg("--version").output().is_err() {
+            eprintln!("skipping, python3 is not installed");
+            return;
+        }
+        for source in PROGRAMS.iter() {
+            for (indent_width, module) in [(4, false), (2, false), (4, true)] {
+                let options = Options {
+                    indent_width,
+                    module,
+                    ..Options::default()
+                };
+                let code = python(source, &options);
+                let mut child = Command::new("python3")
+                    .args(["-c", check])
+                    .stdin(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .unwrap();
+                child
+                    .stdin
+                    .take()
+                    .unwrap()
+                    .write_all(code.as_bytes())
+                    .unwrap();
+                let output = child.wait_with_output().unwrap();
+                assert!(
+                    output.status.success(),
+                    "{}\n{}",
+                    code,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1410 @@
+use super::{
+    arities, collect_functions, has_return, identifier, signature, Backend, CodeWriter, Options,
+    Output,
+};
+use crate::lexer::{Operator, TokenType};
+use crate::parser::ParseNode;
+use crate::utils::{always_returns, collect_bindings, split_if};
+use std::cell::Cell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// Name of the parameter generated functions take for the top level
+/// variables they read, and of the matching local in `main`.
+const GLOBALS: &str = "asdf";
+
+/// Reports a runtime error the way the interpreter does and exits.
+const FAIL: &str = r#"use std::process;
+
+fn fail(message: String) -> ! {
+    eprintln!("Runtime error: {}", message);
+    process::exit(1)
+}
+"#;
+
+/// asdf division always produces a float and fails on a zero divisor.
+const DIV: &str = r#"fn divide(a: f64, b: f64) -> f64 {
+    if b == 0.0 {
+        fail("division by zero".to_string());
+    }
+    a / b
+}
+"#;
+
+/// Dynamically typed values and the operations on them, for whatever can't
+/// be typed statically. A program only uses some of the operations, so the
+/// runtime allows dead code.
+const VALUE: &str = r#"use std::cmp::Ordering;
+use std::fmt;
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Default, PartialEq)]
+enum Value {
+    #[default]
+    None,
+    Number(f64),
+    Str(String),
+    Bool(bool),
+}
+
+#[allow(dead_code)]
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::None => "None",
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Number(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::None => false,
+        }
+    }
+
+    fn type_error(op: &str, a: &Value, b: &Value) -> ! {
+        fail(format!(
+            "unsupported operand types for {}: '{}' and '{}'",
+            op,
+            a.type_name(),
+            b.type_name()
+        ))
+    }
+
+    fn numbers(op: &str, a: Value, b: Value) -> (f64, f64) {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => (a, b),
+            (a, b) => Value::type_error(op, &a, &b),
+        }
+    }
+
+    fn add(a: Value, b: Value) -> Value {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+            (Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
+            (a, b) => Value::type_error("+", &a, &b),
+        }
+    }
+
+    fn sub(a: Value, b: Value) -> Value {
+        let (a, b) = Value::numbers("-", a, b);
+        Value::Number(a - b)
+    }
+
+    fn mul(a: Value, b: Value) -> Value {
+        let (a, b) = Value::numbers("*", a, b);
+        Value::Number(a * b)
+    }
+
+    fn div(a: Value, b: Value) -> Value {
+        let (a, b) = Value::numbers("/", a, b);
+        if b == 0.0 {
+            fail("division by zero".to_string());
+        }
+        Value::Number(a / b)
+    }
+
+    fn neg(a: Value) -> Value {
+        match a {
+            Value::Number(n) => Value::Number(-n),
+            a => fail(format!("bad operand type for unary -: '{}'", a.type_name())),
+        }
+    }
+
+    fn pos(a: Value) -> Value {
+        match a {
+            Value::Number(n) => Value::Number(n),
+            a => fail(format!("bad operand type for unary +: '{}'", a.type_name())),
+        }
+    }
+
+    fn compare(op: &str, a: Value, b: Value, test: fn(Ordering) -> bool) -> Value {
+        let ordering = match (&a, &b) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+            _ => Value::type_error(op, &a, &b),
+        };
+        Value::Bool(ordering.map_or(false, test))
+    }
+
+    fn lt(a: Value, b: Value) -> Value {
+        Value::compare("<", a, b, Ordering::is_lt)
+    }
+
+    fn le(a: Value, b: Value) -> Value {
+        Value::compare("<=", a, b, Ordering::is_le)
+    }
+
+    fn gt(a: Value, b: Value) -> Value {
+        Value::compare(">", a, b, Ordering::is_gt)
+    }
+
+    fn ge(a: Value, b: Value) -> Value {
+        Value::compare(">=", a, b, Ordering::is_ge)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(true) => write!(f, "True"),
+            Value::Bool(false) => write!(f, "False"),
+            Value::None => write!(f, "None"),
+        }
+    }
+}
+"#;
+
+/// Rust keywords, including reserved ones, prelude names that can't be
+/// rebound, and the names used by the generated runtime.
+const RESERVED: [&str; 69] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "typeof", "unsized", "virtual", "yield", "try", "main", "asdf", "Value",
+    "Globals", "Ordering", "fmt", "process", "String", "fail", "divide", "None", "Some", "Ok",
+    "Err", "Option", "Result", "Vec", "Box",
+];
+
+/// Largest magnitude up to which every whole number is exact in an `f64`.
+const MAX_EXACT: u64 = 1 << 53;
+
+/// Emits a single Rust source file that builds with `rustc result.rs`
+/// without warnings. Every asdf function becomes a top level `fn`, top
+/// level variables that functions read live in a `Globals` struct passed to
+/// the functions that need it, and the remaining top level statements
+/// become `main`.
+///
+/// Variables, parameters and results get native types where every value
+/// they can hold is known: `i64` for whole numbers that provably stay
+/// within the range an `f64` holds exactly, so the result is the same as
+/// the interpreter's, `f64` for other numbers, `String` and `bool`.
+/// Anything else is a dynamically typed `Value`.
+pub struct Rust {
+    indent_width: usize,
+}
+
+impl Rust {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            indent_width: options.indent_width,
+        }
+    }
+}
+
+impl Backend for Rust {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String> {
+        let mut functions = vec![];
+        collect_functions(nodes, &mut functions);
+        let arities = arities("Rust", &functions)?;
+        if has_return(nodes) {
+            return Err("'return' outside function".to_string());
+        }
+        let top = Scope::new(nodes, None, &[], &arities);
+        let mut params = HashMap::new();
+        let mut names = vec![];
+        let mut scopes = vec![];
+        for function in functions.iter() {
+            let (name, list) = signature(function)?;
+            scopes.push(Scope::new(&function.children, Some(name), &list, &arities));
+            params.insert(name, list);
+            names.push(name);
+        }
+
+        // Top level variables read by some function, in declaration order.
+        let free: Vec<Vec<String>> = functions
+            .iter()
+            .zip(scopes.iter())
+            .map(|(function, scope)| {
+                let (reads, _) = uses(&function.children);
+                reads.into_iter().filter(|r| !scope.contains(r)).collect()
+            })
+            .collect();
+        let globals: Vec<String> = top
+            .locals
+            .iter()
+            .chain(top.inline.iter())
+            .filter(|v| free.iter().any(|f| f.contains(v)))
+            .cloned()
+            .collect();
+
+        // Functions that touch the globals, directly or through a call.
+        let calls: Vec<Vec<String>> = functions.iter().map(|f| uses(&f.children).1).collect();
+        let mut needs_globals: Vec<&str> = vec![];
+        loop {
+            let before = needs_globals.len();
+            for ((name, free), calls) in names.iter().zip(free.iter()).zip(calls.iter()) {
+                if !needs_globals.contains(name)
+                    && (free.iter().any(|f| globals.contains(f))
+                        || calls.iter().any(|c| needs_globals.contains(&c.as_str())))
+                {
+                    needs_globals.push(*name);
+                }
+            }
+            if needs_globals.len() == before {
+                break;
+            }
+        }
+
+        // Functions reachable from `main`; rustc warns about the rest.
+        let mut live = uses(nodes).1;
+        let mut i = 0;
+        while i < live.len() {
+            if let Some(index) = names.iter().position(|n| *n == live[i]) {
+                for call in calls[index].iter() {
+                    if !live.contains(call) {
+                        live.push(call.clone());
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let mut bodies: Vec<&[ParseNode]> = vec![nodes];
+        bodies.extend(functions.iter().map(|f| f.children.as_slice()));
+        scopes.insert(0, top);
+        let types = Types::infer(&bodies, &scopes, &params);
+        let top = scopes.remove(0);
+
+        let generator = Generator {
+            arities: &arities,
+            params: &params,
+            globals: &globals,
+            needs_globals: &needs_globals,
+            types: &types,
+            uses_value: Cell::new(false),
+            uses_div: Cell::new(false),
+        };
+        let mut body = CodeWriter::new(self.indent_width);
+        for (function, scope) in functions.iter().zip(scopes.iter()) {
+            let live = live
+                .iter()
+                .any(|l| Some(l.as_str()) == scope.owner.as_deref());
+            generator.function(function, scope, live, &mut body)?;
+            body.blank();
+        }
+        let mut main = top;
+        main.locals.retain(|v| !globals.contains(v));
+        main.inline.retain(|v| !globals.contains(v));
+        generator.allow(&main, &uses(nodes).0, true, &mut body);
+        body.line("fn main() {");
+        body.indent();
+        if !globals.is_empty() {
+            body.line(&format!("let {} = &mut Globals::default();", GLOBALS));
+        }
+        generator.declare(&main, &mut body);
+        generator.block(nodes, &main, &mut body)?;
+        body.dedent();
+        body.line("}");
+
+        let mut fields = vec![];
+        for global in globals.iter() {
+            let ty = generator.variable_type(global, &main);
+            fields.push(format!("{}: {},", global, generator.name(ty)));
+        }
+        let mut w = CodeWriter::new(self.indent_width);
+        if generator.uses_value.get() || generator.uses_div.get() {
+            w.push(FAIL.to_string());
+        }
+        if generator.uses_div.get() {
+            w.push(DIV.to_string());
+        }
+        if generator.uses_value.get() {
+            w.push(VALUE.to_string());
+        }
+        if !globals.is_empty() {
+            if !globals.iter().all(|g| is_snake_case(g)) {
+                w.line("#[allow(non_snake_case)]");
+            }
+            w.line("#[derive(Default)]");
+            w.line("struct Globals {");
+            w.indent();
+            for field in fields.iter() {
+                w.line(field);
+            }
+            w.dedent();
+            w.line("}");
+            w.blank();
+        }
+        w.append(body);
+        Ok(vec![w.finish("result.rs")])
+    }
+
+    fn reserved(&self) -> &'static [&'static str] {
+        &RESERVED
+    }
+}
+
+/// The statements of `nodes` up to the first one that always returns, as
+/// rustc warns about anything after it.
+fn reachable(nodes: &[ParseNode]) -> &[ParseNode] {
+    match nodes
+        .iter()
+        .position(|n| always_returns(std::slice::from_ref(n)))
+    {
+        Some(i) => &nodes[..=i],
+        None => nodes,
+    }
+}
+
+/// The variables read and the functions called by the reachable code in
+/// `nodes`, not counting nested functions.
+fn uses(nodes: &[ParseNode]) -> (Vec<String>, Vec<String>) {
+    fn walk(nodes: &[ParseNode], reads: &mut Vec<String>, calls: &mut Vec<String>) {
+        for node in reachable(nodes).iter() {
+            match &node.token {
+                TokenType::Fn(_) => continue,
+                TokenType::Identifier(x) if !reads.contains(x) => reads.push(x.clone()),
+                TokenType::Call(info) => {
+                    if let TokenType::Identifier(x) = &*info.name {
+                        if !calls.contains(x) {
+                            calls.push(x.clone());
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if let Some(extra) = &node.extra_info {
+                walk(std::slice::from_ref(extra), reads, calls);
+            }
+            // The target of a `let` is written, not read.
+            let skip = usize::from(node.token == TokenType::Let);
+            let (body, branches) = split_if(node);
+            walk(&body[skip.min(body.len())..], reads, calls);
+            walk(branches, reads, calls);
+        }
+    }
+    let (mut reads, mut calls) = (vec![], vec![]);
+    walk(nodes, &mut reads, &mut calls);
+    (reads, calls)
+}
+
+/// Every function in `nodes` whose result is used as a value, rather than
+/// only called as a statement.
+fn results(nodes: &[ParseNode], out: &mut Vec<String>) {
+    fn expression(node: &ParseNode, out: &mut Vec<String>) {
+        if let TokenType::Call(info) = &node.token {
+            if let TokenType::Identifier(x) = &*info.name {
+                out.push(x.clone());
+            }
+        }
+        for child in node.children.iter() {
+            expression(child, out);
+        }
+    }
+    for node in nodes.iter() {
+        if let Some(cond) = &node.extra_info {
+            expression(cond, out);
+        }
+        match node.token {
+            TokenType::Call(_) => node.children.iter().for_each(|a| expression(a, out)),
+            TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                results(&node.children, out)
+            }
+            TokenType::Fn(_) => {}
+            _ => node.children.iter().for_each(|c| expression(c, out)),
+        }
+    }
+}
+
+/// Whether rustc accepts `name` without a `non_snake_case` warning.
+fn is_snake_case(name: &str) -> bool {
+    !name.trim_matches('_').contains("__") && !name.chars().any(char::is_uppercase)
+}
+
+/// The variables of one function body, or of the top level when `owner` is
+/// `None`. Rust scopes variables to blocks, so anything assigned more than
+/// once or inside a nested block is declared mutable at the top of the
+/// function. A variable bound exactly once at the top of the body is
+/// declared where it is bound.
+struct Scope {
+    owner: Option<String>,
+    params: Vec<String>,
+    mutable_params: Vec<String>,
+    locals: Vec<String>,
+    inline: Vec<String>,
+}
+
+impl Scope {
+    fn new(
+        nodes: &[ParseNode],
+        owner: Option<&str>,
+        params: &[&str],
+        arities: &HashMap<&str, usize>,
+    ) -> Self {
+        let mut bindings = vec![];
+        collect_bindings(nodes, &mut bindings);
+        bindings.retain(|b| !arities.contains_key(b.as_str()));
+        let mut scope = Scope {
+            owner: owner.map(str::to_string),
+            params: params.iter().map(|p| p.to_string()).collect(),
+            mutable_params: vec![],
+            locals: vec![],
+            inline: vec![],
+        };
+        for binding in bindings.iter() {
+            if scope.params.contains(binding) {
+                if !scope.mutable_params.contains(binding) {
+                    scope.mutable_params.push(binding.clone());
+                }
+                continue;
+            }
+            if scope.locals.contains(binding) || scope.inline.contains(binding) {
+                continue;
+            }
+            let count = bindings.iter().filter(|b| *b == binding).count();
+            let at_top = nodes.iter().any(|n| {
+                n.token == TokenType::Let
+                    && n.children
+                        .first()
+                        .is_some_and(|c| c.token == TokenType::Identifier(binding.clone()))
+            });
+            if count == 1 && at_top {
+                scope.inline.push(binding.clone());
+            } else {
+                scope.locals.push(binding.clone());
+            }
+        }
+        scope
+    }
+
+    fn names(&self) -> impl Iterator<Item = &String> {
+        self.params
+            .iter()
+            .chain(self.locals.iter())
+            .chain(self.inline.iter())
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.names().any(|v| v == name)
+    }
+
+    /// Identifies the variable `name` refers to: a local of this scope or a
+    /// top level variable.
+    fn key(&self, name: &str) -> (Option<String>, String) {
+        if self.contains(name) {
+            (self.owner.clone(), name.to_string())
+        } else {
+            (None, name.to_string())
+        }
+    }
+}
+
+/// What the backend knows about a value before the program runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Type {
+    /// A whole number no further from zero than the bound, which is at most
+    /// `MAX_EXACT`.
+    Int(u64),
+    Float,
+    Str,
+    Bool,
+    Value,
+    /// The result of a function that never returns a value and whose
+    /// result is never used.
+    Unit,
+}
+
+impl Type {
+    /// The narrowest type holding the values of both `a` and `b`, where
+    /// `None` means nothing is known yet.
+    fn join(a: Option<Type>, b: Option<Type>) -> Option<Type> {
+        match (a, b) {
+            (None, t) | (t, None) => t,
+            (Some(Type::Int(a)), Some(Type::Int(b))) => Some(Type::Int(a.max(b))),
+            (Some(a), Some(b)) if a == b => Some(a),
+            (Some(a), Some(b)) if a.numeric() && b.numeric() => Some(Type::Float),
+            _ => Some(Type::Value),
+        }
+    }
+
+    /// Joins a newly found type into a known one. A bound that grows is
+    /// taken to keep growing, as in a loop counter, so it goes straight to
+    /// `MAX_EXACT` rather than one step per round of inference.
+    fn widen(old: Option<Type>, new: Option<Type>) -> Option<Type> {
+        match (old, Type::join(old, new)) {
+            (Some(Type::Int(a)), Some(Type::Int(b))) if b > a => Some(Type::Int(MAX_EXACT)),
+            (_, joined) => joined,
+        }
+    }
+
+    fn numeric(self) -> bool {
+        matches!(self, Type::Int(_) | Type::Float)
+    }
+
+    /// Whether values of both types are the same Rust type.
+    fn same(self, other: Type) -> bool {
+        self.name() == other.name()
+    }
+
+    /// An `Int` bounded by `bound`, or `Float` if that may not be exact.
+    fn int(bound: Option<u64>) -> Type {
+        match bound {
+            Some(bound) if bound <= MAX_EXACT => Type::Int(bound),
+            _ => Type::Float,
+        }
+    }
+
+    fn literal(x: f64) -> Type {
+        if x.fract() == 0.0 && x.abs() <= MAX_EXACT as f64 {
+            Type::Int(x.abs() as u64)
+        } else {
+            Type::Float
+        }
+    }
+
+    fn unary(operand: Type) -> Type {
+        if operand.numeric() {
+            operand
+        } else {
+            Type::Value
+        }
+    }
+
+    fn binary(op: &Operator, lhs: Type, rhs: Type) -> Type {
+        let numbers = lhs.numeric() && rhs.numeric();
+        match op {
+            Operator::Equality => Type::Bool,
+            Operator::Plus if lhs == Type::Str && rhs == Type::Str => Type::Str,
+            Operator::Plus | Operator::Minus | Operator::Multiply => match (lhs, rhs) {
+                (Type::Int(a), Type::Int(b)) if *op == Operator::Multiply => {
+                    Type::int(a.checked_mul(b))
+                }
+                (Type::Int(a), Type::Int(b)) => Type::int(a.checked_add(b)),
+                _ if numbers => Type::Float,
+                _ => Type::Value,
+            },
+            Operator::Divide if numbers => Type::Float,
+            Operator::LessThan
+            | Operator::LessThanEqual
+            | Operator::GreaterThan
+            | Operator::GreaterThanEqual
+                if numbers || (lhs == Type::Str && rhs == Type::Str) =>
+            {
+                Type::Bool
+            }
+            _ => Type::Value,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Type::Int(_) => "i64",
+            Type::Float => "f64",
+            Type::Str => "String",
+            Type::Bool => "bool",
+            Type::Value => "Value",
+            Type::Unit => "()",
+        }
+    }
+
+    /// The value variables start with before their first assignment.
+    fn zero(self) -> &'static str {
+        match self {
+            Type::Int(_) => "0",
+            Type::Float => "0.0",
+            Type::Str => "String::new()",
+            Type::Bool => "false",
+            Type::Value => "Value::None",
+            Type::Unit => "()",
+        }
+    }
+}
+
+/// The types of every variable and function result, found by joining the
+/// types of everything assigned to or returned from them until nothing
+/// changes.
+struct Types {
+    variables: HashMap<(Option<String>, String), Type>,
+    returns: HashMap<String, Type>,
+    /// Functions whose result is used, so they return `None` as a `Value`.
+    valued: Vec<String>,
+    changed: bool,
+}
+
+impl Types {
+    fn infer(
+        bodies: &[&[ParseNode]],
+        scopes: &[Scope],
+        params: &HashMap<&str, Vec<&str>>,
+    ) -> Types {
+        let mut valued = vec![];
+        for nodes in bodies.iter() {
+            results(nodes, &mut valued);
+        }
+        let mut types = Types {
+            variables: HashMap::new(),
+            returns: HashMap::new(),
+            valued,
+            changed: true,
+        };
+        loop {
+            while types.changed {
+                types.changed = false;
+                for (nodes, scope) in bodies.iter().zip(scopes.iter()) {
+                    if let Some(owner) = &scope.owner {
+                        if !always_returns(nodes) {
+                            types.returned(owner, Some(types.nothing(owner)));
+                        }
+                    }
+                    types.visit(nodes, scope, params);
+                }
+            }
+            // Whatever is still unknown never receives a value the backend
+            // can see, like the parameters of a function nobody calls.
+            for scope in scopes.iter() {
+                for name in scope.names() {
+                    if let Entry::Vacant(entry) = types.variables.entry(scope.key(name)) {
+                        entry.insert(Type::Value);
+                        types.changed = true;
+                    }
+                }
+                if let Some(owner) = &scope.owner {
+                    if let Entry::Vacant(entry) = types.returns.entry(owner.clone()) {
+                        entry.insert(Type::Value);
+                        types.changed = true;
+                    }
+                }
+            }
+            if !types.changed {
+                return types;
+            }
+        }
+    }
+
+    /// The type `function` returns `None` as.
+    fn nothing(&self, function: &str) -> Type {
+        if self.valued.iter().any(|v| v == function) {
+            Type::Value
+        } else {
+            Type::Unit
+        }
+    }
+
+    fn assign(&mut self, key: (Option<String>, String), ty: Option<Type>) {
+        let old = self.variables.get(&key).copied();
+        if let Some(new) = Type::widen(old, ty) {
+            if old != Some(new) {
+                self.variables.insert(key, new);
+                self.changed = true;
+            }
+        }
+    }
+
+    fn returned(&mut self, function: &str, ty: Option<Type>) {
+        let old = self.returns.get(function).copied();
+        if let Some(new) = Type::widen(old, ty) {
+            if old != Some(new) {
+                self.returns.insert(function.to_string(), new);
+                self.changed = true;
+            }
+        }
+    }
+
+    fn visit(&mut self, nodes: &[ParseNode], scope: &Scope, params: &HashMap<&str, Vec<&str>>) {
+        for node in reachable(nodes).iter() {
+            match &node.token {
+                TokenType::Fn(_) => {}
+                TokenType::Let => {
+                    if let [target, value] = node.children.as_slice() {
+                        self.calls(value, scope, params);
+                        if let TokenType::Identifier(x) = &target.token {
+                            let ty = self.of(value, scope);
+                            self.assign(scope.key(x), ty);
+                        }
+                    }
+                }
+                TokenType::Return => {
+                    let ty = match node.children.first() {
+                        Some(value) => {
+                            self.calls(value, scope, params);
+                            self.of(value, scope)
+                        }
+                        None => scope.owner.as_deref().map(|o| self.nothing(o)),
+                    };
+                    if let Some(owner) = &scope.owner {
+                        self.returned(owner, ty);
+                    }
+                }
+                TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                    if let Some(cond) = &node.extra_info {
+                        self.calls(cond, scope, params);
+                    }
+                    let (body, branches) = split_if(node);
+                    self.visit(body, scope, params);
+                    self.visit(branches, scope, params);
+                }
+                _ => self.calls(node, scope, params),
+            }
+        }
+    }
+
+    /// Passes the argument types of the calls in `node` to the parameters.
+    fn calls(&mut self, node: &ParseNode, scope: &Scope, params: &HashMap<&str, Vec<&str>>) {
+        if let TokenType::Call(info) = &node.token {
+            if let TokenType::Identifier(name) = &*info.name {
+                if let Some(names) = params.get(name.as_str()) {
+                    if names.len() == node.children.len() {
+                        for (param, arg) in names.iter().zip(node.children.iter()) {
+                            let ty = self.of(arg, scope);
+                            self.assign((Some(name.clone()), param.to_string()), ty);
+                        }
+                    }
+                }
+            }
+        }
+        for child in node.children.iter() {
+            self.calls(child, scope, params);
+        }
+    }
+
+    /// The type of the expression `node`, if known.
+    fn of(&self, node: &ParseNode, scope: &Scope) -> Option<Type> {
+        match &node.token {
+            TokenType::Identifier(x) => self.variables.get(&scope.key(x)).copied(),
+            TokenType::Number(x) => Some(Type::literal(x.into_inner() as f64)),
+            TokenType::StringLiteral(_) => Some(Type::Str),
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => self.of(operand, scope).map(Type::unary),
+                [_, _] if *op == Operator::Equality => Some(Type::Bool),
+                [lhs, rhs] => Some(Type::binary(op, self.of(lhs, scope)?, self.of(rhs, scope)?)),
+                _ => None,
+            },
+            TokenType::Call(info) => match &*info.name {
+                TokenType::Identifier(name) => self.returns.get(name).copied(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Generated code for an expression and its type. Operators aren't atomic,
+/// so they get parentheses when they are an operand.
+struct Expr {
+    code: String,
+    ty: Type,
+    atomic: bool,
+}
+
+impl Expr {
+    fn atom(code: String, ty: Type) -> Self {
+        Self {
+            code,
+            ty,
+            atomic: true,
+        }
+    }
+
+    fn op(code: String, ty: Type) -> Self {
+        Self {
+            code,
+            ty,
+            atomic: false,
+        }
+    }
+
+    fn operand(&self) -> String {
+        if self.atomic {
+            self.code.clone()
+        } else {
+            format!("({})", self.code)
+        }
+    }
+}
+
+struct Generator<'a> {
+    arities: &'a HashMap<&'a str, usize>,
+    params: &'a HashMap<&'a str, Vec<&'a str>>,
+    globals: &'a [String],
+    needs_globals: &'a [&'a str],
+    types: &'a Types,
+    uses_value: Cell<bool>,
+    uses_div: Cell<bool>,
+}
+
+impl<'a> Generator<'a> {
+    fn name(&self, ty: Type) -> &'static str {
+        if ty == Type::Value {
+            self.uses_value.set(true);
+        }
+        ty.name()
+    }
+
+    fn variable_type(&self, name: &str, scope: &Scope) -> Type {
+        let key = scope.key(name);
+        self.types
+            .variables
+            .get(&key)
+            .copied()
+            .unwrap_or(Type::Value)
+    }
+
+    fn return_type(&self, name: &str) -> Type {
+        self.types.returns.get(name).copied().unwrap_or(Type::Value)
+    }
+
+    /// Allows the warnings rustc would give for the asdf code in `scope`,
+    /// which reads the variables in `reads`.
+    fn allow(&self, scope: &Scope, reads: &[String], live: bool, w: &mut CodeWriter) {
+        let mut lints = vec![];
+        if !live {
+            lints.push("dead_code");
+        }
+        if !scope
+            .owner
+            .iter()
+            .chain(scope.names())
+            .all(|n| is_snake_case(n))
+        {
+            lints.push("non_snake_case");
+        }
+        if scope.names().any(|n| !reads.contains(n)) {
+            lints.push("unused_variables");
+        }
+        if !scope.locals.is_empty() || !scope.mutable_params.is_empty() {
+            lints.push("unused_assignments");
+        }
+        if !lints.is_empty() {
+            w.line(&format!("#[allow({})]", lints.join(", ")));
+        }
+    }
+
+    fn declare(&self, scope: &Scope, w: &mut CodeWriter) {
+        for local in scope.locals.iter() {
+            let ty = self.variable_type(local, scope);
+            w.line(&format!(
+                "let mut {}: {} = {};",
+                local,
+                self.name(ty),
+                ty.zero()
+            ));
+        }
+    }
+
+    fn function(
+        &self,
+        node: &ParseNode,
+        scope: &Scope,
+        live: bool,
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        let (name, params) = signature(node)?;
+        let mut args: Vec<String> = params
+            .iter()
+            .map(|p| {
+                let prefix = if scope.mutable_params.iter().any(|m| m == p) {
+                    "mut "
+                } else {
+                    ""
+                };
+                let ty = self.variable_type(p, scope);
+                format!("{}{}: {}", prefix, p, self.name(ty))
+            })
+            .collect();
+        if self.needs_globals.contains(&name) {
+            args.push(format!("{}: &mut Globals", GLOBALS));
+        }
+        self.allow(scope, &uses(&node.children).0, live, w);
+        w.mark(node.span);
+        let ty = self.return_type(name);
+        let result = match ty {
+            Type::Unit => String::new(),
+            ty => format!(" -> {}", self.name(ty)),
+        };
+        w.line(&format!("fn {}({}){} {{", name, args.join(", "), result));
+        w.indent();
+        self.declare(scope, w);
+        self.block(&node.children, scope, w)?;
+        if ty != Type::Unit && !always_returns(&node.children) {
+            w.line(ty.zero());
+        }
+        w.dedent();
+        w.line("}");
+        Ok(())
+    }
+
+    fn block(&self, nodes: &[ParseNode], scope: &Scope, w: &mut CodeWriter) -> Result<(), String> {
+        for node in reachable(nodes).iter() {
+            self.statement(node, scope, w)?;
+        }
+        Ok(())
+    }
+
+    /// Emits `nodes` one level deeper than the current line.
+    fn body(&self, nodes: &[ParseNode], scope: &Scope, w: &mut CodeWriter) -> Result<(), String> {
+        w.indent();
+        self.block(nodes, scope, w)?;
+        w.dedent();
+        Ok(())
+    }
+
+    fn statement(&self, node: &ParseNode, scope: &Scope, w: &mut CodeWriter) -> Result<(), String> {
+        // Hoisted to the top level.
+        if let TokenType::Fn(_) = node.token {
+            return Ok(());
+        }
+        w.mark(node.span);
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let ty = self.variable_type(id, scope);
+                let value = self.expression(node.children.get(1).ok_or("Invalid state")?, scope)?;
+                let value = self.coerce(value, ty)?;
+                if scope.inline.iter().any(|i| i == id) {
+                    w.line(&format!("let {}: {} = {};", id, self.name(ty), value.code));
+                } else {
+                    w.line(&format!("{} = {};", self.variable(id, scope)?, value.code));
+                }
+            }
+            TokenType::Print => {
+                let value =
+                    self.expression(node.children.first().ok_or("Invalid state")?, scope)?;
+                if value.ty == Type::Bool {
+                    w.line(&format!(
+                        "println!(\"{{}}\", if {} {{ \"True\" }} else {{ \"False\" }});",
+                        value.code
+                    ));
+                } else {
+                    w.line(&format!("println!(\"{{}}\", {});", value.code));
+                }
+            }
+            TokenType::While => {
+                let cond =
+                    self.condition(node.extra_info.as_ref().ok_or("Invalid state")?, scope)?;
+                w.line(&format!("while {} {{", cond));
+                self.body(&node.children, scope, w)?;
+                w.line("}");
+            }
+            TokenType::If => {
+                let cond =
+                    self.condition(node.extra_info.as_ref().ok_or("Invalid state")?, scope)?;
+                let (body, branches) = split_if(node);
+                w.line(&format!("if {} {{", cond));
+                self.body(body, scope, w)?;
+                for branch in branches.iter() {
+                    w.mark(branch.span);
+                    match &branch.extra_info {
+                        Some(cond) => {
+                            w.line(&format!("}} else if {} {{", self.condition(cond, scope)?))
+                        }
+                        None => w.line("} else {"),
+                    }
+                    self.body(&branch.children, scope, w)?;
+                }
+                w.line("}");
+            }
+            TokenType::Call(_) => w.line(&format!("{};", self.expression(node, scope)?.code)),
+            TokenType::Return => {
+                let owner = scope.owner.as_deref().ok_or("Invalid state")?;
+                let ty = self.return_type(owner);
+                match node.children.first() {
+                    Some(value) => {
+                        let value = self.expression(value, scope)?;
+                        w.line(&format!("return {};", self.coerce(value, ty)?.code));
+                    }
+                    None if ty == Type::Unit => w.line("return;"),
+                    None => w.line(&format!("return {};", ty.zero())),
+                }
+            }
+            t => return Err(format!("{:?} is not a statement", t)),
+        }
+        Ok(())
+    }
+
+    fn variable(&self, name: &str, scope: &Scope) -> Result<String, String> {
+        if scope.contains(name) {
+            Ok(name.to_string())
+        } else if self.globals.iter().any(|g| g == name) {
+            Ok(format!("{}.{}", GLOBALS, name))
+        } else if self.arities.contains_key(name) {
+            Err(format!(
+                "Rust backend can only call functions by name, {} is used as a value",
+                name
+            ))
+        } else {
+            Err(format!(
+                "Rust backend: {} is not declared in this function or at the top level",
+                name
+            ))
+        }
+    }
+
+    /// A Rust `bool` for asdf's truthiness of `node`.
+    fn condition(&self, node: &ParseNode, scope: &Scope) -> Result<String, String> {
+        let value = self.expression(node, scope)?;
+        Ok(match value.ty {
+            Type::Bool => value.code,
+            Type::Int(_) => format!("{} != 0", value.operand()),
+            Type::Float => format!("{} != 0.0", value.operand()),
+            Type::Str => format!("!{}.is_empty()", value.operand()),
+            Type::Value => format!("{}.truthy()", value.operand()),
+            Type::Unit => format!("{}.truthy()", self.boxed(value).code),
+        })
+    }
+
+    fn float(&self, value: Expr) -> Expr {
+        match value.ty {
+            Type::Int(_) => match value.code.parse::<i64>() {
+                Ok(n) => Expr {
+                    code: format!("{:?}", n as f64),
+                    ty: Type::Float,
+                    atomic: n >= 0,
+                },
+                Err(_) => Expr::op(format!("{} as f64", value.operand()), Type::Float),
+            },
+            _ => value,
+        }
+    }
+
+    fn boxed(&self, value: Expr) -> Expr {
+        self.uses_value.set(true);
+        let variant = match value.ty {
+            Type::Int(_) | Type::Float => "Number",
+            Type::Str => "Str",
+            Type::Bool => "Bool",
+            Type::Value => return value,
+            Type::Unit => {
+                return Expr::atom(format!("{{ {}; Value::None }}", value.code), Type::Value)
+            }
+        };
+        let value = self.float(value);
+        Expr::atom(format!("Value::{}({})", variant, value.code), Type::Value)
+    }
+
+    /// Converts `value` to the wider type `ty`.
+    fn coerce(&self, value: Expr, ty: Type) -> Result<Expr, String> {
+        match (value.ty, ty) {
+            (from, to) if from.same(to) => Ok(value),
+            (Type::Int(_), Type::Float) => Ok(self.float(value)),
+            (_, Type::Value) => Ok(self.boxed(value)),
+            (from, to) => Err(format!("Invalid state: {:?} used as {:?}", from, to)),
+        }
+    }
+
+    fn expression(&self, node: &ParseNode, scope: &Scope) -> Result<Expr, String> {
+        match &node.token {
+            TokenType::Identifier(x) => {
+                let ty = self.variable_type(x, scope);
+                let variable = self.variable(x, scope)?;
+                Ok(match ty {
+                    Type::Str | Type::Value => Expr::atom(format!("{}.clone()", variable), ty),
+                    _ => Expr::atom(variable, ty),
+                })
+            }
+            TokenType::Number(x) => {
+                let x = x.into_inner() as f64;
+                Ok(match Type::literal(x) {
+                    ty @ Type::Int(_) => Expr::atom(format!("{}", x as i64), ty),
+                    _ if x.is_finite() => Expr::atom(format!("{:?}", x), Type::Float),
+                    _ => Expr::atom("f64::INFINITY".to_string(), Type::Float),
+                })
+            }
+            TokenType::StringLiteral(x) => {
+                Ok(Expr::atom(format!("String::from({:?})", x), Type::Str))
+            }
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => self.unary(op, self.expression(operand, scope)?),
+                [lhs, rhs] => self.binary(
+                    op,
+                    self.expression(lhs, scope)?,
+                    self.expression(rhs, scope)?,
+                ),
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let name = identifier(&info.name)?;
+                let params = match self.params.get(name) {
+                    Some(params) => params,
+                    None => return Err(format!("Rust backend: {} is not a function", name)),
+                };
+                if params.len() != node.children.len() {
+                    return Err(format!(
+                        "{}() takes {} arguments but {} were given",
+                        name,
+                        params.len(),
+                        node.children.len()
+                    ));
+                }
+                // The globals go last so they are only reborrowed once the
+                // other arguments have been evaluated.
+                let mut args = vec![];
+                for (param, arg) in params.iter().zip(node.children.iter()) {
+                    let ty = self
+                        .types
+                        .variables
+                        .get(&(Some(name.to_string()), param.to_string()))
+                        .copied()
+                        .unwrap_or(Type::Value);
+                    args.push(self.coerce(self.expression(arg, scope)?, ty)?.code);
+                }
+                if self.needs_globals.contains(&name) {
+                    args.push(GLOBALS.to_string());
+                }
+                Ok(Expr::atom(
+                    format!("{}({})", name, args.join(", ")),
+                    self.return_type(name),
+                ))
+            }
+            t => Err(format!("{:?} is not an expression", t)),
+        }
+    }
+
+    fn unary(&self, op: &Operator, operand: Expr) -> Result<Expr, String> {
+        let func = match op {
+            Operator::Minus => "neg",
+            Operator::Plus => "pos",
+            _ => return Err(format!("Invalid prefix operator {}", op)),
+        };
+        Ok(match Type::unary(operand.ty) {
+            Type::Value => Expr::atom(
+                format!("Value::{}({})", func, self.boxed(operand).code),
+                Type::Value,
+            ),
+            _ if *op == Operator::Plus => operand,
+            ty => Expr::op(format!("-{}", operand.operand()), ty),
+        })
+    }
+
+    fn binary(&self, op: &Operator, lhs: Expr, rhs: Expr) -> Result<Expr, String> {
+        if *op == Operator::Equal {
+            return Err("Invalid operator = in expression".into());
+        }
+        if *op == Operator::Equality {
+            let (lhs, rhs) = match (lhs.ty, rhs.ty) {
+                (a, b) if a.same(b) => (lhs, rhs),
+                (a, b) if a.numeric() && b.numeric() => (self.float(lhs), self.float(rhs)),
+                _ => (self.boxed(lhs), self.boxed(rhs)),
+            };
+            return Ok(Expr::op(
+                format!("{} == {}", lhs.operand(), rhs.operand()),
+                Type::Bool,
+            ));
+        }
+        Ok(match Type::binary(op, lhs.ty, rhs.ty) {
+            Type::Value => {
+                let func = match op {
+                    Operator::Plus => "add",
+                    Operator::Minus => "sub",
+                    Operator::Multiply => "mul",
+                    Operator::Divide => "div",
+                    Operator::LessThan => "lt",
+                    Operator::LessThanEqual => "le",
+                    Operator::GreaterThan => "gt",
+                    _ => "ge",
+                };
+                Expr::atom(
+                    format!(
+                        "Value::{}({}, {})",
+                        func,
+                        self.boxed(lhs).code,
+                        self.boxed(rhs).code
+                    ),
+                    Type::Value,
+                )
+            }
+            Type::Str => Expr::atom(
+                format!("format!(\"{{}}{{}}\", {}, {})", lhs.code, rhs.code),
+                Type::Str,
+            ),
+            _ if *op == Operator::Divide => {
+                self.uses_div.set(true);
+                Expr::atom(
+                    format!("divide({}, {})", self.float(lhs).code, self.float(rhs).code),
+                    Type::Float,
+                )
+            }
+            ty => {
+                // Whole numbers that may not fit are computed as floats.
+                let (lhs, rhs) = if lhs.ty.same(rhs.ty) && ty != Type::Float {
+                    (lhs, rhs)
+                } else {
+                    (self.float(lhs), self.float(rhs))
+                };
+                Expr::op(format!("{} {} {}", lhs.operand(), op, rhs.operand()), ty)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile::transpile_source;
+    use std::fs;
+    use std::process::Command;
+
+    fn rust(source: &str) -> String {
+        transpile_source("rust", source).remove(0).contents
+    }
+
+    /// Builds `source` with rustc, warnings being errors, and returns what it
+    /// prints, or None if rustc is not installed.
+    fn run(name: &str, source: &str) -> Option<String> {
+        if Command::new("rustc").arg("--version").output().is_err() {
+            eprintln!("skipping, rustc is not installed");
+            return None;
+        }
+        let dir = std::env::temp_dir().join(format!("asdf-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("result.rs"), rust(source)).unwrap();
+        let build = Command::new("rustc")
+            .args(["--edition", "2021", "-D", "warnings", "-o"])
+            .arg(dir.join("result"))
+            .arg(dir.join("result.rs"))
+            .output()
+            .unwrap();
+        let run = Command::new(dir.join("result")).output();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            build.status.success(),
+            "{}",
+            String::from_utf8_lossy(&build.stderr)
+        );
+        Some(String::from_utf8(run.unwrap().stdout).unwrap())
+    }
+
+    #[test]
+    fn fib() {
+        let source = "
+fn fib(n) {
+    if (n < 2) {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+print(fib(10))
+";
+        assert_eq!(
+            rust(source),
+            "\
+fn fib(n: f64) -> f64 {
+    if n < 2.0 {
+        return n;
+    }
+    return fib(n - 1.0) + fib(n - 2.0);
+}
+
+fn main() {
+    println!(\"{}\", fib(10.0));
+}
+"
+        );
+    }
+
+    #[test]
+    fn integers_only_where_they_cannot_overflow() {
+        let source = "
+fn fact(n) {
+    if (n < 2) {
+        return 1
+    }
+    return n * fact(n - 1)
+}
+print(fact(25))
+let a = 6
+let b = a * 7
+print(b - 50)
+";
+        let output = rust(source);
+        assert!(output.contains("fn fact(n: f64) -> f64 {"), "{}", output);
+        assert!(output.contains("let b: i64 = a * 7;"), "{}", output);
+        if let Some(printed) = run("rust-overflow", source) {
+            assert_eq!(printed, "15511210043330986000000000\n-8\n");
+        }
+    }
+
+    #[test]
+    fn mixed_types_fall_back_to_values() {
+        let output = rust("let x = 1\nlet x = \"one\"\nprint(x)\nprint(x == 1)");
+        assert!(output.contains("enum Value {"));
+        assert!(output.contains("let mut x: Value = Value::None;"));
+        assert!(output.contains("x = Value::Number(1.0);"));
+        assert!(output.contains("if x.clone() == Value::Number(1.0) {"));
+    }
+
+    #[test]
+    fn prelude_names() {
+        let output = rust("fn Ok(Err) {\n    return Err\n}\nlet None = Ok(1)\nprint(None)");
+        assert!(output.contains("fn Ok_(Err_: i64) -> i64 {"));
+        assert!(output.contains("let None_: i64 = Ok_(1);"));
+    }
+
+    #[test]
+    fn builds_without_warnings() {
+        let source = r#"
+fn half(x) {
+    return x / 2
+}
+fn isEven(n) {
+    if (n == 0) {
+        return 1
+    }
+    return isOdd(n - 1)
+}
+fn isOdd(n) {
+    if (n == 0) {
+        return 0
+    }
+    return isEven(n - 1)
+}
+fn unused(a, b) {
+    return a
+}
+fn early(n) {
+    if (n > 1) {
+        return "big"
+    } else {
+        return "small"
+    }
+    print("never")
+}
+fn greet(name) {
+    print("hi " + name)
+}
+fn maybe(n) {
+    if (n) {
+        return n
+    }
+}
+let total = 1
+fn add(n) {
+    return total + n
+}
+let s = "b"
+print(s < "c")
+print(half(7))
+print(isEven(10))
+print(early(3))
+greet("bob")
+print(maybe(0))
+print(add(5))
+let k = 3
+while (k) {
+    let k = k - 1
+}
+print(k + 1 / 2)
+let x = 1
+let x = "a string"
+print(x)
+fn Ok(Err) {
+    return Err + 1
+}
+let None = Ok(1)
+print(None)
+"#;
+        if let Some(printed) = run("rust", source) {
+            assert_eq!(
+                printed,
+                "True\n3.5\n1\nbig\nhi bob\nNone\n6\n0.5\na string\n2\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use super::{
+    arities, collect_functions, has_return, identifier, signature, Backend, CodeWriter, Options,
+    Output,
+};
+use crate::lexer::{Operator, TokenType};
+use crate::optimize::remove_dead_stores;
+use crate::parser::ParseNode;
+use crate::utils::collect_bindings;
+use std::collections::HashMap;
+
+/// Functions of the generated module that are not asdf functions.
+const RESERVED: [&str; 1] = ["asdf_main"];
+
+/// Emits a WebAssembly text module for the numeric subset of the language.
+/// Every value is an `f64`: comparisons produce 1 or 0, functions that fall
+/// off the end return 0 and dividing by zero follows IEEE 754 instead of
+/// failing. Functions are exported under their asdf names, even when their
+/// `$` identifiers have to be spelled differently, `print` calls the
+/// host function imported as `env.print`, and the top level statements run
+/// as the start function when the module is instantiated.
+pub struct Wat {
+    indent_width: usize,
+    optimize: bool,
+}
+
+impl Wat {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            indent_width: options.indent_width,
+            optimize: options.optimize,
+        }
+    }
+}
+
+impl Backend for Wat {
+    fn generate(&self, nodes: &[ParseNode]) -> Result<Vec<Output>, String> {
+        self.generate_renamed(nodes, &HashMap::new())
+    }
+
+    fn generate_renamed(
+        &self,
+        nodes: &[ParseNode],
+        renames: &HashMap<String, String>,
+    ) -> Result<Vec<Output>, String> {
+        let nodes = &match self.optimize {
+            true => remove_dead_stores(nodes),
+            false => nodes.to_vec(),
+        };
+        let mut functions = vec![];
+        collect_functions(nodes, &mut functions);
+        let arities = arities("WebAssembly", &functions)?;
+        if has_return(nodes) {
+            return Err("'return' outside function".to_string());
+        }
+        let mut bindings = vec![];
+        collect_bindings(nodes, &mut bindings);
+        let mut globals: Vec<String> = vec![];
+        for binding in bindings {
+            if !globals.contains(&binding) && !arities.contains_key(binding.as_str()) {
+                globals.push(binding);
+            }
+        }
+
+        let generator = Generator {
+            arities: &arities,
+            globals: &globals,
+            // Exports are strings, so they keep the asdf names.
+            exports: renames
+                .iter()
+                .map(|(name, renamed)| (renamed.as_str(), name.as_str()))
+                .collect(),
+        };
+        let mut w = CodeWriter::new(self.indent_width);
+        w.line("(module");
+        w.indent();
+        w.line("(import \"env\" \"print\" (func $print (param f64)))");
+        for global in globals.iter() {
+            w.line(&format!("(global ${} (mut f64) (f64.const 0))", global));
+        }
+        for function in functions.iter() {
+            generator.function(function, &mut w)?;
+        }
+        w.line("(func $asdf_main");
+        generator.body(nodes, &[], &mut w)?;
+        w.line(")");
+        w.line("(start $asdf_main)");
+        w.dedent();
+        w.line(")");
+        Ok(vec![w.finish("result.wat")])
+    }
+
+    fn reserved(&self) -> &'static [&'static str] {
+        &RESERVED
+    }
+
+    fn identifier_char(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c)
+    }
+}
+
+/// Formats a number as a WebAssembly float literal.
+fn number(n: f64) -> String {
+    if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{:?}", n)
+    }
+}
+
+struct Generator<'a> {
+    arities: &'a HashMap<&'a str, usize>,
+    globals: &'a [String],
+    /// The asdf name of each renamed function.
+    exports: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Generator<'a> {
+    fn function(&self, node: &ParseNode, w: &mut CodeWriter) -> Result<(), String> {
+        let (name, params) = signature(node)?;
+        let mut locals: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+        let export = self.exports.get(name).unwrap_or(&name);
+        let mut header = format!("(func ${} (export \"{}\")", name, export);
+        for param in params.iter() {
+            header.push_str(&format!(" (param ${} f64)", param));
+        }
+        header.push_str(" (result f64)");
+        w.mark(node.span);
+        w.line(&header);
+        w.indent();
+        let mut bindings = vec![];
+        collect_bindings(&node.children, &mut bindings);
+        for binding in bindings {
+            if !locals.contains(&binding) && !self.arities.contains_key(binding.as_str()) {
+                w.line(&format!("(local ${} f64)", binding));
+                locals.push(binding);
+            }
+        }
+        self.block(&node.children, &locals, w)?;
+        w.line("(f64.const 0)");
+        w.dedent();
+        w.line(")");
+        Ok(())
+    }
+
+    fn block(
+        &self,
+        nodes: &[ParseNode],
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        for node in nodes.iter() {
+            self.statement(node, locals, w)?;
+        }
+        Ok(())
+    }
+
+    /// Emits `nodes` one level deeper than the current line.
+    fn body(
+        &self,
+        nodes: &[ParseNode],
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        w.indent();
+        self.block(nodes, locals, w)?;
+        w.dedent();
+        Ok(())
+    }
+
+    fn statement(
+        &self,
+        node: &ParseNode,
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        // Hoisted to the top level.
+        if let TokenType::Fn(_) = node.token {
+            return Ok(());
+        }
+        w.mark(node.span);
+        match &node.token {
+            TokenType::Let => {
+                let id = identifier(&node.children.first().ok_or("Invalid state")?.token)?;
+                let (scope, id) = self.variable(id, locals)?;
+                let value =
+                    self.expression(node.children.get(1).ok_or("Invalid state")?, locals)?;
+                w.line(&format!("({}.set ${} {})", scope, id, value));
+            }
+            TokenType::Print => {
+                let value =
+                    self.expression(node.children.first().ok_or("Invalid state")?, locals)?;
+                w.line(&format!("(call $print {})", value));
+            }
+            TokenType::While => {
+                let cond =
+                    self.condition(node.extra_info.as_ref().ok_or("Invalid state")?, locals)?;
+                w.line("(block");
+                w.indent();
+                w.line("(loop");
+                w.indent();
+                w.line(&format!("(br_if 1 (i32.eqz {}))", cond));
+                self.block(&node.children, locals, w)?;
+                w.line("(br 0)))");
+                w.dedent();
+                w.dedent();
+            }
+            TokenType::If => {
+                let split = node
+                    .children
+                    .iter()
+                    .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+                    .unwrap_or(node.children.len());
+                let (body, branches) = node.children.split_at(split);
+                let cond = node.extra_info.as_ref().ok_or("Invalid state")?;
+                self.branches(cond, body, branches, locals, w)?;
+            }
+            TokenType::Call(_) => w.line(&format!("(drop {})", self.expression(node, locals)?)),
+            TokenType::Return => match node.children.first() {
+                Some(value) => w.line(&format!("(return {})", self.expression(value, locals)?)),
+                None => w.line("(return (f64.const 0))"),
+            },
+            t => return Err(format!("{:?} is not a statement", t)),
+        }
+        Ok(())
+    }
+
+    /// WebAssembly has no `else if`, so every `elif` nests another `if`
+    /// inside the `else` of the previous one.
+    fn branches(
+        &self,
+        cond: &ParseNode,
+        body: &[ParseNode],
+        branches: &[ParseNode],
+        locals: &[String],
+        w: &mut CodeWriter,
+    ) -> Result<(), String> {
+        w.line(&format!("(if {}", self.condition(cond, locals)?));
+        w.indent();
+        w.line("(then");
+        self.body(body, locals, w)?;
+        w.line(")");
+        if let Some((branch, rest)) = branches.split_first() {
+            w.line("(else");
+            w.indent();
+            match &branch.extra_info {
+                Some(cond) => self.branches(cond, &branch.children, rest, locals, w)?,
+                None => self.block(&branch.children, locals, w)?,
+            }
+            w.dedent();
+            w.line(")");
+        }
+        w.dedent();
+        w.line(")");
+        Ok(())
+    }
+
+    /// Returns whether `name` is a local or a global, and its name.
+    fn variable<'b>(
+        &self,
+        name: &'b str,
+        locals: &[String],
+    ) -> Result<(&'static str, &'b str), String> {
+        if locals.iter().any(|l| l == name) {
+            Ok(("local", name))
+        } else if self.globals.iter().any(|g| g == name) {
+            Ok(("global", name))
+        } else if self.arities.contains_key(name) {
+            Err(format!(
+                "WebAssembly backend can only call functions by name, {} is used as a value",
+                name
+            ))
+        } else {
+            Err(format!(
+                "WebAssembly backend: {} is not declared in this function or at the top level",
+                name
+            ))
+        }
+    }
+
+    /// An `i32` that is non-zero when `node` is truthy. Comparisons are used
+    /// as they are instead of converting their result to `f64` and back.
+    fn condition(&self, node: &ParseNode, locals: &[String]) -> Result<String, String> {
+        match (&node.token, node.children.as_slice()) {
+            (TokenType::Operator(op), [lhs, rhs]) if Generator::comparison(op).is_some() => {
+                self.compare(op, lhs, rhs, locals)
+            }
+            _ => Ok(format!(
+                "(f64.ne {} (f64.const 0))",
+                self.expression(node, locals)?
+            )),
+        }
+    }
+
+    fn comparison(op: &Operator) -> Option<&'static str> {
+        match op {
+            Operator::Equality => Some("f64.eq"),
+            Operator::LessThan => Some("f64.lt"),
+            Operator::LessThanEqual => Some("f64.le"),
+            Operator::GreaterThan => Some("f64.gt"),
+            Operator::GreaterThanEqual => Some("f64.ge"),
+            _ => None,
+        }
+    }
+
+    fn compare(
+        &self,
+        op: &Operator,
+        lhs: &ParseNode,
+        rhs: &ParseNode,
+        locals: &[String],
+    ) -> Result<String, String> {
+        Ok(format!(
+            "({} {} {})",
+            Generator::comparison(op).ok_or("Invalid state")?,
+            self.expression(lhs, locals)?,
+            self.expression(rhs, locals)?
+        ))
+    }
+
+    fn expression(&self, node: &ParseNode, locals: &[String]) -> Result<String, String> {
+        match &node.token {
+            TokenType::Identifier(x) => {
+                let (scope, id) = self.variable(x, locals)?;
+                Ok(format!("({}.get ${})", scope, id))
+            }
+            TokenType::Number(x) => Ok(format!("(f64.const {})", number(x.into_inner() as f64))),
+            TokenType::StringLiteral(_) => {
+                Err("WebAssembly backend only supports numbers, found a string".to_string())
+            }
+            TokenType::Operator(op) => match node.children.as_slice() {
+                [operand] => match op {
+                    Operator::Minus => {
+                        Ok(format!("(f64.neg {})", self.expression(operand, locals)?))
+                    }
+                    Operator::Plus => self.expression(operand, locals),
+                    _ => Err(format!("Invalid prefix operator {}", op)),
+                },
+                [lhs, rhs] => {
+                    if Generator::comparison(op).is_some() {
+                        return Ok(format!(
+                            "(f64.convert_i32_u {})",
+                            self.compare(op, lhs, rhs, locals)?
+                        ));
+                    }
+                    let instr = match op {
+                        Operator::Plus => "f64.add",
+                        Operator::Minus => "f64.sub",
+                        Operator::Multiply => "f64.mul",
+                        Operator::Divide => "f64.div",
+                        _ => return Err(format!("Invalid operator {} in expression", op)),
+                    };
+                    Ok(format!(
+                        "({} {} {})",
+                        instr,
+                        self.expression(lhs, locals)?,
+                        self.expression(rhs, locals)?
+                    ))
+                }
+                _ => Err("Invalid state".to_string()),
+            },
+            TokenType::Call(info) => {
+                let name = identifier(&info.name)?;
+                let arity = match self.arities.get(name) {
+                    Some(arity) => *arity,
+                    None => return Err(format!("WebAssembly backend: {} is not a function", name)),
+                };
+                if arity != node.children.len() {
+                    return Err(format!(
+                        "{}() takes {} arguments but {} were given",
+                        name,
+                        arity,
+                        node.children.len()
+                    ));
+                }
+                let mut res = format!("(call ${}", name);
+                for arg in node.children.iter() {
+                    res.push(' ');
+                    res.push_str(&self.expression(arg, locals)?);
+                }
+                res.push(')');
+                Ok(res)
+            }
+            t => Err(format!("{:?} is not an expression", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile::transpile_source;
+    use std::fs;
+    use std::process::Command;
+
+    const PROGRAM: &str = "
+let café = 2
+fn π(r) {
+    return r * 3
+}
+fn countdown(n) {
+    while (n > 0) {
+        print(n)
+        let n = n - 1
+    }
+}
+print(π(café))
+if (café == 2) {
+    countdown(3)
+} elif (café < 2) {
+    print(1)
+} else {
+    print(0 - 1)
+}
+let caf_ue9 = 1 / 2
+print(caf_ue9)
+";
+
+    fn wat(source: &str) -> String {
+        transpile_source("wat", source).remove(0).contents
+    }
+
+    #[test]
+    fn identifiers_are_idchars() {
+        let output = wat(PROGRAM);
+        assert!(output.contains("(global $caf_ue9_ (mut f64) (f64.const 0))"));
+        assert!(output.contains("(func $_u3c0 (export \"π\") (param $r f64) (result f64)"));
+        assert!(output.contains("(func $countdown (export \"countdown\") (param $n f64)"));
+        for id in output.split('$').skip(1) {
+            let id: String = id.chars().take_while(|c| !" ()\"".contains(*c)).collect();
+            assert!(id.is_ascii(), "{} is not a valid identifier", id);
+        }
+    }
+
+    #[test]
+    fn validates() {
+        let dir = std::env::temp_dir().join(format!("asdf-wat-{}", std::process::id()));
+        let path = dir.join("result.wat");
+        let out = dir.join("result.wasm");
+        let validators: [(&str, Vec<&std::ffi::OsStr>); 2] = [
+            ("wasm-tools", vec!["validate".as_ref(), path.as_os_str()]),
+            (
+                "wat2wasm",
+                vec![path.as_os_str(), "-o".as_ref(), out.as_os_str()],
+            ),
+        ];
+        let installed: Vec<_> = validators
+            .iter()
+            .filter(|(tool, _)| Command::new(tool).arg("--version").output().is_ok())
+            .collect();
+        if installed.is_empty() {
+            eprintln!("skipping, neither wasm-tools nor wat2wasm is installed");
+            return;
+        }
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, wat(PROGRAM)).unwrap();
+        for (tool, args) in installed {
+            let output = Command::new(tool).args(args).output().unwrap();
+            assert!(
+                output.status.success(),
+                "{} rejected the module: {}",
+                tool,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
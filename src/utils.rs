@@ -1,32 +1,53 @@
+use crate::lexer::TokenType;
 use crate::parser::ParseNode;
 
-pub fn get_sexp(parse_node: &ParseNode, level: usize) -> String {
-    if parse_node.children.is_empty() {
-        format!(
-            "{:?}{}",
-            parse_node.token,
-            if let Some(p) = &parse_node.extra_info {
-                format!("\n\t({})", get_sexp(p, level + 1))
-            } else {
-                " ".to_string()
+/// Names bound by `let` or `fn` directly inside `nodes`, not descending into
+/// nested functions. Like Python these are local to the whole function.
+pub fn collect_bindings(nodes: &[ParseNode], out: &mut Vec<String>) {
+    for node in nodes.iter() {
+        match &node.token {
+            TokenType::Let => {
+                if let Some(ParseNode {
+                    token: TokenType::Identifier(id),
+                    ..
+                }) = node.children.first()
+                {
+                    out.push(id.clone());
+                }
             }
-        )
-    } else {
-        format!(
-            "({:?}{}\n{}{})",
-            parse_node.token,
-            if let Some(p) = &parse_node.extra_info {
-                format!("\n\t({})", get_sexp(p, level + 1))
-            } else {
-                " ".to_string()
-            },
-            "\t".repeat(level),
-            parse_node
-                .children
-                .iter()
-                .map(|x| get_sexp(x, level + 1))
-                .collect::<Vec<String>>()
-                .join(&format!("\n{}", "\t".repeat(level)))
-        )
+            TokenType::Fn(Some(info)) => {
+                if let TokenType::Identifier(id) = &*info.name {
+                    out.push(id.clone());
+                }
+            }
+            TokenType::While | TokenType::If | TokenType::Elif | TokenType::Else => {
+                collect_bindings(&node.children, out)
+            }
+            _ => {}
+        }
     }
 }
+
+/// The body of an `if` and its `elif` and `else` branches.
+pub fn split_if(node: &ParseNode) -> (&[ParseNode], &[ParseNode]) {
+    let split = node
+        .children
+        .iter()
+        .position(|c| matches!(c.token, TokenType::Elif | TokenType::Else))
+        .unwrap_or(node.children.len());
+    node.children.split_at(split)
+}
+
+/// Whether every path through `nodes` ends in a `return`.
+pub fn always_returns(nodes: &[ParseNode]) -> bool {
+    nodes.iter().any(|node| match node.token {
+        TokenType::Return => true,
+        TokenType::If => {
+            let (body, branches) = split_if(node);
+            always_returns(body)
+                && branches.last().is_some_and(|b| b.token == TokenType::Else)
+                && branches.iter().all(|b| always_returns(&b.children))
+        }
+        _ => false,
+    })
+}
@@ -0,0 +1,137 @@
+use crate::lexer::Operator;
+use std::fmt;
+use std::rc::Rc;
+
+/*
+The values of a running asdf program, shared by the tree-walking interpreter
+and the bytecode VM. The two only differ in what a function is (a body and
+its environment, or a compiled function and its cells), so `Value` is
+generic over that, and the operators live here so that both engines compute
+the same results and fail with the same messages.
+*/
+
+/// Same limit CPython uses, so deep recursion fails the same way it would in
+/// the transpiled program instead of overflowing the native stack.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+/// What a function value has to offer to be printed.
+pub trait Callable {
+    fn name(&self) -> &str;
+}
+
+#[derive(Debug)]
+pub enum Value<F> {
+    Number(f64),
+    Str(Rc<str>),
+    Bool(bool),
+    Function(Rc<F>),
+    None,
+}
+
+// Derived `Clone` would require `F: Clone`.
+impl<F> Clone for Value<F> {
+    fn clone(&self) -> Self {
+        match self {
+            Value::Number(n) => Value::Number(*n),
+            Value::Str(s) => Value::Str(Rc::clone(s)),
+            Value::Bool(b) => Value::Bool(*b),
+            Value::Function(f) => Value::Function(Rc::clone(f)),
+            Value::None => Value::None,
+        }
+    }
+}
+
+impl<F> Value<F> {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Number(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::Function(_) => true,
+            Value::None => false,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Function(_) => "function",
+            Value::None => "None",
+        }
+    }
+
+    /// `op value`, or the message of the runtime error it raises.
+    pub fn unary(op: &Operator, value: Self) -> Result<Self, String> {
+        match (op, value) {
+            (Operator::Plus, Value::Number(n)) => Ok(Value::Number(n)),
+            (Operator::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+            (op, v) => Err(format!(
+                "bad operand type for unary {}: '{}'",
+                op,
+                v.type_name()
+            )),
+        }
+    }
+
+    /// `lhs op rhs`, or the message of the runtime error it raises.
+    pub fn binary(op: &Operator, lhs: Self, rhs: Self) -> Result<Self, String> {
+        match (op, lhs, rhs) {
+            (Operator::Equality, l, r) => Ok(Value::Bool(l == r)),
+            (Operator::Plus, Value::Str(l), Value::Str(r)) => {
+                Ok(Value::Str(Rc::from(format!("{}{}", l, r))))
+            }
+            (Operator::LessThan, Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l < r)),
+            (Operator::LessThanEqual, Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l <= r)),
+            (Operator::GreaterThan, Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l > r)),
+            (Operator::GreaterThanEqual, Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l >= r)),
+            (Operator::Divide, Value::Number(_), Value::Number(0.0)) => {
+                Err("division by zero".to_string())
+            }
+            (op, Value::Number(l), Value::Number(r)) => Ok(match op {
+                Operator::Plus => Value::Number(l + r),
+                Operator::Minus => Value::Number(l - r),
+                Operator::Multiply => Value::Number(l * r),
+                Operator::Divide => Value::Number(l / r),
+                Operator::LessThan => Value::Bool(l < r),
+                Operator::LessThanEqual => Value::Bool(l <= r),
+                Operator::GreaterThan => Value::Bool(l > r),
+                Operator::GreaterThanEqual => Value::Bool(l >= r),
+                _ => return Err(format!("invalid operator {}", op)),
+            }),
+            (op, l, r) => Err(format!(
+                "unsupported operand types for {}: '{}' and '{}'",
+                op,
+                l.type_name(),
+                r.type_name()
+            )),
+        }
+    }
+}
+
+impl<F> PartialEq for Value<F> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::None, Value::None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<F: Callable> fmt::Display for Value<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(true) => write!(f, "True"),
+            Value::Bool(false) => write!(f, "False"),
+            Value::Function(func) => write!(f, "<function {}>", func.name()),
+            Value::None => write!(f, "None"),
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use crate::bytecode::{Constant, OpCode, Program};
+use crate::lexer::Operator;
+use crate::value::{self, Callable, MAX_CALL_DEPTH};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+pub type Value = value::Value<Closure>;
+
+/// A variable captured by a closure. None until it is assigned.
+type Cell = Rc<RefCell<Option<Value>>>;
+
+#[derive(Debug)]
+pub struct Closure {
+    function: usize,
+    name: Rc<str>,
+    /// Cells of the function's free names, in order.
+    cells: Vec<Cell>,
+}
+
+impl Callable for Closure {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The operator an arithmetic or comparison instruction performs.
+fn operator(op: OpCode) -> Option<Operator> {
+    Some(match op {
+        OpCode::Add | OpCode::Positive => Operator::Plus,
+        OpCode::Subtract | OpCode::Negate => Operator::Minus,
+        OpCode::Multiply => Operator::Multiply,
+        OpCode::Divide => Operator::Divide,
+        OpCode::Equal => Operator::Equality,
+        OpCode::Less => Operator::LessThan,
+        OpCode::LessEqual => Operator::LessThanEqual,
+        OpCode::Greater => Operator::GreaterThan,
+        OpCode::GreaterEqual => Operator::GreaterThanEqual,
+        _ => return None,
+    })
+}
+
+struct Frame {
+    function: usize,
+    ip: usize,
+    /// Stack index of the callee, where the return value goes.
+    base: usize,
+    /// None for locals that are not assigned yet.
+    locals: Vec<Option<Value>>,
+    /// Captured locals followed by the closure's cells.
+    cells: Vec<Cell>,
+}
+
+pub struct Vm<'a> {
+    program: &'a Program,
+    constants: Vec<Value>,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// Function names, shared by all closures of a function.
+    names: Vec<Rc<str>>,
+    /// Lines printed by the program, when they are collected instead of
+    /// written to stdout.
+    output: Option<Vec<String>>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            constants: program
+                .constants
+                .iter()
+                .map(|c| match c {
+                    Constant::Number(n) => Value::Number(*n),
+                    Constant::Str(s) => Value::Str(Rc::from(s.as_str())),
+                })
+                .collect(),
+            globals: vec![None; program.globals.len()],
+            stack: vec![],
+            frames: vec![],
+            names: program
+                .functions
+                .iter()
+                .map(|f| Rc::from(f.name.as_str()))
+                .collect(),
+            output: None,
+        }
+    }
+
+    /// A VM that keeps what the program prints, see `output`.
+    #[cfg(test)]
+    pub fn collecting(program: &'a Program) -> Self {
+        Self {
+            output: Some(vec![]),
+            ..Self::new(program)
+        }
+    }
+
+    #[cfg(test)]
+    pub fn output(&self) -> &[String] {
+        self.output.as_deref().unwrap_or_default()
+    }
+
+    fn error<T>(&self, message: impl fmt::Display) -> Result<T, String> {
+        let span = match self.frames.last() {
+            Some(frame) => {
+                let chunk = &self.program.functions[frame.function].chunk;
+                // `ip` already points past the failing instruction.
+                chunk.span_at(frame.ip.saturating_sub(1))
+            }
+            None => Default::default(),
+        };
+        if span.line == 0 {
+            // Compiled without a debug line table.
+            return Err(format!("Runtime error: {}", message));
+        }
+        Err(format!("Runtime error at {}: {}", span, message))
+    }
+
+    /// A frame for calling `closure` with `args`, the callee at `base`.
+    fn frame(&self, closure: &Closure, base: usize, args: Vec<Value>) -> Frame {
+        let proto = &self.program.functions[closure.function];
+        let mut locals: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        locals.resize(proto.locals.len(), None);
+        let mut cells: Vec<Cell> = proto
+            .cells
+            .iter()
+            .map(|slot| Rc::new(RefCell::new(locals[*slot].take())))
+            .collect();
+        cells.extend(closure.cells.iter().cloned());
+        Frame {
+            function: closure.function,
+            ip: 0,
+            base,
+            locals,
+            cells,
+        }
+    }
+
+    fn unbound<T>(&self, name: &str) -> Result<T, String> {
+        self.error(format!(
+            "local variable '{}' referenced before assignment",
+            name
+        ))
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    pub fn run(&mut self) -> Result<(), String> {
+        let main = Rc::new(Closure {
+            function: 0,
+            name: Rc::clone(&self.names[0]),
+            cells: vec![],
+        });
+        let frame = self.frame(&main, 0, vec![]);
+        self.stack.push(Value::Function(main));
+        self.frames.push(frame);
+        loop {
+            let frame = self.frames.last_mut().ok_or("Invalid state")?;
+            let function = frame.function;
+            let code = &self.program.functions[function].chunk.code;
+            let byte = code[frame.ip];
+            let op = OpCode::from_byte(byte).ok_or_else(|| format!("Invalid opcode {}", byte))?;
+            let arg = if op.operand_len() == 2 {
+                u16::from_le_bytes([code[frame.ip + 1], code[frame.ip + 2]]) as usize
+            } else {
+                0
+            };
+            frame.ip += 1 + op.operand_len();
+            let proto = &self.program.functions[function];
+            match op {
+                OpCode::Constant => self.stack.push(self.constants[arg].clone()),
+                OpCode::None => self.stack.push(Value::None),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => match frame.locals[arg].clone() {
+                    Some(v) => self.stack.push(v),
+                    None => return self.unbound(&proto.locals[arg]),
+                },
+                OpCode::SetLocal => {
+                    let value = self.pop();
+                    self.frames.last_mut().ok_or("Invalid state")?.locals[arg] = Some(value);
+                }
+                OpCode::GetCell => {
+                    let value = frame.cells[arg].borrow().clone();
+                    match value {
+                        Some(v) => self.stack.push(v),
+                        None => return self.unbound(proto.cell_name(arg).unwrap_or_default()),
+                    }
+                }
+                OpCode::SetCell => {
+                    let value = self.pop();
+                    let frame = self.frames.last().ok_or("Invalid state")?;
+                    *frame.cells[arg].borrow_mut() = Some(value);
+                }
+                OpCode::GetGlobal => match &self.globals[arg] {
+                    Some(v) => self.stack.push(v.clone()),
+                    None => {
+                        return self.error(format!(
+                            "name '{}' is not defined",
+                            self.program.globals[arg]
+                        ))
+                    }
+                },
+                OpCode::SetGlobal => {
+                    let value = self.pop();
+                    self.globals[arg] = Some(value);
+                }
+                OpCode::Function => {
+                    let cells = self.program.functions[arg]
+                        .free
+                        .iter()
+                        .map(|(_, cell)| Rc::clone(&frame.cells[*cell]))
+                        .collect();
+                    let closure = Closure {
+                        function: arg,
+                        name: Rc::clone(&self.names[arg]),
+                        cells,
+                    };
+                    self.stack.push(Value::Function(Rc::new(closure)));
+                }
+                OpCode::Negate | OpCode::Positive => {
+                    let value = self.pop();
+                    let op = operator(op).ok_or("Invalid state")?;
+                    match Value::unary(&op, value) {
+                        Ok(value) => self.stack.push(value),
+                        Err(message) => return self.error(message),
+                    }
+                }
+                OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Equal
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Greater
+                | OpCode::GreaterEqual => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let op = operator(op).ok_or("Invalid state")?;
+                    match Value::binary(&op, lhs, rhs) {
+                        Ok(value) => self.stack.push(value),
+                        Err(message) => return self.error(message),
+                    }
+                }
+                OpCode::Jump => self.frames.last_mut().ok_or("Invalid state")?.ip = arg,
+                OpCode::JumpIfFalse => {
+                    if !self.pop().is_truthy() {
+                        self.frames.last_mut().ok_or("Invalid state")?.ip = arg;
+                    }
+                }
+                OpCode::Call => {
+                    let callee = self.stack.len() - arg - 1;
+                    let closure = match &self.stack[callee] {
+                        Value::Function(c) => Rc::clone(c),
+                        v => return self.error(format!("'{}' is not callable", v.type_name())),
+                    };
+                    let proto = &self.program.functions[closure.function];
+                    if proto.arity != arg {
+                        return self.error(format!(
+                            "{}() takes {} arguments but {} were given",
+                            proto.name, proto.arity, arg
+                        ));
+                    }
+                    // The main frame is not a call.
+                    if self.frames.len() > MAX_CALL_DEPTH {
+                        return self.error("maximum recursion depth exceeded");
+                    }
+                    let args = self.stack.split_off(callee + 1);
+                    let frame = self.frame(&closure, callee, args);
+                    self.frames.push(frame);
+                }
+                OpCode::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().ok_or("Invalid state")?;
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    match &mut self.output {
+                        Some(output) => output.push(value.to_string()),
+                        None => println!("{}", value),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Compiler;
+    use crate::interpret::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// What `source` prints on the VM, and how it ends.
+    fn run(source: &str) -> (Vec<String>, Result<(), String>) {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let program = Compiler::new().compile(&nodes).unwrap();
+        let mut vm = Vm::collecting(&program);
+        let result = vm.run();
+        (vm.output().to_vec(), result)
+    }
+
+    /// Runs `source` on both engines, which must agree, and returns what it
+    /// printed.
+    fn same(source: &str) -> (Vec<String>, Result<(), String>) {
+        let nodes = Parser::new(Lexer::new(source).lex()).parse().unwrap();
+        let mut interpreter = Interpreter::collecting();
+        let expected = interpreter.run(&nodes);
+        let actual = run(source);
+        assert_eq!(
+            actual,
+            (interpreter.output().to_vec(), expected),
+            "{}",
+            source
+        );
+        actual
+    }
+
+    #[test]
+    fn closures() {
+        let source = "fn adder(n) { fn add(m) { return n + m } return add }
+            let add2 = adder(2) let add3 = adder(3)
+            print(add2(1)) print(add3(1)) print(add2) print(add2 == add2) print(add2 == add3)";
+        assert_eq!(
+            same(source).0,
+            ["3", "4", "<function add>", "True", "False"]
+        );
+    }
+
+    #[test]
+    fn cells() {
+        // `later` is captured before it is assigned, and `x` is reassigned
+        // after the closure is made.
+        let source = "fn outer() {
+                let x = 1
+                fn get() { return x + later }
+                let later = 10
+                let x = 2
+                return get
+            }
+            let get = outer()
+            print(get())
+            let g = 5
+            fn read() { return g }
+            let g = 6
+            print(read())";
+        assert_eq!(same(source).0, ["12", "6"]);
+        let (output, result) = same("fn f() { fn g() { return y } print(g()) let y = 1 } f()");
+        assert!(output.is_empty());
+        assert!(result
+            .unwrap_err()
+            .contains("'y' referenced before assignment"));
+    }
+
+    #[test]
+    fn operators() {
+        let source = "print(0 - 3 * 3 / 2) print(\"a\" < \"b\") print(\"a\" + \"b\" == \"ab\")
+            print(1 == \"1\") print(0 - 0) print(2 >= 2)";
+        assert_eq!(
+            same(source).0,
+            ["-4.5", "True", "True", "False", "0", "True"]
+        );
+        for source in [
+            "print(1 / 0)",
+            "print(\"a\" * 2)",
+            "print(-\"a\")",
+            "print(1 < \"a\")",
+        ] {
+            assert!(same(source).1.is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let down = |n: usize| {
+            format!(
+                "fn down(n) {{ if (n == 0) {{ return 0 }} return down(n - 1) }} print(down({}))",
+                n
+            )
+        };
+        // The interpreter needs a few Rust frames per level of recursion.
+        let (deepest, too_deep) = std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || (same(&down(MAX_CALL_DEPTH - 1)), same(&down(MAX_CALL_DEPTH))))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deepest, (vec!["0".to_string()], Ok(())));
+        assert_eq!(
+            too_deep.1,
+            Err("Runtime error at 1:46: maximum recursion depth exceeded".into())
+        );
+    }
+}
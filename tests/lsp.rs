@@ -0,0 +1,106 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/*
+Runs `parser lsp` on a whole session, from initialize to exit, and checks
+every reply. The server is a separate process here, so messages are framed
+and written exactly as an editor would send them.
+*/
+
+const URI: &str = "file:///tmp/session.asdf";
+
+/// A message with its Content-Length header.
+fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+/// The bodies of the framed messages in `output`.
+fn bodies(mut output: &str) -> Vec<&str> {
+    let mut res = vec![];
+    while let Some((header, rest)) = output.split_once("\r\n\r\n") {
+        let length: usize = header
+            .strip_prefix("Content-Length: ")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_else(|| panic!("bad header {:?}", header));
+        res.push(&rest[..length]);
+        output = &rest[length..];
+    }
+    assert!(output.is_empty(), "trailing output {:?}", output);
+    res
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    frame(&format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    ))
+}
+
+fn position(line: usize, character: usize) -> String {
+    format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    )
+}
+
+#[test]
+fn session() {
+    // `total` is read inside the function, which must not be reported as
+    // used before its declaration.
+    let text = r#"let total = 0\nfn add(n) {\n    return total + n\n}\nprint(add(2))\n"#;
+    let input = [
+        request(1, "initialize", "{}"),
+        frame(&format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"asdf","version":1,"text":"{}"}}}}}}"#,
+            URI, text
+        )),
+        request(2, "textDocument/definition", &position(2, 12)),
+        request(3, "textDocument/hover", &position(4, 7)),
+        request(4, "shutdown", "null"),
+        frame(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+    ]
+    .concat();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_parser"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("couldn't run parser");
+    child
+        .stdin
+        .take()
+        .expect("no stdin")
+        .write_all(input.as_bytes())
+        .expect("couldn't write to the server");
+    let output = child.wait_with_output().expect("server didn't finish");
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout).expect("output isn't UTF-8");
+
+    let replies = bodies(&output);
+    assert_eq!(replies.len(), 5, "{:?}", replies);
+    assert!(
+        replies[0].starts_with(r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{"#),
+        "{}",
+        replies[0]
+    );
+    assert_eq!(
+        replies[1],
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":"{}","diagnostics":[]}}}}"#,
+            URI
+        )
+    );
+    assert_eq!(
+        replies[2],
+        format!(
+            r#"{{"jsonrpc":"2.0","id":2,"result":{{"uri":"{}","range":{{"start":{{"line":0,"character":4}},"end":{{"line":0,"character":9}}}}}}}}"#,
+            URI
+        )
+    );
+    assert_eq!(
+        replies[3],
+        r#"{"jsonrpc":"2.0","id":3,"result":{"contents":{"kind":"markdown","value":"```asdf\nfn add(n: number) -> number\n```"},"range":{"start":{"line":4,"character":6},"end":{"line":4,"character":9}}}}"#
+    );
+    assert_eq!(replies[4], r#"{"jsonrpc":"2.0","id":4,"result":null}"#);
+}